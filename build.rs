@@ -7,7 +7,7 @@ use std::{
     io::Write,
     path::{self, Path, PathBuf},
     process::Command,
-    sync::LazyLock,
+    sync::{LazyLock, Mutex},
 };
 
 use glob::glob;
@@ -24,29 +24,38 @@ static SF_SOURCE_FILES: LazyLock<Vec<PathBuf>> = LazyLock::new(|| {
         "Directory Stockfish/src does not exist. Try: git submodule update --init",
     );
     assert!(
-        Path::new("Fairy-Stockfish").join("src").is_dir(),
+        official_only() || Path::new("Fairy-Stockfish").join("src").is_dir(),
         "Directory Fairy-Stockfish/src does not exist. Try: git submodule update --init",
     );
 
-    [
+    let mut patterns = vec![
         // Stockfish
-        "Stockfish/src/Makefile",
-        "Stockfish/**/*.sh",
-        "Stockfish/src/**/*.cpp",
-        "Stockfish/src/**/*.h",
-        &format!("Stockfish/src/{}", EVAL_FILE_NAME),
-        &format!("Stockfish/src/{}", EVAL_FILE_SMALL_NAME),
-        // Fairy-Stockfish
-        "Fairy-Stockfish/src/Makefile",
-        "Fairy-Stockfish/src/**/*.cpp",
-        "Fairy-Stockfish/src/**/*.h",
-    ]
-    .iter()
-    .flat_map(|pattern| glob(pattern).unwrap())
-    .collect::<Result<Vec<PathBuf>, _>>()
-    .unwrap()
+        "Stockfish/src/Makefile".to_owned(),
+        "Stockfish/**/*.sh".to_owned(),
+        "Stockfish/src/**/*.cpp".to_owned(),
+        "Stockfish/src/**/*.h".to_owned(),
+        format!("Stockfish/src/{}", EVAL_FILE_NAME),
+        format!("Stockfish/src/{}", EVAL_FILE_SMALL_NAME),
+    ];
+    if !official_only() {
+        patterns.extend([
+            // Fairy-Stockfish
+            "Fairy-Stockfish/src/Makefile".to_owned(),
+            "Fairy-Stockfish/src/**/*.cpp".to_owned(),
+            "Fairy-Stockfish/src/**/*.h".to_owned(),
+        ]);
+    }
+
+    patterns
+        .iter()
+        .flat_map(|pattern| glob(pattern).unwrap())
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .unwrap()
 });
 
+/// Architectures of the bundled engines, for --cpu-features.
+static ARCHS: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
 static SF_BUILD_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
     let mut hasher = DefaultHasher::new();
     (*SF_SOURCE_FILES).hash(&mut hasher);
@@ -58,6 +67,18 @@ fn main() {
         "cargo:rustc-env=FISHNET_TARGET={}",
         env::var("TARGET").unwrap()
     );
+    println!("cargo:rustc-env=FISHNET_EVAL_FILE={EVAL_FILE_NAME}");
+    println!("cargo:rustc-env=FISHNET_EVAL_FILE_SMALL={EVAL_FILE_SMALL_NAME}");
+
+    let variant_eval_files = variant_eval_files();
+    println!(
+        "cargo:rustc-env=FISHNET_VARIANT_EVAL_FILES={}",
+        variant_eval_files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>()
+            .join(",")
+    );
 
     // Build Stockfish and Fairy-Stockfish and archive them
     // (along with eval files).
@@ -65,6 +86,10 @@ fn main() {
         ZstdEncoder::new(File::create(OUT_PATH.join("assets.ar.zst")).unwrap(), 6).unwrap(),
     );
     stockfish_build(&mut archive);
+    println!(
+        "cargo:rustc-env=FISHNET_ARCHS={}",
+        ARCHS.lock().unwrap().join(",")
+    );
     append_file(
         &mut archive,
         SF_BUILD_PATH
@@ -81,11 +106,42 @@ fn main() {
             .join(EVAL_FILE_SMALL_NAME),
         0o644,
     );
+    for path in variant_eval_files {
+        append_file(&mut archive, path, 0o644);
+    }
     archive.into_inner().unwrap().finish().unwrap();
 
+    // Expose bench signatures for fishnet selftest, if they could be
+    // recorded on this builder.
+    for (name, var) in [
+        ("stockfish", "FISHNET_STOCKFISH_BENCH"),
+        ("fairy-stockfish", "FISHNET_FAIRY_STOCKFISH_BENCH"),
+    ] {
+        if let Ok(bench) = fs::read_to_string(SF_BUILD_PATH.join(format!("{name}.bench"))) {
+            println!("cargo:rustc-env={var}={bench}");
+        }
+    }
+
     add_favicon();
 }
 
+fn official_only() -> bool {
+    env::var_os("CARGO_FEATURE_OFFICIAL_ONLY").is_some()
+}
+
+/// Optional NNUE networks for Fairy-Stockfish, named after the variant they
+/// are trained for, like `atomic-2cf13ff256cc.nnue`.
+fn variant_eval_files() -> Vec<PathBuf> {
+    println!("cargo:rerun-if-changed=Fairy-Stockfish-nnue");
+    if official_only() {
+        return Vec::new();
+    }
+    glob("Fairy-Stockfish-nnue/*.nnue")
+        .unwrap()
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .unwrap()
+}
+
 fn has_target_feature(feature: &str) -> bool {
     env::var("CARGO_CFG_TARGET_FEATURE")
         .unwrap()
@@ -322,6 +378,12 @@ impl Target {
         name: &'static str,
         archive: &mut ar::Builder<W>,
     ) {
+        let mut archs = ARCHS.lock().unwrap();
+        if !archs.contains(&self.arch) {
+            archs.push(self.arch);
+        }
+        drop(archs);
+
         let release = env::var("PROFILE").unwrap() == "release";
         let windows = env::var("CARGO_CFG_TARGET_FAMILY").unwrap() == "windows";
         let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
@@ -476,6 +538,9 @@ impl Target {
         );
 
         let exe_path = Path::new(src_path).join(exe);
+        if self.native {
+            record_bench(src_path, &exe_path, name);
+        }
         append_file(archive, &exe_path, 0o755);
         fs::remove_file(&exe_path).unwrap();
     }
@@ -490,6 +555,9 @@ impl Target {
     }
 
     fn build_multi_variant<W: Write>(&self, archive: &mut ar::Builder<W>) {
+        if official_only() {
+            return;
+        }
         self.build(
             Flavor::MultiVariant,
             &SF_BUILD_PATH.join("Fairy-Stockfish").join("src"),
@@ -504,6 +572,36 @@ impl Target {
     }
 }
 
+/// Runs bench with the given engine and records the node count, unless
+/// already recorded from another build of the same engine. The signature
+/// does not depend on the target architecture.
+fn record_bench(src_path: &Path, exe_path: &Path, name: &str) {
+    let bench_path = SF_BUILD_PATH.join(format!("{name}.bench"));
+    if bench_path.exists() {
+        return;
+    }
+    let output = Command::new(exe_path)
+        .current_dir(src_path)
+        .arg("bench")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{name} bench");
+    let nodes = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stdout).lines())
+        .find_map(|line| {
+            line.strip_prefix("Nodes searched")?
+                .trim_start_matches([' ', ':'])
+                .trim()
+                .parse::<u64>()
+                .ok()
+        });
+    match nodes {
+        Some(nodes) => fs::write(bench_path, nodes.to_string()).unwrap(),
+        None => println!("cargo:warning=Could not record bench signature of {name}"),
+    }
+}
+
 fn append_file<W: Write, P: AsRef<Path>>(archive: &mut ar::Builder<W>, path: P, mode: u32) {
     let file = File::open(&path).unwrap();
     let metadata = file.metadata().unwrap();
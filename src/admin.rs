@@ -0,0 +1,299 @@
+//! Small HTTP API on localhost, for dashboards and scripts that need more
+//! than the read-only control socket.
+
+use std::{
+    fmt::Write as _,
+    fs, io,
+    io::Write as _,
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+use ring::rand::{SecureRandom as _, SystemRandom};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+
+use crate::{control::StatusReport, logger::Logger, util::NevermindExt as _};
+
+const MAX_HEADER_LINES: usize = 64;
+const MAX_BODY: usize = 1024;
+
+/// Authenticated request, to be handled by the main loop.
+pub struct AdminRequest {
+    pub command: AdminCommand,
+    responder: oneshot::Sender<(u16, String)>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum AdminCommand {
+    State,
+    Pause,
+    Resume,
+    Cores(usize),
+    Shutdown,
+}
+
+#[derive(Serialize)]
+pub struct AdminState {
+    pub paused: bool,
+    pub shutting_down: bool,
+    pub cores: usize,
+    pub active_cores: usize,
+    #[serde(flatten)]
+    pub status: StatusReport,
+}
+
+#[derive(Deserialize)]
+struct CoresBody {
+    cores: usize,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+impl AdminRequest {
+    pub fn respond<T: Serialize>(self, status: u16, body: &T) {
+        let body = serde_json::to_string(body).expect("serialize admin response");
+        self.responder.send((status, body)).nevermind("client gone");
+    }
+
+    pub fn reject(self, status: u16, error: &str) {
+        self.respond(status, &ErrorBody { error });
+    }
+}
+
+/// Listening end of the admin API. Never yields requests if disabled.
+pub struct AdminServer {
+    rx: Option<mpsc::Receiver<AdminRequest>>,
+}
+
+impl AdminServer {
+    pub fn disabled() -> AdminServer {
+        AdminServer { rx: None }
+    }
+
+    /// Binds to the given port on 127.0.0.1. Requests must carry the token
+    /// as `Authorization: Bearer <token>`.
+    pub async fn bind(port: u16, token: String, logger: Logger) -> io::Result<AdminServer> {
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port))).await?;
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let tx = tx.clone();
+                        let token = token.clone();
+                        let logger = logger.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = handle_connection(stream, &token, tx).await {
+                                logger.debug(&format!("Admin API connection failed: {err}"));
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        logger.warn(&format!("Failed to accept admin API connection: {err}"));
+                    }
+                }
+                if tx.is_closed() {
+                    break;
+                }
+            }
+        });
+        Ok(AdminServer { rx: Some(rx) })
+    }
+
+    pub async fn recv(&mut self) -> AdminRequest {
+        if let Some(ref mut rx) = self.rx {
+            if let Some(req) = rx.recv().await {
+                return req;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    token: &str,
+    tx: mpsc::Sender<AdminRequest>,
+) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let (status, body) = match timeout(Duration::from_secs(10), read_request(&mut stream)).await {
+        Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "slow request")),
+        Ok(Err(err)) => return Err(err),
+        Ok(Ok(request)) => match route(request, token) {
+            Ok(command) => {
+                let (responder, response) = oneshot::channel();
+                if tx.send(AdminRequest { command, responder }).await.is_err() {
+                    return Ok(());
+                }
+                response.await.unwrap_or_else(|_| {
+                    (
+                        503,
+                        serde_json::to_string(&ErrorBody {
+                            error: "shutting down",
+                        })
+                        .expect("serialize admin response"),
+                    )
+                })
+            }
+            Err((status, error)) => (
+                status,
+                serde_json::to_string(&ErrorBody { error }).expect("serialize admin response"),
+            ),
+        },
+    };
+    let response = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        reason(status),
+        body.len()
+    );
+    let stream = stream.get_mut();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+struct RawRequest {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+async fn read_request(stream: &mut BufReader<TcpStream>) -> io::Result<RawRequest> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts
+        .next()
+        .ok_or_else(|| invalid("missing method"))?
+        .to_owned();
+    let path = parts
+        .next()
+        .ok_or_else(|| invalid("missing path"))?
+        .to_owned();
+
+    let mut authorization = None;
+    let mut content_length = 0;
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(invalid("unexpected end of headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            let mut body = vec![0; content_length];
+            stream.read_exact(&mut body).await?;
+            return Ok(RawRequest {
+                method,
+                path,
+                authorization,
+                body,
+            });
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(invalid("malformed header"));
+        };
+        if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.trim().to_owned());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|&n| n <= MAX_BODY)
+                .ok_or_else(|| invalid("bad content length"))?;
+        }
+    }
+    Err(invalid("too many headers"))
+}
+
+fn route(request: RawRequest, token: &str) -> Result<AdminCommand, (u16, &'static str)> {
+    let authorized = request
+        .authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| token_eq(given.trim(), token));
+    if !authorized {
+        return Err((401, "missing or invalid bearer token"));
+    }
+
+    let expected_method = match request.path.as_str() {
+        "/state" => "GET",
+        "/pause" | "/resume" | "/cores" | "/shutdown" => "POST",
+        _ => return Err((404, "not found")),
+    };
+    if request.method != expected_method {
+        return Err((405, "method not allowed"));
+    }
+
+    Ok(match request.path.as_str() {
+        "/state" => AdminCommand::State,
+        "/pause" => AdminCommand::Pause,
+        "/resume" => AdminCommand::Resume,
+        "/cores" => AdminCommand::Cores(
+            serde_json::from_slice::<CoresBody>(&request.body)
+                .map_err(|_| (400, "expected {\"cores\": N}"))?
+                .cores,
+        ),
+        _ => AdminCommand::Shutdown,
+    })
+}
+
+/// Reads the bearer token from the file, or creates the file with a new
+/// random token, readable only by the current user.
+pub fn load_or_create_token(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_owned()),
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "empty token")),
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        Err(_) => (),
+    }
+
+    let mut bytes = [0; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io::Error::other("no secure randomness"))?;
+    let token = bytes.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").expect("write to string");
+        hex
+    });
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    writeln!(options.open(path)?, "{token}")?;
+    Ok(token)
+}
+
+/// Compares digests, so that the time taken does not reveal how much of
+/// the token was guessed correctly.
+fn token_eq(given: &str, token: &str) -> bool {
+    let digest = |s: &str| ring::digest::digest(&ring::digest::SHA256, s.as_bytes());
+    digest(given).as_ref() == digest(token).as_ref()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
@@ -1,7 +1,24 @@
-use std::{env, error::Error, fmt, fmt::Write, num::NonZeroU8, str::FromStr, time::Duration};
+use std::{
+    cmp::{max, min},
+    collections::HashMap,
+    convert::Infallible,
+    env,
+    error::Error,
+    fmt,
+    fmt::Write,
+    iter,
+    num::{NonZeroU8, NonZeroU64},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
 use arrayvec::ArrayString;
-use reqwest::{Client, StatusCode};
+use futures_util::{StreamExt as _, stream};
+use reqwest::{
+    Body, Client, Method, RequestBuilder, Response, StatusCode,
+    header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, HeaderValue, IF_NONE_MATCH, RETRY_AFTER},
+};
 use serde::{Deserialize, Serialize};
 use serde_repr::Deserialize_repr as DeserializeRepr;
 use serde_with::{
@@ -10,17 +27,24 @@ use serde_with::{
 };
 use shakmaty::{fen::Fen, uci::UciMove, variant::Variant};
 use tokio::{
-    sync::{mpsc, oneshot},
-    time::sleep,
+    sync::{Notify, Semaphore, mpsc, oneshot},
+    task::JoinSet,
+    time::{sleep, sleep_until},
 };
 use url::Url;
 
 use crate::{
-    assets::EvalFlavor,
-    configure::{Endpoint, Key, KeyError},
+    assets::{ByEngineFlavor, EngineFlavor, EvalFlavor},
+    configure::{ContributorTag, Endpoint, Key, KeyError},
+    control::StatusBoard,
+    dns::DnsError,
+    fatal::Fatal,
     ipc::Chunk,
+    keycheck,
     logger::Logger,
-    util::{NevermindExt as _, RandomizedBackoff},
+    stats::ApiErrorCounters,
+    stockfish::EngineId,
+    util::{NevermindExt as _, RandomizedBackoff, dot_thousands},
 };
 
 pub fn channel(
@@ -30,12 +54,31 @@ pub fn channel(
     logger: Logger,
 ) -> (ApiStub, ApiActor) {
     let (tx, rx) = mpsc::unbounded_channel();
+    let interrupt = Arc::new(Notify::new());
+    let errors = Arc::new(ApiErrorCounters::default());
     (
         ApiStub {
             tx,
+            interrupt: interrupt.clone(),
             endpoint: endpoint.clone(),
+            errors: errors.clone(),
+        },
+        ApiActor::new(rx, interrupt, endpoint, key, client, errors, logger),
+    )
+}
+
+/// Stub that hands all messages to the returned receiver, instead of
+/// sending requests.
+pub(crate) fn mock() -> (ApiStub, mpsc::UnboundedReceiver<ApiMessage>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (
+        ApiStub {
+            tx,
+            interrupt: Arc::new(Notify::new()),
+            endpoint: Endpoint::default(),
+            errors: Arc::new(ApiErrorCounters::default()),
         },
-        ApiActor::new(rx, endpoint, key, client, logger),
+        rx,
     )
 }
 
@@ -46,13 +89,16 @@ pub fn spawn(endpoint: Endpoint, key: Option<Key>, client: Client, logger: Logge
 }
 
 #[derive(Debug)]
-enum ApiMessage {
+pub(crate) enum ApiMessage {
     CheckKey {
         callback: oneshot::Sender<Result<(), KeyError>>,
     },
     Status {
         callback: oneshot::Sender<AnalysisStatus>,
     },
+    Account {
+        callback: oneshot::Sender<Result<AccountStats, AccountError>>,
+    },
     Abort {
         batch_id: BatchId,
     },
@@ -62,6 +108,7 @@ enum ApiMessage {
     },
     SubmitAnalysis {
         batch_id: BatchId,
+        engine: EngineFlavor,
         flavor: EvalFlavor,
         analysis: Vec<Option<AnalysisPart>>,
     },
@@ -77,26 +124,93 @@ struct StatusResponseBody {
     analysis: AnalysisStatus,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct AnalysisStatus {
     pub user: QueueStatus,
     pub system: QueueStatus,
 }
 
+impl fmt::Display for AnalysisStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user {}, system {}", self.user, self.system)
+    }
+}
+
 #[serde_as]
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct QueueStatus {
     // Using signed types here, because lila computes these values as
     // differences of non-atomic measurements. The results may occasionally be
     // negative.
     #[serde(rename = "acquired")]
     pub _acquired: i64,
-    #[serde(rename = "queued")]
-    pub _queued: i64,
+    pub queued: i64,
     #[serde_as(as = "DurationSeconds<u64>")]
     pub oldest: Duration,
 }
 
+impl fmt::Display for QueueStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} queued (oldest {:?})",
+            dot_thousands(self.queued.max(0) as u64),
+            self.oldest
+        )
+    }
+}
+
+/// Contributions of the key, as counted by the server.
+#[derive(Debug, Deserialize)]
+pub struct AccountStats {
+    pub batches: u64,
+    pub positions: u64,
+    pub nodes: u64,
+    #[serde(default)]
+    pub rank: Option<u64>,
+    #[serde(default)]
+    pub contributors: Option<u64>,
+}
+
+impl fmt::Display for AccountStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Total: {} batches, {} positions, {} nodes",
+            dot_thousands(self.batches),
+            dot_thousands(self.positions),
+            dot_thousands(self.nodes)
+        )?;
+        match (self.rank, self.contributors) {
+            (Some(rank), Some(contributors)) => writeln!(
+                f,
+                "Rank: {} of {}",
+                dot_thousands(rank),
+                dot_thousands(contributors)
+            ),
+            (Some(rank), None) => writeln!(f, "Rank: {}", dot_thousands(rank)),
+            (None, _) => writeln!(f, "Rank: unknown"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AccountError {
+    Unsupported,
+    AccessDenied,
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccountError::Unsupported => "server does not report contribution stats",
+            AccountError::AccessDenied => "access denied (check the configured key)",
+        })
+    }
+}
+
+impl Error for AccountError {}
+
 #[derive(Debug, Serialize)]
 pub struct VoidRequestBody {
     fishnet: Fishnet,
@@ -106,13 +220,16 @@ pub struct VoidRequestBody {
 struct Fishnet {
     version: &'static str,
     apikey: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
 }
 
 impl Fishnet {
-    fn authenticated(key: Option<Key>) -> Fishnet {
+    fn authenticated(key: Option<Key>, tag: Option<ContributorTag>) -> Fishnet {
         Fishnet {
             version: env!("CARGO_PKG_VERSION"),
             apikey: key.map_or("".to_owned(), |k| k.0),
+            tag: tag.map(|t| t.0),
         }
     }
 }
@@ -120,6 +237,10 @@ impl Fishnet {
 #[derive(Debug, Serialize)]
 struct Stockfish {
     flavor: EvalFlavor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arch: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -140,6 +261,10 @@ pub enum Work {
         depth: Option<u8>,
         #[serde(default)]
         multipv: Option<NonZeroU8>,
+        /// Optional wall-clock limit per position, in addition to nodes.
+        #[serde_as(as = "Option<DurationMilliSeconds<u64>>")]
+        #[serde(default)]
+        movetime: Option<Duration>,
         #[serde_as(as = "DurationMilliSeconds<u64>")]
         timeout: Duration,
     },
@@ -218,6 +343,27 @@ pub struct NodeLimit {
 }
 
 impl NodeLimit {
+    pub fn new(classical: u32, nnue: u32) -> NodeLimit {
+        NodeLimit {
+            classical,
+            sf16: nnue,
+        }
+    }
+
+    pub fn halved(self) -> NodeLimit {
+        NodeLimit {
+            classical: self.classical / 2,
+            sf16: self.sf16 / 2,
+        }
+    }
+
+    pub fn scaled(self, factor: f64) -> NodeLimit {
+        NodeLimit {
+            classical: (f64::from(self.classical) * factor) as u32,
+            sf16: (f64::from(self.sf16) * factor) as u32,
+        }
+    }
+
     pub fn get(&self, flavor: EvalFlavor) -> u64 {
         // Adjust for nodes spent on overlap of chunks: Worst case is
         // Chunk::MAX_POSITIONS positions split into one chunk of
@@ -259,6 +405,21 @@ impl SkillLevel {
         })
     }
 
+    /// Move time adjusted for the measured speed of this machine (NNUE
+    /// nodes per second and core), so that slow hardware still searches
+    /// about as deep as intended, and fast hardware not much deeper.
+    pub fn scaled_time(self, nnue_nps: Option<u32>) -> Duration {
+        // Speed for which the move times are tuned.
+        const REFERENCE_NPS: f64 = 1_000_000.0;
+        match nnue_nps.filter(|&nps| nps > 0) {
+            Some(nps) => self
+                .time()
+                .mul_f64((REFERENCE_NPS / f64::from(nps)).clamp(0.25, 4.0))
+                .min(Duration::from_secs(4)),
+            None => self.time(),
+        }
+    }
+
     pub fn skill_level(self) -> i32 {
         match self {
             Self::One => -9,
@@ -300,9 +461,50 @@ impl From<Centis> for Duration {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct PositionIndex(pub usize);
 
+/// Variant of an acquired batch. Variants that are not (yet) known to this
+/// client are preserved, so that the batch can be declined, instead of
+/// failing to parse the entire response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LichessVariant {
+    Known(Variant),
+    Unknown(String),
+}
+
+impl Default for LichessVariant {
+    fn default() -> LichessVariant {
+        LichessVariant::Known(Variant::default())
+    }
+}
+
+impl From<Variant> for LichessVariant {
+    fn from(variant: Variant) -> LichessVariant {
+        LichessVariant::Known(variant)
+    }
+}
+
+impl FromStr for LichessVariant {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(variant) => LichessVariant::Known(variant),
+            Err(_) => LichessVariant::Unknown(s.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for LichessVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LichessVariant::Known(variant) => fmt::Display::fmt(variant, f),
+            LichessVariant::Unknown(name) => f.write_str(name),
+        }
+    }
+}
+
 #[serde_as]
 #[derive(Debug, Deserialize)]
 pub struct AcquireResponseBody {
@@ -313,7 +515,7 @@ pub struct AcquireResponseBody {
     pub position: Fen,
     #[serde_as(as = "DisplayFromStr")]
     #[serde(default)]
-    pub variant: Variant,
+    pub variant: LichessVariant,
     #[serde_as(as = "StringWithSeparator::<SpaceSeparator, UciMove>")]
     pub moves: Vec<UciMove>,
     #[serde(rename = "skipPositions", default)]
@@ -336,7 +538,10 @@ impl AcquireResponseBody {
 pub enum Acquired {
     Accepted(AcquireResponseBody),
     NoContent,
-    Rejected,
+    /// The server asked clients to hold off for the given duration, for
+    /// example during maintenance.
+    Paused(Duration),
+    Rejected(Fatal),
 }
 
 #[derive(Debug, Serialize)]
@@ -360,7 +565,7 @@ struct BestMove {
 }
 
 #[serde_as]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(untagged)]
 pub enum AnalysisPart {
     Skipped {
@@ -388,7 +593,7 @@ pub enum AnalysisPart {
     },
 }
 
-#[derive(Debug, Serialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 pub enum Score {
     #[serde(rename = "cp")]
     Cp(i64),
@@ -396,6 +601,15 @@ pub enum Score {
     Mate(i64),
 }
 
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Score::Cp(cp) => write!(f, "cp {cp}"),
+            Score::Mate(mate) => write!(f, "mate {mate}"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct SubmitQuery {
     slow: bool,
@@ -405,7 +619,9 @@ struct SubmitQuery {
 #[derive(Debug, Clone)]
 pub struct ApiStub {
     tx: mpsc::UnboundedSender<ApiMessage>,
+    interrupt: Arc<Notify>,
     endpoint: Endpoint,
+    errors: Arc<ApiErrorCounters>,
 }
 
 impl ApiStub {
@@ -413,6 +629,16 @@ impl ApiStub {
         &self.endpoint
     }
 
+    pub fn error_counters(&self) -> Arc<ApiErrorCounters> {
+        self.errors.clone()
+    }
+
+    /// Cut short the current error backoff, for example after the system
+    /// resumed from suspend.
+    pub fn reset_backoff(&self) {
+        self.interrupt.notify_waiters();
+    }
+
     pub async fn check_key(&mut self) -> Option<Result<(), KeyError>> {
         let (req, res) = oneshot::channel();
         self.tx
@@ -429,6 +655,14 @@ impl ApiStub {
         res.await.ok()
     }
 
+    pub async fn account(&mut self) -> Option<Result<AccountStats, AccountError>> {
+        let (req, res) = oneshot::channel();
+        self.tx
+            .send(ApiMessage::Account { callback: req })
+            .expect("api actor alive");
+        res.await.ok()
+    }
+
     pub fn abort(&mut self, batch_id: BatchId) {
         self.tx
             .send(ApiMessage::Abort { batch_id })
@@ -449,12 +683,14 @@ impl ApiStub {
     pub fn submit_analysis(
         &mut self,
         batch_id: BatchId,
+        engine: EngineFlavor,
         flavor: EvalFlavor,
         analysis: Vec<Option<AnalysisPart>>,
     ) {
         self.tx
             .send(ApiMessage::SubmitAnalysis {
                 batch_id,
+                engine,
                 flavor,
                 analysis,
             })
@@ -480,75 +716,357 @@ impl ApiStub {
 
 pub struct ApiActor {
     rx: mpsc::UnboundedReceiver<ApiMessage>,
+    interrupt: Arc<Notify>,
     endpoint: Endpoint,
     key: Option<Key>,
+    contributor_tag: Option<ContributorTag>,
     client: Client,
+    errors: Arc<ApiErrorCounters>,
+    rate_limit: Arc<RateLimit>,
+    upload_throttle: Option<Arc<UploadThrottle>>,
     error_backoff: RandomizedBackoff,
     logger: Logger,
+    status_board: Option<StatusBoard>,
+    engine_ids: Arc<ByEngineFlavor<EngineId>>,
+    access_denied: u32,
+    key_validated: bool,
+    failover: Option<Failover>,
+    batch_endpoints: HashMap<BatchId, Endpoint>,
+    status_cache: Option<CachedStatus>,
+    request_id: RequestId,
+    submissions: Arc<Semaphore>,
+    in_flight: JoinSet<()>,
+}
+
+/// Last queue status, to be revalidated with `If-None-Match`.
+struct CachedStatus {
+    url: String,
+    etag: HeaderValue,
+    status: AnalysisStatus,
 }
 
+/// Endpoints to fail over to, while the current one keeps failing.
+struct Failover {
+    endpoints: Vec<Endpoint>,
+    current: usize,
+    after: Duration,
+    failing_since: Option<Instant>,
+    probed: Instant,
+}
+
+/// Complete analysis can be large (in particular matrix results), so it is
+/// uploaded in the background, while acquiring the next batch proceeds on
+/// another connection.
+const MAX_CONCURRENT_SUBMISSIONS: usize = 4;
+
 impl ApiActor {
     fn new(
         rx: mpsc::UnboundedReceiver<ApiMessage>,
+        interrupt: Arc<Notify>,
         endpoint: Endpoint,
         key: Option<Key>,
         client: Client,
+        errors: Arc<ApiErrorCounters>,
         logger: Logger,
     ) -> ApiActor {
         ApiActor {
             rx,
+            interrupt,
             endpoint,
             client,
             key,
+            contributor_tag: None,
+            errors,
+            rate_limit: Arc::default(),
+            upload_throttle: None,
             error_backoff: RandomizedBackoff::default(),
             logger,
+            status_board: None,
+            engine_ids: Arc::default(),
+            access_denied: 0,
+            key_validated: false,
+            failover: None,
+            batch_endpoints: HashMap::new(),
+            status_cache: None,
+            request_id: RequestId::random(),
+            submissions: Arc::new(Semaphore::new(MAX_CONCURRENT_SUBMISSIONS)),
+            in_flight: JoinSet::new(),
         }
     }
 
+    /// Record errors for `fishnet status`.
+    pub fn with_status_board(mut self, status_board: StatusBoard) -> ApiActor {
+        self.status_board = Some(status_board);
+        self
+    }
+
+    /// Identify the engines in analysis submissions.
+    pub fn with_engine_ids(mut self, engine_ids: Arc<ByEngineFlavor<EngineId>>) -> ApiActor {
+        self.engine_ids = engine_ids;
+        self
+    }
+
+    /// Attribute contributions to a team.
+    pub fn with_contributor_tag(mut self, contributor_tag: Option<ContributorTag>) -> ApiActor {
+        self.contributor_tag = contributor_tag;
+        self
+    }
+
+    /// Switch to the next of the given endpoints after requests failed for
+    /// the given duration, and return to the first endpoint once it
+    /// recovers.
+    pub fn with_failover(mut self, fallbacks: Vec<Endpoint>, after: Duration) -> ApiActor {
+        self.failover = (!fallbacks.is_empty()).then(|| Failover {
+            endpoints: iter::once(self.endpoint.clone()).chain(fallbacks).collect(),
+            current: 0,
+            after,
+            failing_since: None,
+            probed: Instant::now(),
+        });
+        self
+    }
+
+    /// Limit the bandwidth used by analysis submissions, shared between
+    /// concurrent uploads.
+    pub fn with_max_upload_rate(mut self, bytes_per_second: Option<NonZeroU64>) -> ApiActor {
+        self.upload_throttle = bytes_per_second.map(|rate| Arc::new(UploadThrottle::new(rate)));
+        self
+    }
+
     pub async fn run(mut self) {
         self.logger.debug("Api actor started");
-        while let Some(msg) = self.rx.recv().await {
-            self.handle_message(msg).await;
+        loop {
+            tokio::select! {
+                msg = self.rx.recv() => match msg {
+                    Some(msg) => self.handle_message(msg).await,
+                    None => break,
+                },
+                Some(_) = self.in_flight.join_next() => (),
+            }
         }
+        while self.in_flight.join_next().await.is_some() {}
         self.logger.debug("Api actor exited");
     }
 
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .bearer_auth(self.key.as_ref().map_or("", |k| &k.0))
+            .header("X-Request-Id", self.request_id.to_string())
+    }
+
     async fn handle_message(&mut self, msg: ApiMessage) {
-        if let Err(err) = self.handle_message_inner(msg).await {
-            if err.status().is_some_and(|s| s.is_success()) {
-                self.error_backoff.reset();
-            } else if err.status() == Some(StatusCode::TOO_MANY_REQUESTS) {
+        self.request_id = RequestId::random();
+        let msg = match msg {
+            ApiMessage::Acquire { callback, .. } if self.access_denied >= MAX_ACCESS_DENIED => {
+                callback
+                    .send(Acquired::Rejected(Fatal::InvalidKey))
+                    .nevermind("callback dropped");
+                return;
+            }
+            msg => msg,
+        };
+
+        self.probe_primary().await;
+
+        if let Some(delay) = self.rate_limit.delay() {
+            self.logger.debug(&format!(
+                "Pacing requests to stay under rate limit: {delay:?}"
+            ));
+            sleep(delay).await;
+        }
+
+        let kind = RequestKind::of(&msg);
+        let Err(err) = self.handle_message_inner(msg).await else {
+            self.error_backoff.reset();
+            self.access_denied = 0;
+            self.endpoint_succeeded();
+            return;
+        };
+
+        if err.status().is_some_and(|s| s.is_success()) {
+            self.error_backoff.reset();
+            self.access_denied = 0;
+            self.endpoint_succeeded();
+            return;
+        }
+
+        kind.count_error(&self.errors, &err);
+
+        if let Some(ref status_board) = self.status_board {
+            status_board.api_error(format!(
+                "{} (request {})",
+                error_report(&err),
+                self.request_id
+            ));
+        }
+
+        let class = ErrorClass::classify(&err);
+        if !matches!(class, ErrorClass::TooManyRequests | ErrorClass::Client) {
+            self.endpoint_failed();
+        }
+        let backoff = match class {
+            ErrorClass::TooManyRequests => {
                 let backoff = Duration::from_secs(60) + self.error_backoff.next();
                 self.logger.error(&format!(
-                    "Too many requests. Suspending requests for {backoff:?}."
-                ));
-                sleep(backoff).await;
-            } else {
-                let backoff = self.error_backoff.next();
-                self.logger.error(&format!(
-                    "{}. Backing off {:?}.",
-                    error_report(&err),
-                    backoff
+                    "Too many requests (request {}). Suspending requests for {backoff:?}.",
+                    self.request_id
                 ));
-                sleep(backoff).await;
+                // Respect the rate limit, even if the network changes.
+                self.backoff(backoff, false).await;
+                return;
             }
-        } else {
+            // Only acquire requests tell whether the key itself is rejected.
+            ErrorClass::AccessDenied if matches!(kind, RequestKind::Acquire) => {
+                self.access_denied += 1;
+                if self.access_denied >= MAX_ACCESS_DENIED {
+                    self.logger.error(&format!(
+                        "{} (request {}). Access denied repeatedly. Check the configured key (fishnet configure).",
+                        error_report(&err),
+                        self.request_id
+                    ));
+                    return;
+                }
+                self.error_backoff.next()
+            }
+            // Unlikely to be resolved by retrying quickly.
+            ErrorClass::Client | ErrorClass::AccessDenied | ErrorClass::Dns | ErrorClass::Tls => {
+                Duration::from_secs(60) + self.error_backoff.next()
+            }
+            // Typically transient, e.g. during server restarts.
+            ErrorClass::Gateway => min(self.error_backoff.next(), Duration::from_secs(10)),
+            ErrorClass::Server | ErrorClass::Connect | ErrorClass::Other => {
+                self.error_backoff.next()
+            }
+        };
+
+        self.logger.error(&format!(
+            "{} (request {}){}. Backing off {:?}.",
+            error_report(&err),
+            self.request_id,
+            class.hint(),
+            backoff
+        ));
+        self.backoff(backoff, true).await;
+    }
+
+    /// Waits, unless interrupted via [`ApiStub::reset_backoff()`] if
+    /// `interruptible`.
+    async fn backoff(&mut self, backoff: Duration, interruptible: bool) {
+        if let Some(ref status_board) = self.status_board {
+            status_board.api_backoff(Some(backoff));
+        }
+        tokio::select! {
+            _ = sleep(backoff) => (),
+            _ = self.interrupt.notified(), if interruptible => {
+                self.logger.debug("Backoff interrupted");
+                self.error_backoff.reset();
+            }
+        }
+        if let Some(ref status_board) = self.status_board {
+            status_board.api_backoff(None);
+        }
+    }
+
+    /// Remembers that the endpoint accepted the key, once per run, so that
+    /// later starts can proceed during an outage.
+    fn key_accepted(&mut self) {
+        if !self.key_validated {
+            self.key_validated = true;
+            if let Some(ref key) = self.key {
+                keycheck::record(&self.endpoint, key);
+            }
+        }
+    }
+
+    fn endpoint_succeeded(&mut self) {
+        if let Some(ref mut failover) = self.failover {
+            failover.failing_since = None;
+        }
+    }
+
+    fn endpoint_failed(&mut self) {
+        let Some(ref mut failover) = self.failover else {
+            return;
+        };
+        let now = Instant::now();
+        let failing = now.duration_since(*failover.failing_since.get_or_insert(now));
+        if failing < failover.after {
+            return;
+        }
+        if let Some(next) = failover.endpoints.get(failover.current + 1) {
+            self.logger.warn(&format!(
+                "Endpoint {} failing for {failing:?}. Failing over to {next}.",
+                self.endpoint
+            ));
+            self.endpoint = next.clone();
+            failover.current += 1;
+            failover.failing_since = None;
+            failover.probed = now;
+            self.access_denied = 0;
             self.error_backoff.reset();
         }
     }
 
+    /// Returns to the primary endpoint if it responds again, checking at
+    /// most once per failover period.
+    async fn probe_primary(&mut self) {
+        let primary = match self.failover {
+            Some(ref mut failover)
+                if failover.current > 0 && failover.probed.elapsed() >= failover.after =>
+            {
+                failover.probed = Instant::now();
+                failover.endpoints[0].clone()
+            }
+            _ => return,
+        };
+        let url = format!("{primary}/status");
+        let recovered = self
+            .request(Method::GET, &url)
+            .send()
+            .await
+            .is_ok_and(|res| {
+                !res.status().is_server_error() && res.status() != StatusCode::TOO_MANY_REQUESTS
+            });
+        if recovered {
+            self.logger.fishnet_info(&format!(
+                "Endpoint {primary} recovered. Returning from {}.",
+                self.endpoint
+            ));
+            self.endpoint = primary;
+            if let Some(ref mut failover) = self.failover {
+                failover.current = 0;
+                failover.failing_since = None;
+            }
+        }
+    }
+
+    /// Remembers where the batch was acquired, so that results are submitted
+    /// to the same endpoint after failing over.
+    fn track_batch(&mut self, batch_id: BatchId) {
+        if self.failover.is_some() {
+            self.batch_endpoints.insert(batch_id, self.endpoint.clone());
+        }
+    }
+
+    fn batch_endpoint(&self, batch_id: BatchId) -> &Endpoint {
+        self.batch_endpoints
+            .get(&batch_id)
+            .unwrap_or(&self.endpoint)
+    }
+
     async fn abort(&mut self, batch_id: BatchId) -> reqwest::Result<()> {
-        let url = format!("{}/abort/{}", self.endpoint, batch_id);
+        let url = format!("{}/abort/{}", self.batch_endpoint(batch_id), batch_id);
+        self.batch_endpoints.remove(&batch_id);
         self.logger.warn(&format!("Aborting batch {batch_id}."));
         let res = self
-            .client
-            .post(&url)
-            .bearer_auth(self.key.as_ref().map_or("", |k| &k.0))
+            .request(Method::POST, &url)
             .json(&VoidRequestBody {
-                fishnet: Fishnet::authenticated(self.key.clone()),
+                fishnet: Fishnet::authenticated(self.key.clone(), self.contributor_tag.clone()),
             })
             .send()
             .await?;
+        self.rate_limit.observe(&res);
 
         if res.status() == StatusCode::NOT_FOUND {
             self.logger.warn(&format!(
@@ -564,14 +1082,11 @@ impl ApiActor {
         match msg {
             ApiMessage::CheckKey { callback } => {
                 let url = format!("{}/key", self.endpoint);
-                let res = self
-                    .client
-                    .get(&url)
-                    .bearer_auth(self.key.as_ref().map_or("", |k| &k.0))
-                    .send()
-                    .await?;
+                let res = self.request(Method::GET, &url).send().await?;
+                self.rate_limit.observe(&res);
                 match res.status() {
                     StatusCode::NO_CONTENT | StatusCode::OK => {
+                        self.key_accepted();
                         callback.send(Ok(())).nevermind("callback dropped");
                     }
                     StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
@@ -587,17 +1102,16 @@ impl ApiActor {
                             self.endpoint,
                             self.key.as_ref().map_or("", |k| &k.0)
                         );
-                        let res = self
-                            .client
-                            .get(&url)
-                            .bearer_auth(self.key.as_ref().map_or("", |k| &k.0))
-                            .send()
-                            .await?;
+                        let res = self.request(Method::GET, &url).send().await?;
+                        self.rate_limit.observe(&res);
                         match res.status() {
                             StatusCode::NOT_FOUND => callback
                                 .send(Err(KeyError::AccessDenied))
                                 .nevermind("callback dropped"),
-                            StatusCode::OK => callback.send(Ok(())).nevermind("callback dropped"),
+                            StatusCode::OK => {
+                                self.key_accepted();
+                                callback.send(Ok(())).nevermind("callback dropped");
+                            }
                             status => {
                                 self.logger.warn(&format!(
                                     "Unexpected status while checking legacy key: {status}"
@@ -615,16 +1129,33 @@ impl ApiActor {
             }
             ApiMessage::Status { callback } => {
                 let url = format!("{}/status", self.endpoint);
-                let res = self
-                    .client
-                    .get(&url)
-                    .bearer_auth(self.key.as_ref().map_or("", |k| &k.0))
-                    .send()
-                    .await?;
+                let mut req = self.request(Method::GET, &url);
+                if let Some(ref cached) = self.status_cache {
+                    if cached.url == url {
+                        req = req.header(IF_NONE_MATCH, cached.etag.clone());
+                    }
+                }
+                let res = req.send().await?;
+                self.rate_limit.observe(&res);
                 match res.status() {
-                    StatusCode::OK => callback
-                        .send(res.json::<StatusResponseBody>().await?.analysis)
-                        .nevermind("callback dropped"),
+                    StatusCode::OK => {
+                        let etag = res.headers().get(ETAG).cloned();
+                        let status = res.json::<StatusResponseBody>().await?.analysis;
+                        self.status_cache = etag.map(|etag| CachedStatus {
+                            url,
+                            etag,
+                            status: status.clone(),
+                        });
+                        callback.send(status).nevermind("callback dropped");
+                    }
+                    StatusCode::NOT_MODIFIED => match self.status_cache {
+                        Some(ref cached) if cached.url == url => callback
+                            .send(cached.status.clone())
+                            .nevermind("callback dropped"),
+                        _ => self
+                            .logger
+                            .warn("Queue status not modified, but nothing cached"),
+                    },
                     StatusCode::NOT_FOUND => (),
                     status => {
                         self.logger
@@ -633,47 +1164,86 @@ impl ApiActor {
                     }
                 }
             }
+            ApiMessage::Account { callback } => {
+                let url = format!("{}/account", self.endpoint);
+                let res = self.request(Method::GET, &url).send().await?;
+                self.rate_limit.observe(&res);
+                match res.status() {
+                    StatusCode::OK => callback
+                        .send(Ok(res.json().await?))
+                        .nevermind("callback dropped"),
+                    StatusCode::NOT_FOUND => callback
+                        .send(Err(AccountError::Unsupported))
+                        .nevermind("callback dropped"),
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => callback
+                        .send(Err(AccountError::AccessDenied))
+                        .nevermind("callback dropped"),
+                    status => {
+                        self.logger
+                            .warn(&format!("Unexpected status for account: {status}"));
+                        res.error_for_status()?;
+                    }
+                }
+            }
             ApiMessage::Abort { batch_id } => {
                 self.abort(batch_id).await?;
             }
             ApiMessage::Acquire { callback, query } => {
                 let url = format!("{}/acquire", self.endpoint);
                 let res = self
-                    .client
-                    .post(&url)
-                    .bearer_auth(self.key.as_ref().map_or("", |k| &k.0))
+                    .request(Method::POST, &url)
                     .query(&query)
                     .json(&VoidRequestBody {
-                        fishnet: Fishnet::authenticated(self.key.clone()),
+                        fishnet: Fishnet::authenticated(
+                            self.key.clone(),
+                            self.contributor_tag.clone(),
+                        ),
                     })
                     .send()
                     .await?;
+                self.rate_limit.observe(&res);
 
-                match res.status() {
-                    StatusCode::NO_CONTENT => callback
+                if res.status().is_success() {
+                    // Also validates the key, if the endpoint was unreachable
+                    // at startup.
+                    self.key_accepted();
+                }
+
+                match (res.status(), retry_after(&res)) {
+                    (StatusCode::NO_CONTENT | StatusCode::SERVICE_UNAVAILABLE, Some(pause)) => {
+                        callback
+                            .send(Acquired::Paused(pause))
+                            .nevermind("callback dropped");
+                    }
+                    (StatusCode::NO_CONTENT, None) => callback
                         .send(Acquired::NoContent)
                         .nevermind("callback dropped"),
-                    StatusCode::BAD_REQUEST
-                    | StatusCode::UNAUTHORIZED
-                    | StatusCode::FORBIDDEN
-                    | StatusCode::NOT_ACCEPTABLE => {
+                    (StatusCode::BAD_REQUEST | StatusCode::NOT_ACCEPTABLE, _) => {
                         let text = res.text().await?;
-                        self.logger
-                            .error(&format!("Server rejected request: {text}"));
+                        self.logger.error(&format!(
+                            "Server rejected request {}: {text}",
+                            self.request_id
+                        ));
                         callback
-                            .send(Acquired::Rejected)
+                            .send(Acquired::Rejected(Fatal::ClientRejected))
                             .nevermind("callback dropped");
                     }
-                    StatusCode::OK | StatusCode::ACCEPTED => {
+                    (StatusCode::OK | StatusCode::ACCEPTED, _) => {
+                        let body: AcquireResponseBody = res.json().await?;
+                        self.track_batch(body.work.id());
                         if let Err(Acquired::Accepted(res)) =
-                            callback.send(Acquired::Accepted(res.json().await?))
+                            callback.send(Acquired::Accepted(body))
                         {
                             self.logger
                                 .error("Acquired a batch, but callback dropped. Aborting.");
                             self.abort(res.work.id()).await?;
                         }
                     }
-                    status => {
+                    (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN, _) => {
+                        // Rejected as invalid key only if repeated.
+                        res.error_for_status()?;
+                    }
+                    (status, _) => {
                         self.logger
                             .warn(&format!("Unexpected status for acquire: {status}"));
                         res.error_for_status()?;
@@ -682,58 +1252,131 @@ impl ApiActor {
             }
             ApiMessage::SubmitAnalysis {
                 batch_id,
+                engine,
                 flavor,
                 analysis,
             } => {
-                let url = format!("{}/analysis/{}", self.endpoint, batch_id);
-                let res = self
-                    .client
-                    .post(&url)
-                    .bearer_auth(self.key.as_ref().map_or("", |k| &k.0))
-                    .query(&SubmitQuery {
-                        stop: true,
-                        slow: false,
-                    })
-                    .json(&AnalysisRequestBody {
-                        fishnet: Fishnet::authenticated(self.key.clone()),
-                        stockfish: Stockfish { flavor },
-                        analysis,
-                    })
-                    .send()
-                    .await?
-                    .error_for_status()?;
+                let engine_id = self.engine_ids.get(engine);
+                let url = format!("{}/analysis/{}", self.batch_endpoint(batch_id), batch_id);
+                // Quirk: Lila distinguishes progress reports from complete
+                // analysis by looking at the first part.
+                let complete = analysis.first().is_some_and(Option::is_some);
+                let body = AnalysisRequestBody {
+                    fishnet: Fishnet::authenticated(self.key.clone(), self.contributor_tag.clone()),
+                    stockfish: Stockfish {
+                        flavor,
+                        name: engine_id.name.get().cloned(),
+                        arch: engine_id.arch.clone(),
+                    },
+                    analysis,
+                };
+                if complete {
+                    self.batch_endpoints.remove(&batch_id);
+                }
+                let req = self.request(Method::POST, &url).query(&SubmitQuery {
+                    stop: true,
+                    slow: false,
+                });
+                let req = match self.upload_throttle {
+                    Some(ref throttle) => {
+                        let body = serde_json::to_vec(&body).expect("serialize analysis");
+                        req.header(CONTENT_TYPE, "application/json")
+                            .header(CONTENT_LENGTH, body.len())
+                            .timeout(throttle.timeout(body.len()))
+                            .body(throttle.body(body))
+                    }
+                    None => req.json(&body),
+                };
 
-                if res.status() != StatusCode::NO_CONTENT {
-                    self.logger.warn(&format!(
-                        "Unexpected status for submitting analysis: {}",
-                        res.status()
-                    ));
+                if !complete {
+                    // Progress reports are small. Keep them in order, so
+                    // that none can arrive after the complete analysis.
+                    let res = req.send().await?;
+                    self.rate_limit.observe(&res);
+                    let res = res.error_for_status()?;
+                    if res.status() != StatusCode::NO_CONTENT {
+                        self.logger.warn(&format!(
+                            "Unexpected status for submitting progress: {}",
+                            res.status()
+                        ));
+                    }
+                    return Ok(());
                 }
+
+                let permit = Arc::clone(&self.submissions)
+                    .acquire_owned()
+                    .await
+                    .expect("submission semaphore open");
+                let logger = self.logger.clone();
+                let errors = self.errors.clone();
+                let status_board = self.status_board.clone();
+                let rate_limit = self.rate_limit.clone();
+                let request_id = self.request_id;
+                self.in_flight.spawn(async move {
+                    let _permit = permit;
+                    match req
+                        .send()
+                        .await
+                        .inspect(|res| rate_limit.observe(res))
+                        .and_then(|res| res.error_for_status())
+                    {
+                        Ok(res) if res.status() != StatusCode::NO_CONTENT => {
+                            logger.warn(&format!(
+                                "Unexpected status for submitting analysis: {}",
+                                res.status()
+                            ));
+                        }
+                        Ok(_) => (),
+                        Err(err) => {
+                            RequestKind::Submit.count_error(&errors, &err);
+                            if matches!(
+                                err.status(),
+                                Some(StatusCode::NOT_FOUND | StatusCode::GONE)
+                            ) {
+                                // No longer assigned to us, probably timed
+                                // out on the server.
+                                errors.aborted_by_server();
+                            }
+                            let report = format!("{} (request {request_id})", error_report(&err));
+                            logger.error(&format!(
+                                "Failed to submit analysis for {batch_id}: {report}"
+                            ));
+                            if let Some(status_board) = status_board {
+                                status_board.api_error(report);
+                            }
+                        }
+                    }
+                });
             }
             ApiMessage::SubmitMove {
                 batch_id,
                 best_move,
                 callback,
             } => {
-                let url = format!("{}/move/{}", self.endpoint, batch_id);
+                let url = format!("{}/move/{}", self.batch_endpoint(batch_id), batch_id);
+                self.batch_endpoints.remove(&batch_id);
                 let res = self
-                    .client
-                    .post(&url)
-                    .bearer_auth(self.key.as_ref().map_or("", |k| &k.0))
+                    .request(Method::POST, &url)
                     .json(&MoveRequestBody {
-                        fishnet: Fishnet::authenticated(self.key.clone()),
+                        fishnet: Fishnet::authenticated(
+                            self.key.clone(),
+                            self.contributor_tag.clone(),
+                        ),
                         m: BestMove { best_move },
                     })
                     .send()
                     .await?;
+                self.rate_limit.observe(&res);
 
                 match res.status() {
                     StatusCode::NO_CONTENT => callback
                         .send(Acquired::NoContent)
                         .nevermind("callback dropped"),
                     StatusCode::OK | StatusCode::ACCEPTED => {
+                        let body: AcquireResponseBody = res.json().await?;
+                        self.track_batch(body.work.id());
                         if let Err(Acquired::Accepted(res)) =
-                            callback.send(Acquired::Accepted(res.json().await?))
+                            callback.send(Acquired::Accepted(body))
                         {
                             self.logger.error("Acquired a batch while submitting move, but callback dropped. Aborting.");
                             self.abort(res.work.id()).await?;
@@ -756,6 +1399,267 @@ impl ApiActor {
     }
 }
 
+/// Random UUID (version 4) sent as `X-Request-Id`, so that failures can be
+/// correlated with server logs.
+#[derive(Debug, Copy, Clone)]
+pub struct RequestId(u128);
+
+impl RequestId {
+    pub fn random() -> RequestId {
+        let bits = fastrand::u128(..);
+        let bits = (bits & !(0xf << 76)) | (0x4 << 76); // Version 4
+        let bits = (bits & !(0x3 << 62)) | (0x2 << 62); // Variant 1
+        RequestId(bits)
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = format!("{:032x}", self.0);
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+/// Give up after this many consecutive 401/403 responses to acquire
+/// requests.
+const MAX_ACCESS_DENIED: u32 = 3;
+
+/// Paces requests according to rate limit headers, if the server sends
+/// them, instead of running into 429 Too Many Requests and backing off for
+/// a long time.
+#[derive(Debug, Default)]
+struct RateLimit {
+    next: Mutex<Option<Instant>>,
+}
+
+/// Spreads uploads over time, like a token bucket without burst capacity.
+#[derive(Debug)]
+struct UploadThrottle {
+    bytes_per_second: NonZeroU64,
+    next: Mutex<Instant>,
+}
+
+impl UploadThrottle {
+    const PIECE_SIZE: usize = 16 * 1024;
+
+    fn new(bytes_per_second: NonZeroU64) -> UploadThrottle {
+        UploadThrottle {
+            bytes_per_second,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves bandwidth for the given number of bytes, and waits until it
+    /// is their turn.
+    async fn reserve(&self, bytes: usize) {
+        let at = {
+            let mut next = self.next.lock().expect("upload throttle");
+            let at = max(*next, Instant::now());
+            *next = at + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second.get() as f64);
+            at
+        };
+        sleep_until(at.into()).await;
+    }
+
+    /// Request timeout for an upload of the given size, allowing for the
+    /// uploads that are already queued ahead of it, plus the usual margin.
+    fn timeout(&self, bytes: usize) -> Duration {
+        let queued = self
+            .next
+            .lock()
+            .expect("upload throttle")
+            .saturating_duration_since(Instant::now());
+        queued
+            + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second.get() as f64)
+            + Duration::from_secs(30)
+    }
+
+    fn body(self: &Arc<Self>, body: Vec<u8>) -> Body {
+        let throttle = Arc::clone(self);
+        let pieces: Vec<Vec<u8>> = body
+            .chunks(UploadThrottle::PIECE_SIZE)
+            .map(<[u8]>::to_vec)
+            .collect();
+        Body::wrap_stream(stream::iter(pieces).then(move |piece| {
+            let throttle = Arc::clone(&throttle);
+            async move {
+                throttle.reserve(piece.len()).await;
+                Ok::<_, Infallible>(piece)
+            }
+        }))
+    }
+}
+
+/// Longest pause requested via `Retry-After` that is honored.
+const MAX_SERVER_PAUSE: Duration = Duration::from_secs(60 * 60);
+
+/// Delay requested with a `Retry-After` header, in seconds.
+fn retry_after(res: &Response) -> Option<Duration> {
+    let secs = res
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(min(Duration::from_secs(secs), MAX_SERVER_PAUSE))
+}
+
+impl RateLimit {
+    const MAX_DELAY: Duration = Duration::from_secs(60);
+
+    fn observe(&self, res: &Response) {
+        let header = |names: [&str; 2]| {
+            names.into_iter().find_map(|name| {
+                res.headers()
+                    .get(name)?
+                    .to_str()
+                    .ok()?
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+        };
+        let (Some(remaining), Some(reset)) = (
+            header(["ratelimit-remaining", "x-ratelimit-remaining"]),
+            header(["ratelimit-reset", "x-ratelimit-reset"]),
+        ) else {
+            return;
+        };
+
+        // Reset is either a delay or a Unix timestamp, in seconds.
+        let reset = if reset > 1_000_000_000 {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            Duration::from_secs(reset).saturating_sub(now)
+        } else {
+            Duration::from_secs(reset)
+        };
+
+        // Spread the remaining requests evenly until the reset.
+        let delay = min(
+            reset / u32::try_from(remaining.saturating_add(1)).unwrap_or(u32::MAX),
+            RateLimit::MAX_DELAY,
+        );
+        *self.next.lock().expect("rate limit") = Some(Instant::now() + delay);
+    }
+
+    fn delay(&self) -> Option<Duration> {
+        self.next
+            .lock()
+            .expect("rate limit")
+            .map(|next| next.saturating_duration_since(Instant::now()))
+            .filter(|delay| !delay.is_zero())
+    }
+}
+
+/// Kind of request, for error statistics.
+#[derive(Debug, Copy, Clone)]
+enum RequestKind {
+    Acquire,
+    Submit,
+    Other,
+}
+
+impl RequestKind {
+    fn of(msg: &ApiMessage) -> RequestKind {
+        match msg {
+            ApiMessage::Acquire { .. } => RequestKind::Acquire,
+            ApiMessage::SubmitAnalysis { .. } | ApiMessage::SubmitMove { .. } => {
+                RequestKind::Submit
+            }
+            _ => RequestKind::Other,
+        }
+    }
+
+    fn count_error(self, errors: &ApiErrorCounters, err: &reqwest::Error) {
+        match self {
+            RequestKind::Acquire => errors.acquire_failed(),
+            RequestKind::Submit => errors.submit_failed(),
+            RequestKind::Other => (),
+        }
+        if err.status() == Some(StatusCode::TOO_MANY_REQUESTS) {
+            errors.rate_limited();
+        }
+        if err.is_timeout() {
+            errors.timed_out();
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ErrorClass {
+    TooManyRequests,
+    AccessDenied,
+    Client,
+    Gateway,
+    Server,
+    Dns,
+    Tls,
+    Connect,
+    Other,
+}
+
+impl ErrorClass {
+    fn classify(err: &reqwest::Error) -> ErrorClass {
+        if let Some(status) = err.status() {
+            return match status {
+                StatusCode::TOO_MANY_REQUESTS => ErrorClass::TooManyRequests,
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ErrorClass::AccessDenied,
+                StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT => ErrorClass::Gateway,
+                s if s.is_server_error() => ErrorClass::Server,
+                _ => ErrorClass::Client,
+            };
+        }
+
+        let mut source: Option<&(dyn Error + 'static)> = err.source();
+        while let Some(src) = source {
+            if src.is::<DnsError>() {
+                return ErrorClass::Dns;
+            }
+            if src.is::<rustls::Error>() {
+                return ErrorClass::Tls;
+            }
+            if let Some(io) = src.downcast_ref::<std::io::Error>() {
+                if io
+                    .get_ref()
+                    .is_some_and(|inner| inner.is::<rustls::Error>())
+                {
+                    return ErrorClass::Tls;
+                }
+            }
+            source = src.source();
+        }
+
+        if err.is_connect() {
+            ErrorClass::Connect
+        } else {
+            ErrorClass::Other
+        }
+    }
+
+    fn hint(self) -> &'static str {
+        match self {
+            ErrorClass::AccessDenied => " (check the configured key)",
+            ErrorClass::Dns => " (check network connectivity and DNS)",
+            ErrorClass::Tls => " (check the system clock and any intercepting proxies)",
+            _ => "",
+        }
+    }
+}
+
 fn error_report(mut err: &dyn Error) -> String {
     let mut report = format!("{}", err);
     while let Some(src) = err.source() {
@@ -0,0 +1,311 @@
+//! Local record of submitted analysis.
+
+use std::{
+    fmt,
+    fmt::Write as _,
+    fs,
+    fs::OpenOptions,
+    io,
+    io::Write as _,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use shakmaty::{
+    CastlingMode, Color, EnPassantMode, Position as _, PositionError,
+    fen::Fen,
+    san::{San, SanPlus},
+    uci::UciMove,
+    variant::{Variant, VariantPosition},
+};
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+use crate::api::{AnalysisPart, Score};
+
+/// Appends records as newline-delimited JSON to one file per UTC day, for
+/// example `analysis-2025-06-01.jsonl.zst`.
+///
+/// Each record is a separate zstd frame. Concatenated frames decompress as
+/// one stream, so the files can be read with `zstdcat`.
+#[derive(Debug)]
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    pub fn new(dir: PathBuf) -> io::Result<Archive> {
+        fs::create_dir_all(&dir)?;
+        Ok(Archive { dir })
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    pub fn append<T: Serialize>(&self, record: &T) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut encoder = ZstdEncoder::new(Vec::new(), 0)?;
+        encoder.write_all(&line)?;
+        let frame = encoder.finish()?;
+
+        let (year, month, day) = today();
+        let path = self
+            .dir
+            .join(format!("analysis-{year:04}-{month:02}-{day:02}.jsonl.zst"));
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&frame)
+    }
+
+    /// Writes a PGN of a single game, for example
+    /// `analysis-2025-06-01-abcd1234.pgn`.
+    pub fn write_pgn(&self, name: impl fmt::Display, pgn: &str) -> io::Result<()> {
+        let (year, month, day) = today();
+        let path = self
+            .dir
+            .join(format!("analysis-{year:04}-{month:02}-{day:02}-{name}.pgn"));
+        fs::write(path, pgn)
+    }
+}
+
+/// Game to be rendered by [`annotated_pgn()`].
+pub struct PgnGame<'a> {
+    pub site: Option<&'a str>,
+    pub annotator: Option<&'a str>,
+    pub variant: Variant,
+    pub position: &'a Fen,
+    pub moves: &'a [UciMove],
+}
+
+/// Renders an analysed game as PGN, with the evaluation after each move and
+/// the engine's preferred move wherever the game deviated from it.
+///
+/// `analysis` is indexed by ply, starting with the root position, and scores
+/// are relative to the side to move. Returns `None` if the game cannot be
+/// replayed.
+pub fn annotated_pgn(game: &PgnGame<'_>, analysis: &[Option<AnalysisPart>]) -> Option<String> {
+    let mut pos = VariantPosition::from_setup(
+        game.variant,
+        game.position.clone().into_setup(),
+        CastlingMode::Chess960,
+    )
+    .or_else(PositionError::ignore_invalid_ep_square)
+    .or_else(PositionError::ignore_invalid_castling_rights)
+    .or_else(PositionError::ignore_too_much_material)
+    .ok()?;
+
+    let (year, month, day) = today();
+    let mut pgn = String::new();
+    let mut tag = |name: &str, value: &str| {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(pgn, "[{name} \"{value}\"]").expect("write to string");
+    };
+    tag("Event", "Analysis");
+    tag("Site", game.site.unwrap_or("?"));
+    tag("Date", &format!("{year:04}.{month:02}.{day:02}"));
+    tag("Round", "-");
+    tag("White", "?");
+    tag("Black", "?");
+    tag("Result", "*");
+    if game.variant != Variant::Chess {
+        tag("Variant", pgn_variant_name(game.variant));
+    }
+    if *game.position
+        != Fen::from_position(&VariantPosition::new(game.variant), EnPassantMode::Legal)
+    {
+        tag("FEN", &game.position.to_string());
+        tag("SetUp", "1");
+    }
+    if let Some(annotator) = game.annotator {
+        tag("Annotator", annotator);
+    }
+    pgn.push('\n');
+
+    let mut tokens = Vec::new();
+    for (ply, uci) in game.moves.iter().enumerate() {
+        let m = uci.to_move(&pos).ok()?;
+        let best = analysis
+            .get(ply)
+            .and_then(Option::as_ref)
+            .and_then(best_line)
+            .and_then(|(_, best)| best)
+            .and_then(|best| best.to_move(&pos).ok())
+            .filter(|best| *best != m)
+            .map(|best| San::from_move(&pos, best));
+
+        let number = pos.fullmoves();
+        tokens.push(match pos.turn() {
+            Color::White => format!("{number}."),
+            Color::Black => format!("{number}..."),
+        });
+        tokens.push(SanPlus::from_move_and_play_unchecked(&mut pos, m).to_string());
+
+        let mut comment = Vec::new();
+        if let Some((score, _)) = analysis
+            .get(ply + 1)
+            .and_then(Option::as_ref)
+            .and_then(best_line)
+        {
+            if let Some(eval) = pgn_eval(score, pos.turn()) {
+                comment.push(format!("[%eval {eval}]"));
+            }
+        }
+        if let Some(best) = best {
+            comment.push(format!("{best} was best."));
+        }
+        if !comment.is_empty() {
+            tokens.push(format!("{{ {} }}", comment.join(" ")));
+        }
+    }
+    tokens.push("*".to_owned());
+
+    // Keep lines short, as recommended for PGN export.
+    let mut line_len = 0;
+    for token in tokens.join(" ").split(' ') {
+        if line_len > 0 && line_len + 1 + token.len() > 79 {
+            pgn.push('\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            pgn.push(' ');
+            line_len += 1;
+        }
+        pgn.push_str(token);
+        line_len += token.len();
+    }
+    pgn.push_str("\n\n");
+    Some(pgn)
+}
+
+/// Score and first move of the principal variation. For multipv analysis
+/// only the best line at the greatest depth is considered.
+fn best_line(part: &AnalysisPart) -> Option<(Score, Option<&UciMove>)> {
+    match part {
+        AnalysisPart::Skipped { .. } => None,
+        AnalysisPart::Best { pv, score, .. } => Some((*score, pv.first())),
+        AnalysisPart::Matrix { pv, score, .. } => Some((
+            score.first()?.iter().rev().find_map(|s| *s)?,
+            pv.first()
+                .and_then(|pv| pv.iter().rev().find_map(Option::as_ref))
+                .and_then(|pv| pv.first()),
+        )),
+    }
+}
+
+/// Formats a score relative to the side to move as `%eval` from White's
+/// point of view.
+fn pgn_eval(score: Score, turn: Color) -> Option<String> {
+    let sign = match turn {
+        Color::White => 1,
+        Color::Black => -1,
+    };
+    match score {
+        // Already checkmated.
+        Score::Mate(0) => None,
+        Score::Mate(mate) => Some(format!("#{}", sign * mate)),
+        Score::Cp(cp) => Some(format!("{:.2}", (sign * cp) as f64 / 100.0)),
+    }
+}
+
+fn pgn_variant_name(variant: Variant) -> &'static str {
+    match variant {
+        Variant::Chess => "Standard",
+        Variant::Antichess => "Antichess",
+        Variant::Atomic => "Atomic",
+        Variant::Crazyhouse => "Crazyhouse",
+        Variant::Horde => "Horde",
+        Variant::KingOfTheHill => "King of the Hill",
+        Variant::RacingKings => "Racing Kings",
+        Variant::ThreeCheck => "Three-check",
+    }
+}
+
+fn today() -> (u64, u64, u64) {
+    civil_from_days(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs()
+            / (24 * 60 * 60),
+    )
+}
+
+/// Converts days since 1970-01-01 to a date in the proleptic Gregorian
+/// calendar.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(20_240), (2025, 6, 1));
+    }
+
+    #[test]
+    fn test_annotated_pgn() {
+        let best = |pv: &str, score| {
+            Some(AnalysisPart::Best {
+                pv: vec![pv.parse().expect("uci")],
+                score,
+                depth: 20,
+                nodes: 1_500_000,
+                time: 1000,
+                nps: None,
+            })
+        };
+        let moves: Vec<UciMove> = ["f2f3", "e7e5", "g2g4", "d8h4"]
+            .into_iter()
+            .map(|uci| uci.parse().expect("uci"))
+            .collect();
+        let analysis = [
+            best("e2e4", Score::Cp(20)),
+            best("e7e5", Score::Cp(50)),
+            best("b1c3", Score::Cp(60)),
+            best("d8h4", Score::Mate(1)),
+            Some(AnalysisPart::Skipped { skipped: true }),
+        ];
+        let pgn = annotated_pgn(
+            &PgnGame {
+                site: Some("https://lichess.org/abcd1234"),
+                annotator: Some("Stockfish 17"),
+                variant: Variant::Chess,
+                position: &Fen::from_position(
+                    &VariantPosition::new(Variant::Chess),
+                    EnPassantMode::Legal,
+                ),
+                moves: &moves,
+            },
+            &analysis,
+        )
+        .expect("replayable game");
+
+        let (tags, movetext) = pgn.split_once("\n\n").expect("movetext");
+        assert!(tags.contains("[Site \"https://lichess.org/abcd1234\"]"));
+        assert!(tags.contains("[Annotator \"Stockfish 17\"]"));
+        assert!(!tags.contains("[FEN "));
+        assert_eq!(
+            movetext,
+            "1. f3 { [%eval -0.50] e4 was best. } 1... e5 { [%eval 0.60] } 2. g4 { [%eval\n\
+             #-1] Nc3 was best. } 2... Qh4# *\n\n"
+        );
+    }
+}
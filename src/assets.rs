@@ -1,19 +1,30 @@
 use std::{
-    fmt,
+    fmt, fs,
     fs::File,
     io,
+    io::{Read as _, Write as _},
     path::{Path, PathBuf},
-    str,
+    process, str,
+    sync::LazyLock,
+    thread,
 };
 
 use ar::Archive;
 use bitflags::bitflags;
 use serde::Serialize;
+use shakmaty::variant::Variant;
 use tempfile::TempDir;
 use zstd::stream::read::Decoder as ZstdDecoder;
 
 static ASSETS_AR_ZST: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/assets.ar.zst"));
 
+static VARIANT_EVAL_FILES: LazyLock<Vec<&'static str>> = LazyLock::new(|| {
+    env!("FISHNET_VARIANT_EVAL_FILES")
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .collect()
+});
+
 bitflags! {
     #[derive(Debug, Copy, Clone, Eq, PartialEq)]
     pub struct Cpu: u32 {
@@ -151,12 +162,51 @@ pub enum EngineFlavor {
 }
 
 impl EngineFlavor {
-    pub fn eval_flavor(self) -> EvalFlavor {
+    pub fn eval_flavor(self, variant: Variant) -> EvalFlavor {
         match self {
             EngineFlavor::Official => EvalFlavor::Nnue,
+            EngineFlavor::MultiVariant if self.variant_eval_file(variant).is_some() => {
+                EvalFlavor::Nnue
+            }
             EngineFlavor::MultiVariant => EvalFlavor::Hce,
         }
     }
+
+    /// Eval file to load for the given variant, if the engine does not
+    /// pick a suitable one by default.
+    pub fn variant_eval_file(self, variant: Variant) -> Option<&'static str> {
+        match self {
+            EngineFlavor::Official => None,
+            EngineFlavor::MultiVariant => VARIANT_EVAL_FILES.iter().copied().find(|name| {
+                name.strip_prefix(variant.uci())
+                    .is_some_and(|hash| hash.starts_with('-'))
+            }),
+        }
+    }
+
+    pub fn is_bundled(self) -> bool {
+        match self {
+            EngineFlavor::Official => true,
+            EngineFlavor::MultiVariant => !cfg!(feature = "official-only"),
+        }
+    }
+
+    /// Node count of bench, if it could be recorded when building the
+    /// bundled engine.
+    pub fn bench_signature(self) -> Option<u64> {
+        match self {
+            EngineFlavor::Official => option_env!("FISHNET_STOCKFISH_BENCH"),
+            EngineFlavor::MultiVariant => option_env!("FISHNET_FAIRY_STOCKFISH_BENCH"),
+        }
+        .and_then(|bench| bench.parse().ok())
+    }
+
+    pub fn eval_files(self) -> &'static [&'static str] {
+        match self {
+            EngineFlavor::Official => &[env!("FISHNET_EVAL_FILE"), env!("FISHNET_EVAL_FILE_SMALL")],
+            EngineFlavor::MultiVariant => &VARIANT_EVAL_FILES,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -205,56 +255,205 @@ pub struct Stockfish {
     pub path: PathBuf,
 }
 
+impl Stockfish {
+    /// Architecture the engine was built for, for example `x86-64-avx2`.
+    pub fn arch(&self) -> &str {
+        let name = self.name.strip_suffix(".exe").unwrap_or(&self.name);
+        name.strip_prefix("fairy-stockfish-")
+            .or_else(|| name.strip_prefix("stockfish-"))
+            .unwrap_or(name)
+    }
+}
+
 #[derive(Debug)]
 pub struct Assets {
-    pub stockfish: ByEngineFlavor<Stockfish>,
-    _dir: TempDir, // Will be deleted when dropped
+    pub stockfish: ByEngineFlavor<Option<Stockfish>>,
+    _dir: Option<TempDir>, // Will be deleted when dropped
+}
+
+/// Marks temporary directories created by fishnet. Contains the id of the
+/// process that uses the directory.
+pub const TEMP_DIR_MARKER: &str = "fishnet.pid";
+
+/// Creates a marked temporary directory for extracted assets.
+pub fn temp_dir() -> io::Result<TempDir> {
+    let dir = tempfile::Builder::new().prefix("fishnet-").tempdir()?;
+    fs::write(
+        dir.path().join(TEMP_DIR_MARKER),
+        format!("{}\n", process::id()),
+    )?;
+    Ok(dir)
 }
 
 impl Assets {
     pub fn prepare(cpu: Cpu) -> io::Result<Assets> {
-        let mut stockfish = ByEngineFlavor::<Option<Stockfish>>::default();
-        let dir = tempfile::Builder::new().prefix("fishnet-").tempdir()?;
+        let dir = temp_dir()?;
+        let mut assets = Assets::extract(cpu, dir.path(), false)?;
+        assets._dir = Some(dir);
+        Ok(assets)
+    }
 
-        let mut archive = Archive::new(ZstdDecoder::new(ASSETS_AR_ZST)?);
-        while let Some(entry) = archive.next_entry() {
-            let mut entry = entry?;
-            let filename = str::from_utf8(entry.header().identifier()).expect("utf-8 filename");
-            let target_path = dir.path().join(filename); // Trusted
-            if filename.starts_with("stockfish-") {
-                if stockfish.official.is_none() && cpu.contains(Cpu::requirements(filename)) {
-                    stockfish.official = Some(Stockfish {
-                        name: filename.to_owned(),
-                        path: target_path.clone(),
-                    });
-                } else {
+    /// Extracts to the given directory, which is not deleted afterwards.
+    /// Files left over from a previous run are reused.
+    pub fn prepare_in(cpu: Cpu, dir: &Path) -> io::Result<Assets> {
+        Assets::extract(cpu, dir, true)
+    }
+
+    fn extract(cpu: Cpu, dir: &Path, reuse: bool) -> io::Result<Assets> {
+        let mut selection = Selection::new(cpu);
+
+        thread::scope(|scope| {
+            let mut writers = Vec::new();
+
+            let mut archive = Archive::new(ZstdDecoder::new(ASSETS_AR_ZST)?);
+            while let Some(entry) = archive.next_entry() {
+                let mut entry = entry?;
+                let filename = str::from_utf8(entry.header().identifier()).expect("utf-8 filename");
+                if !selection.select(filename) {
+                    // Skipped without copying. The archive reader discards
+                    // the remaining payload when advancing.
                     continue;
                 }
-            }
-            if filename.starts_with("fairy-stockfish-") {
-                if stockfish.multi_variant.is_none() && cpu.contains(Cpu::requirements(filename)) {
-                    stockfish.multi_variant = Some(Stockfish {
-                        name: filename.to_owned(),
-                        path: target_path.clone(),
-                    });
-                } else {
-                    continue;
+                let target_path = dir.join(filename); // Trusted
+
+                if reuse {
+                    match fs::metadata(&target_path) {
+                        Ok(meta) if meta.len() == entry.header().size() => continue,
+                        Ok(_) => fs::remove_file(&target_path)?, // Incomplete
+                        Err(_) => (),
+                    }
                 }
+
+                // Decode the entry into memory and hand it off, so that
+                // writing to disk overlaps with decoding the next entries.
+                let mode = entry.header().mode();
+                let mut buf = Vec::with_capacity(entry.header().size() as usize);
+                entry.read_to_end(&mut buf)?;
+                writers.push(scope.spawn(move || create_file(&target_path, mode)?.write_all(&buf)));
+            }
+
+            for writer in writers {
+                writer.join().expect("join asset writer")?;
             }
-            let mode = entry.header().mode();
-            io::copy(&mut entry, &mut create_file(&target_path, mode)?)?;
+            Ok::<_, io::Error>(())
+        })?;
+
+        let stockfish = ByEngineFlavor {
+            official: selection.stockfish.official.map(|name| Stockfish {
+                path: dir.join(&name),
+                name,
+            }),
+            multi_variant: selection.stockfish.multi_variant.map(|name| Stockfish {
+                path: dir.join(&name),
+                name,
+            }),
+        };
+
+        if stockfish.official.is_none()
+            || (stockfish.multi_variant.is_none() && EngineFlavor::MultiVariant.is_bundled())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("no compatible Stockfish for CPU features {cpu}"),
+            ));
         }
 
         Ok(Assets {
-            stockfish: ByEngineFlavor {
-                official: stockfish.official.expect("compatible stockfish"),
-                multi_variant: stockfish
-                    .multi_variant
-                    .expect("compatible multi-variant stockfish"),
-            },
-            _dir: dir,
+            stockfish,
+            _dir: None,
         })
     }
+
+    /// Lists the embedded files without extracting them, and whether they
+    /// would be selected for the given CPU.
+    pub fn list(cpu: Cpu) -> io::Result<Vec<AssetEntry>> {
+        let mut selection = Selection::new(cpu);
+        let mut entries = Vec::new();
+        let mut archive = Archive::new(ZstdDecoder::new(ASSETS_AR_ZST)?);
+        while let Some(entry) = archive.next_entry() {
+            let entry = entry?;
+            let name = str::from_utf8(entry.header().identifier()).expect("utf-8 filename");
+            entries.push(AssetEntry {
+                name: name.to_owned(),
+                size: entry.header().size(),
+                requirements: Cpu::requirements(name),
+                selected: selection.select(name),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Extracts the files selected for the given CPU (or all files) to the
+    /// given directory, for use outside of fishnet. Existing files are not
+    /// overwritten. Returns the names of the extracted files.
+    pub fn export(cpu: Cpu, dir: &Path, all: bool) -> io::Result<Vec<String>> {
+        fs::create_dir_all(dir)?;
+        let mut selection = Selection::new(cpu);
+        let mut exported = Vec::new();
+        let mut archive = Archive::new(ZstdDecoder::new(ASSETS_AR_ZST)?);
+        while let Some(entry) = archive.next_entry() {
+            let mut entry = entry?;
+            let name = str::from_utf8(entry.header().identifier()).expect("utf-8 filename");
+            if !selection.select(name) && !all {
+                continue;
+            }
+            let name = name.to_owned();
+            let mut file = create_file(&dir.join(&name), entry.header().mode())?;
+            io::copy(&mut entry, &mut file)?;
+            exported.push(name);
+        }
+        Ok(exported)
+    }
+}
+
+#[derive(Debug)]
+pub struct AssetEntry {
+    pub name: String,
+    pub size: u64,
+    pub requirements: Cpu,
+    pub selected: bool,
+}
+
+/// Decides which files to extract, given the archive order: engines from
+/// most to least demanding, followed by eval files.
+struct Selection {
+    cpu: Cpu,
+    stockfish: ByEngineFlavor<Option<String>>,
+}
+
+impl Selection {
+    fn new(cpu: Cpu) -> Selection {
+        Selection {
+            cpu,
+            stockfish: ByEngineFlavor::default(),
+        }
+    }
+
+    fn select(&mut self, filename: &str) -> bool {
+        let flavor = if filename.starts_with("stockfish-") {
+            Some(EngineFlavor::Official)
+        } else if filename.starts_with("fairy-stockfish-") {
+            Some(EngineFlavor::MultiVariant)
+        } else {
+            None
+        };
+        if let Some(flavor) = flavor {
+            let selected = self.stockfish.get_mut(flavor);
+            if selected.is_none() && self.cpu.contains(Cpu::requirements(filename)) {
+                *selected = Some(filename.to_owned());
+                return true;
+            }
+            return false;
+        }
+
+        // Eval files must be referenced by a selected engine. They are
+        // archived after the engines, so the selection is final.
+        !filename.ends_with(".nnue")
+            || [EngineFlavor::Official, EngineFlavor::MultiVariant]
+                .into_iter()
+                .filter(|&flavor| self.stockfish.get(flavor).is_some())
+                .any(|flavor| flavor.eval_files().contains(&filename))
+    }
 }
 
 #[cfg(unix)]
@@ -280,4 +479,30 @@ mod tests {
     fn test_prepare_assets() {
         Assets::prepare(Cpu::detect()).expect("assets");
     }
+
+    #[test]
+    fn test_prepare_assets_extracts_only_required_files() {
+        let assets = Assets::prepare(Cpu::detect()).expect("assets");
+        let mut extracted = std::fs::read_dir(assets._dir.as_ref().expect("temporary dir").path())
+            .expect("read asset dir")
+            .map(|entry| entry.expect("dir entry").file_name().into_string().unwrap())
+            .collect::<Vec<String>>();
+        extracted.sort();
+
+        let mut expected = [EngineFlavor::Official, EngineFlavor::MultiVariant]
+            .into_iter()
+            .filter_map(|flavor| assets.stockfish.get(flavor).as_ref())
+            .map(|stockfish| stockfish.name.clone())
+            .collect::<Vec<_>>();
+        expected.extend(
+            [EngineFlavor::Official, EngineFlavor::MultiVariant]
+                .into_iter()
+                .filter(|&flavor| assets.stockfish.get(flavor).is_some())
+                .flat_map(|flavor| flavor.eval_files())
+                .map(|&name| name.to_owned()),
+        );
+        expected.sort();
+
+        assert_eq!(extracted, expected);
+    }
 }
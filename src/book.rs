@@ -0,0 +1,154 @@
+use std::{fs, io, path::Path};
+
+use fastrand::Rng;
+use shakmaty::{
+    CastlingMode, Chess, EnPassantMode, File, Position as _, PositionError, Rank, Role, Square,
+    uci::UciMove,
+    variant::Variant,
+    zobrist::{Zobrist64, ZobristHash as _},
+};
+
+use crate::{
+    api::{SkillLevel, Work},
+    ipc::{Chunk, Matrix, PositionResponse},
+};
+
+#[derive(Debug, Copy, Clone)]
+struct BookEntry {
+    key: u64,
+    raw_move: u16,
+    weight: u16,
+}
+
+/// Opening book in PolyGlot format, used to answer move requests for the
+/// lowest levels without starting a search.
+pub struct PolyglotBook {
+    entries: Vec<BookEntry>,
+}
+
+impl PolyglotBook {
+    /// Highest level for which book moves are played. Stronger levels
+    /// should find good moves on their own.
+    const MAX_LEVEL: u32 = SkillLevel::Three as u32;
+
+    pub fn open(path: &Path) -> io::Result<PolyglotBook> {
+        let data = fs::read(path)?;
+        if data.len() % 16 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "polyglot book size is not a multiple of 16 bytes",
+            ));
+        }
+        let mut entries: Vec<BookEntry> = data
+            .chunks_exact(16)
+            .map(|entry| BookEntry {
+                key: u64::from_be_bytes(entry[0..8].try_into().expect("8 bytes")),
+                raw_move: u16::from_be_bytes(entry[8..10].try_into().expect("2 bytes")),
+                weight: u16::from_be_bytes(entry[10..12].try_into().expect("2 bytes")),
+            })
+            .collect();
+        // Should already be sorted, but do not rely on it.
+        entries.sort_by_key(|entry| entry.key);
+        Ok(PolyglotBook { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Answers a move request with a book move, if applicable.
+    pub fn answer(&self, chunk: &Chunk) -> Option<PositionResponse> {
+        let Work::Move { level, .. } = chunk.work else {
+            return None;
+        };
+        if level as u32 > PolyglotBook::MAX_LEVEL || chunk.variant != Variant::Chess {
+            return None;
+        }
+        let [position] = &chunk.positions[..] else {
+            return None;
+        };
+
+        let mut pos: Chess = position
+            .root_fen
+            .clone()
+            .into_position(CastlingMode::Chess960)
+            .or_else(PositionError::ignore_invalid_ep_square)
+            .or_else(PositionError::ignore_invalid_castling_rights)
+            .ok()?;
+        for uci in position.moves.iter() {
+            let m = uci.to_move(&pos).ok()?;
+            pos.play_unchecked(m);
+        }
+
+        let best_move = self.pick(&pos, &mut Rng::new())?;
+        Some(PositionResponse {
+            work: position.work.clone(),
+            position_index: position.position_index,
+            url: position.url.clone(),
+            scores: Matrix::new(),
+            pvs: Matrix::new(),
+            best_move: Some(best_move),
+            depth: 0,
+            nodes: 0,
+            time: Default::default(),
+            nps: None,
+            stopped_early: false,
+            cached: false,
+        })
+    }
+
+    /// Picks a random book move, weighted as specified in the book.
+    fn pick(&self, pos: &Chess, rng: &mut Rng) -> Option<UciMove> {
+        let key = pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0;
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let candidates: Vec<(UciMove, u32)> = self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter(|entry| entry.weight > 0)
+            .filter_map(|entry| {
+                // Castling is encoded as king to rook, just like UCI in
+                // Chess960 mode.
+                let uci = decode_move(entry.raw_move);
+                uci.to_move(pos).ok()?;
+                Some((uci, u32::from(entry.weight)))
+            })
+            .collect();
+
+        let total: u32 = candidates.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut choice = rng.u32(0..total);
+        for (uci, weight) in candidates {
+            if choice < weight {
+                return Some(uci);
+            }
+            choice -= weight;
+        }
+        None
+    }
+}
+
+fn decode_move(raw: u16) -> UciMove {
+    let square = |bits: u16| {
+        Square::from_coords(
+            File::new(u32::from(bits & 7)),
+            Rank::new(u32::from((bits >> 3) & 7)),
+        )
+    };
+    UciMove::Normal {
+        from: square(raw >> 6),
+        to: square(raw),
+        promotion: match (raw >> 12) & 7 {
+            1 => Some(Role::Knight),
+            2 => Some(Role::Bishop),
+            3 => Some(Role::Rook),
+            4 => Some(Role::Queen),
+            _ => None,
+        },
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    io::{BufReader, BufWriter},
+    mem,
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{DurationMilliSeconds, serde_as};
+use shakmaty::{
+    CastlingMode, EnPassantMode, Position as _, PositionError, fen::Fen, uci::UciMove,
+    variant::VariantPosition,
+};
+
+use crate::{
+    api::{PositionIndex, Score, Work},
+    assets::EngineFlavor,
+    ipc::{Chunk, Matrix, Position, PositionResponse},
+};
+
+/// Identifies an analysis request, independent of the batch it came from.
+/// Includes the engine and network, so that results of previous versions
+/// are not reused after an update, and the halfmove clock, which affects
+/// the evaluation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CacheKey {
+    #[serde(default)]
+    engine: String,
+    #[serde(default)]
+    net: String,
+    variant: String,
+    /// Entries of older versions were keyed by EPD, and never match.
+    #[serde(alias = "epd")]
+    fen: String,
+    nodes: u64,
+    depth: Option<u8>,
+    multipv: u8,
+}
+
+impl CacheKey {
+    fn new(
+        chunk: &Chunk,
+        position: &Position,
+        node_multiplier: f64,
+        engine: &str,
+    ) -> Option<CacheKey> {
+        // Results of time-limited analysis depend on the hardware.
+        let Work::Analysis {
+            nodes,
+            depth,
+            movetime: None,
+            ..
+        } = position.work
+        else {
+            return None;
+        };
+        let mut pos = VariantPosition::from_setup(
+            chunk.variant,
+            position.root_fen.clone().into_setup(),
+            CastlingMode::Chess960,
+        )
+        .or_else(PositionError::ignore_invalid_ep_square)
+        .or_else(PositionError::ignore_invalid_castling_rights)
+        .or_else(PositionError::ignore_too_much_material)
+        .ok()?;
+        for uci in position.moves.iter() {
+            let m = uci.to_move(&pos).ok()?;
+            pos.play_unchecked(m);
+        }
+        // The engine also sees the moves of the game, so results may depend
+        // on a repetition, as soon as one is possible.
+        if pos.halfmoves() >= 4 {
+            return None;
+        }
+        Some(CacheKey {
+            engine: engine.to_owned(),
+            net: match chunk.flavor {
+                EngineFlavor::Official => chunk.flavor.eval_files().join(","),
+                EngineFlavor::MultiVariant => chunk
+                    .flavor
+                    .variant_eval_file(chunk.variant)
+                    .unwrap_or("classical")
+                    .to_owned(),
+            },
+            variant: chunk.variant.uci().to_owned(),
+            fen: Fen::from_position(&pos, EnPassantMode::Legal).to_string(),
+            nodes: nodes
+                .scaled(node_multiplier)
+                .get(chunk.flavor.eval_flavor(chunk.variant)),
+            depth,
+            multipv: position.work.multipv().get(),
+        })
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedAnalysis {
+    scores: Matrix<Score>,
+    pvs: Matrix<Vec<UciMove>>,
+    best_move: Option<UciMove>,
+    depth: u8,
+    nodes: u64,
+    #[serde_as(as = "DurationMilliSeconds")]
+    time: Duration,
+    nps: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    key: CacheKey,
+    analysis: CachedAnalysis,
+}
+
+/// Least recently used cache of analysis results, so that common positions
+/// (most notably from openings) do not have to be analysed over and over
+/// again.
+pub struct PositionCache {
+    path: PathBuf,
+    capacity: usize,
+    clock: u64,
+    entries: HashMap<CacheKey, (u64, CachedAnalysis)>,
+}
+
+impl PositionCache {
+    /// Opens the cache file, or starts with an empty cache if it does not
+    /// exist yet.
+    pub fn open(path: PathBuf, capacity: usize) -> io::Result<PositionCache> {
+        let mut cache = PositionCache {
+            path,
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        };
+        let entries: Vec<Entry> = match File::open(&cache.path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        // Stored from least to most recently used.
+        for entry in entries {
+            cache.insert(entry.key, entry.analysis);
+        }
+        Ok(cache)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes positions with cached results from the chunk, and answers
+    /// them immediately. Returns the cached responses and the keys for the
+    /// remaining positions. The cache is not used until the engine has
+    /// identified itself (`id name`).
+    pub fn split(
+        &mut self,
+        chunk: &mut Chunk,
+        node_multiplier: f64,
+        engine: Option<&str>,
+    ) -> (Vec<PositionResponse>, Vec<(PositionIndex, CacheKey)>) {
+        let Some(engine) = engine else {
+            return (Vec::new(), Vec::new());
+        };
+        let mut hits = Vec::new();
+        let mut misses = Vec::new();
+        let mut remaining = Vec::with_capacity(chunk.positions.len());
+        for position in mem::take(&mut chunk.positions) {
+            let key = position.position_index.and_then(|position_index| {
+                CacheKey::new(chunk, &position, node_multiplier, engine)
+                    .map(|key| (position_index, key))
+            });
+            match key {
+                Some((position_index, key)) => match self.get(&key) {
+                    Some(analysis) => hits.push(PositionResponse {
+                        work: position.work,
+                        position_index: position.position_index,
+                        url: position.url,
+                        scores: analysis.scores,
+                        pvs: analysis.pvs,
+                        best_move: analysis.best_move,
+                        depth: analysis.depth,
+                        nodes: analysis.nodes,
+                        time: analysis.time,
+                        nps: analysis.nps,
+                        stopped_early: false,
+                        cached: true,
+                    }),
+                    None => {
+                        misses.push((position_index, key));
+                        remaining.push(position);
+                    }
+                },
+                None => remaining.push(position),
+            }
+        }
+
+        // Positions without index only provide context for the following
+        // position. Drop them if that one was answered from the cache.
+        let mut positions = Vec::with_capacity(remaining.len());
+        let mut remaining = remaining.into_iter().peekable();
+        while let Some(position) = remaining.next() {
+            if position.position_index.is_some()
+                || remaining
+                    .peek()
+                    .is_some_and(|next| next.position_index.is_some())
+            {
+                positions.push(position);
+            }
+        }
+        chunk.positions = positions;
+
+        (hits, misses)
+    }
+
+    /// Remembers the analysis results for the given keys, as returned by
+    /// [`PositionCache::split()`].
+    pub fn remember(
+        &mut self,
+        keys: Vec<(PositionIndex, CacheKey)>,
+        responses: &[PositionResponse],
+    ) {
+        for (position_index, key) in keys {
+            let Some(res) = responses
+                .iter()
+                .find(|res| res.position_index == Some(position_index))
+            else {
+                continue;
+            };
+            if res.scores.best().is_some() {
+                self.insert(
+                    key,
+                    CachedAnalysis {
+                        scores: res.scores.clone(),
+                        pvs: res.pvs.clone(),
+                        best_move: res.best_move,
+                        depth: res.depth,
+                        nodes: res.nodes,
+                        time: res.time,
+                        nps: res.nps,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(key, (used, analysis))| (*used, EntryRef { key, analysis }))
+            .collect();
+        entries.sort_by_key(|(used, _)| *used);
+        let entries: Vec<_> = entries.into_iter().map(|(_, entry)| entry).collect();
+
+        // Write atomically, so that a crash does not lose the entire cache.
+        let tmp_path = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &entries)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(tmp_path, &self.path)
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<CachedAnalysis> {
+        self.clock += 1;
+        let (used, analysis) = self.entries.get_mut(key)?;
+        *used = self.clock;
+        Some(analysis.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, analysis: CachedAnalysis) {
+        self.clock += 1;
+        self.entries.insert(key, (self.clock, analysis));
+        if self.entries.len() > self.capacity {
+            // Evict in bulk, to amortize the cost of finding the least
+            // recently used entries.
+            let mut used: Vec<u64> = self.entries.values().map(|(used, _)| *used).collect();
+            let evict = self.entries.len() - self.capacity * 9 / 10;
+            let (_, &mut threshold, _) = used.select_nth_unstable(evict - 1);
+            self.entries.retain(|_, (used, _)| *used > threshold);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    key: &'a CacheKey,
+    analysis: &'a CachedAnalysis,
+}
@@ -0,0 +1,199 @@
+use std::{
+    cmp::min,
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    num::{NonZeroU8, NonZeroUsize},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use shakmaty::{fen::Fen, uci::UciMove, variant::Variant};
+use tokio::{task::JoinSet, time::sleep_until};
+
+use crate::{
+    api::{AcquireResponseBody, NodeLimit, PositionIndex, Work},
+    assets::Assets,
+    ipc::{Chunk, PositionResponse},
+    logger::Logger,
+    queue::{IncomingBatch, IncomingError},
+    stockfish,
+    stockfish::EngineOptions,
+};
+
+/// A game (or line) to analyse locally, without talking to a fishnet server.
+#[derive(Debug, Clone)]
+pub struct AnalysisRequest {
+    pub variant: Variant,
+    pub position: Fen,
+    pub moves: Vec<UciMove>,
+    pub nodes: NodeLimit,
+    pub depth: Option<u8>,
+    pub multipv: Option<NonZeroU8>,
+    pub movetime: Option<Duration>,
+    pub timeout_per_ply: Duration,
+}
+
+impl AnalysisRequest {
+    pub fn new(variant: Variant, position: Fen, moves: Vec<UciMove>) -> AnalysisRequest {
+        AnalysisRequest {
+            variant,
+            position,
+            moves,
+            nodes: NodeLimit::new(4_050_000, 1_500_000),
+            depth: None,
+            multipv: None,
+            movetime: None,
+            timeout_per_ply: Duration::from_secs(7),
+        }
+    }
+}
+
+/// Analyses positions with the bundled engines, using the same chunking as
+/// work acquired from a fishnet server.
+pub struct AnalysisClient {
+    assets: Arc<Assets>,
+    cores: NonZeroUsize,
+    logger: Logger,
+}
+
+impl AnalysisClient {
+    pub fn new(assets: Assets, cores: NonZeroUsize, logger: Logger) -> AnalysisClient {
+        AnalysisClient {
+            assets: Arc::new(assets),
+            cores,
+            logger,
+        }
+    }
+
+    /// Analyses the root position and the position after each move. The
+    /// result is indexed by ply.
+    pub async fn analyse(
+        &self,
+        request: AnalysisRequest,
+    ) -> Result<Vec<PositionResponse>, AnalysisError> {
+        let num_positions = request.moves.len() + 1;
+        let body = AcquireResponseBody {
+            work: Work::Analysis {
+                id: "local".parse().expect("valid batch id"),
+                nodes: request.nodes,
+                depth: request.depth,
+                multipv: request.multipv,
+                movetime: request.movetime,
+                timeout: request.timeout_per_ply,
+            },
+            game_id: None,
+            position: request.position,
+            variant: request.variant.into(),
+            moves: request.moves,
+            skip_positions: Vec::new(),
+        };
+        let chunks = IncomingBatch::from_acquired(body, None)?.into_chunks();
+
+        let mut positions = Vec::with_capacity(num_positions);
+        positions.resize_with(num_positions, || None);
+        for res in self.analyse_chunks(chunks).await? {
+            if let Some(PositionIndex(i)) = res.position_index {
+                positions[i] = Some(res);
+            }
+        }
+
+        Ok(positions
+            .into_iter()
+            .map(|pos| pos.expect("all positions analysed"))
+            .collect())
+    }
+
+    /// Analyses prepared chunks, in parallel on up to the configured number
+    /// of engines.
+    pub(crate) async fn analyse_chunks(
+        &self,
+        chunks: Vec<Chunk>,
+    ) -> Result<Vec<PositionResponse>, AnalysisError> {
+        let num_engines = min(self.cores.get(), chunks.len());
+        let chunks = Arc::new(Mutex::new(VecDeque::from(chunks)));
+        let mut join_set = JoinSet::new();
+        for _ in 0..num_engines {
+            join_set.spawn(engine_worker(
+                self.assets.clone(),
+                chunks.clone(),
+                self.logger.clone(),
+            ));
+        }
+
+        let mut responses = Vec::new();
+        while let Some(res) = join_set.join_next().await {
+            responses.extend(res.expect("join")?);
+        }
+        Ok(responses)
+    }
+}
+
+async fn engine_worker(
+    assets: Arc<Assets>,
+    chunks: Arc<Mutex<VecDeque<Chunk>>>,
+    logger: Logger,
+) -> Result<Vec<PositionResponse>, AnalysisError> {
+    let mut engine = None;
+    let mut responses = Vec::new();
+
+    let res = loop {
+        let Some(chunk) = chunks.lock().expect("chunks").pop_front() else {
+            break Ok(responses);
+        };
+
+        let (sf, _) = engine.get_or_insert_with(|| {
+            let exe = assets
+                .stockfish
+                .get(chunk.flavor)
+                .as_ref()
+                .expect("flavor of incoming batch is bundled")
+                .path
+                .clone();
+            let (sf, sf_actor) = stockfish::channel(exe, EngineOptions::default(), logger.clone());
+            (sf, tokio::spawn(sf_actor.run()))
+        });
+
+        let deadline = chunk.deadline;
+        tokio::select! {
+            _ = sleep_until(deadline) => break Err(AnalysisError::Timeout),
+            res = sf.go_multiple(chunk) => match res {
+                Ok(res) => responses.extend(res),
+                Err(_) => break Err(AnalysisError::EngineFailed),
+            }
+        }
+    };
+
+    if let Some((sf, join_handle)) = engine {
+        drop(sf);
+        join_handle.await.expect("join");
+    }
+
+    res
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum AnalysisError {
+    Invalid(IncomingError),
+    EngineFailed,
+    Timeout,
+}
+
+impl Error for AnalysisError {}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisError::Invalid(err) => err.fmt(f),
+            AnalysisError::EngineFailed => f.write_str("engine failed"),
+            AnalysisError::Timeout => f.write_str("engine timed out"),
+        }
+    }
+}
+
+impl From<IncomingError> for AnalysisError {
+    fn from(err: IncomingError) -> AnalysisError {
+        AnalysisError::Invalid(err)
+    }
+}
@@ -1,20 +1,34 @@
 use std::{
+    cmp::{max, min},
     error::Error,
+    ffi::OsString,
     fmt, fs, io,
     io::Write,
-    num::{NonZeroUsize, ParseIntError},
+    iter,
+    num::{NonZeroU8, NonZeroU32, NonZeroU64, NonZeroUsize, ParseIntError},
     path::{Path, PathBuf},
     str::FromStr,
     thread::available_parallelism,
     time::Duration,
 };
 
-use clap::{ArgAction, Parser, ValueEnum, builder::PathBufValueParser};
+use clap::{
+    ArgAction, CommandFactory as _, Parser, ValueEnum, builder::PathBufValueParser,
+    error::ErrorKind,
+};
 use configparser::ini::Ini;
 use reqwest::Client;
+use shakmaty::variant::Variant;
 use url::Url;
 
-use crate::{api, logger::Logger};
+use crate::{
+    api,
+    assets::{Assets, Cpu, EngineFlavor},
+    container::Container,
+    control, keycheck,
+    logger::Logger,
+    stockfish,
+};
 
 /// Distributed Stockfish analysis for lichess.org.
 #[derive(Debug, Parser)]
@@ -25,9 +39,36 @@ pub struct Opt {
 
     /// Automatically install available updates on startup and at random
     /// intervals.
-    #[arg(long, global = true)]
+    #[arg(long, global = true, hide = cfg!(not(feature = "auto-update")))]
     pub auto_update: bool,
 
+    /// Also write warnings and errors to the Windows Event Log, for example
+    /// when running as a service.
+    #[arg(long, global = true, hide = cfg!(not(windows)))]
+    pub event_log: bool,
+
+    /// Log TLS session keys to the file given by SSLKEYLOGFILE, to inspect
+    /// traffic while debugging. This allows decrypting the API key.
+    #[arg(long, global = true)]
+    pub tls_keylog: bool,
+
+    /// Talk to the endpoint via HTTP/3 (QUIC), which can reduce latency
+    /// on lossy connections. The endpoint must support HTTP/3.
+    #[arg(long, global = true, hide = cfg!(not(feature = "http3")))]
+    pub http3: bool,
+
+    /// Give up connecting to the endpoint after this long, for example 10s.
+    /// Split between the resolved addresses. No limit by default, other
+    /// than the 30s request timeout. IPv4 is already tried in parallel if
+    /// IPv6 does not connect within 300ms.
+    #[arg(long, value_name = "DURATION", global = true)]
+    pub connect_timeout: Option<ConnectTimeout>,
+
+    /// Keep fishnet running in a child process, restarting it with backoff
+    /// after crashes. For systems without a service manager.
+    #[arg(long, global = true)]
+    pub supervise: bool,
+
     /// Configuration file. Defaults to fishnet.ini in the current working
     /// directory.
     #[arg(long, value_parser = PathBufValueParser::new(), global = true)]
@@ -45,15 +86,63 @@ pub struct Opt {
     #[arg(long, value_parser = PathBufValueParser::new(), conflicts_with = "key", global = true)]
     pub key_file: Option<PathBuf>,
 
+    /// Team to attribute contributions to, so that many machines with
+    /// separate keys can be counted together.
+    #[arg(long, alias = "team", value_name = "TAG", global = true)]
+    pub contributor_tag: Option<ContributorTag>,
+
     /// Lichess HTTP endpoint. Defaults to https://lichess.org/fishnet.
+    /// Can be given multiple times, to fail over to the next endpoint while
+    /// the previous one is unreachable or rejects requests.
     #[arg(long, global = true)]
-    pub endpoint: Option<Endpoint>,
+    pub endpoint: Vec<Endpoint>,
+
+    /// Fail over to the next --endpoint after requests to the current one
+    /// failed for this long, and try to return to the first one at the
+    /// same interval. Defaults to 5m.
+    #[arg(long, value_name = "DURATION", global = true)]
+    pub failover_after: Option<FailoverAfter>,
+
+    /// Append to the User-Agent header, to tell apart multiple clients using
+    /// the same key, for example myfarm-node-7.
+    #[arg(long, value_name = "SUFFIX", global = true)]
+    pub user_agent_suffix: Option<UserAgentSuffix>,
+
+    /// Allow insecure connections to the endpoint, for testing with a local
+    /// lila instance. Plain http:// endpoints are rejected otherwise.
+    #[arg(long, global = true)]
+    pub development: bool,
+
+    /// Do not verify TLS certificates of the endpoint. Conflicts with
+    /// --auto-update, so that updates are never downloaded without
+    /// verification.
+    #[arg(
+        long,
+        requires = "development",
+        conflicts_with = "auto_update",
+        global = true
+    )]
+    pub insecure_skip_tls_verify: bool,
 
     /// Number of logical CPU cores to use for engine processes
     /// (or auto for n - 1, or all for n).
     #[arg(long, alias = "threads", global = true)]
     pub cores: Option<Cores>,
 
+    #[command(flatten)]
+    pub topology: TopologyOpt,
+
+    /// Keep only the given number of cores busy while the server has little
+    /// queued analysis, and scale up to --cores as the queue grows.
+    #[arg(long, value_name = "N", global = true)]
+    pub min_cores: Option<NonZeroUsize>,
+
+    /// Select the bundled engines built for the given architecture (for
+    /// example x86-64-avx2), instead of detecting CPU features. For
+    /// debugging, or when running under emulators that misreport features.
+    #[arg(long, value_name = "ARCH", global = true)]
+    pub cpu_features: Option<CpuFeatures>,
+
     /// Override CPU scheduling priorty of fishnet and engine processes.
     /// Very low by default.
     #[arg(long, global = true)]
@@ -64,6 +153,164 @@ pub struct Opt {
     #[arg(long, global = true)]
     pub max_backoff: Option<MaxBackoff>,
 
+    /// Seed the randomized backoff, to reproduce the same sequence of
+    /// waiting times, for example when debugging or testing.
+    #[arg(long, value_name = "SEED", env = "FISHNET_BACKOFF_SEED", global = true)]
+    pub backoff_seed: Option<u64>,
+
+    /// Throttle analysis submissions to the given number of bytes per
+    /// second (for example 200k or 1M), so that uploading large results
+    /// does not saturate a slow uplink.
+    #[arg(long, value_name = "BYTES", global = true)]
+    pub max_upload_rate: Option<UploadRate>,
+
+    /// Write newline-delimited JSON progress events (batch acquired,
+    /// position done, batch completed, idle) to the given file or FIFO,
+    /// or to stdout if `-`. Human readable logs move to stderr in the
+    /// latter case.
+    #[arg(long, value_parser = PathBufValueParser::new(), value_name = "FILE", global = true)]
+    pub progress_json: Option<PathBuf>,
+
+    /// When stdout is not a terminal, print a condensed progress line at
+    /// most this often (for example 30s), for log files. Without this,
+    /// progress is only logged with --verbose.
+    #[arg(long, value_name = "DURATION", global = true)]
+    pub progress_interval: Option<ProgressInterval>,
+
+    /// Control socket for `fishnet status`. Defaults to fishnet.sock in
+    /// $XDG_RUNTIME_DIR, or in a directory private to the user in the
+    /// temporary directory.
+    #[arg(long, value_parser = PathBufValueParser::new(), global = true)]
+    pub control_socket: Option<PathBuf>,
+
+    /// Serve an HTTP API on 127.0.0.1 at this port, to query state, pause,
+    /// resume, change the number of active cores, and shut down.
+    #[arg(
+        long,
+        value_name = "PORT",
+        requires = "admin_token_file",
+        global = true
+    )]
+    pub admin_port: Option<u16>,
+
+    /// Bearer token for --admin-port. A random token is generated and
+    /// stored in the file if it does not exist.
+    #[arg(long, value_parser = PathBufValueParser::new(), value_name = "FILE", global = true)]
+    pub admin_token_file: Option<PathBuf>,
+
+    /// Re-analyse a random fraction of completed positions (for example
+    /// 0.01 or 1%) with half the nodes and warn about discrepancies, to
+    /// detect unstable hardware.
+    #[arg(long, value_name = "FRACTION", global = true)]
+    pub spot_check: Option<SpotCheckRate>,
+
+    /// Decline analysis requests with more than the given number of
+    /// principal variations, if they cannot be completed in time on this
+    /// hardware.
+    #[arg(long, value_name = "N", global = true)]
+    pub max_multipv: Option<NonZeroU8>,
+
+    /// Decline batches while more than the given number of chunks beyond
+    /// one per core are already queued, so that work goes to less busy
+    /// clients instead of missing its deadline.
+    #[arg(long, value_name = "N", global = true)]
+    pub max_queued_chunks: Option<usize>,
+
+    /// Stop acquiring batches while queued and pending analysis takes up
+    /// roughly more than the given number of bytes (for example 200M or
+    /// 1G). Long games with many principal variations can otherwise use a
+    /// lot of memory on small machines.
+    #[arg(long, value_name = "BYTES", global = true)]
+    pub max_pending_memory: Option<MemoryBudget>,
+
+    /// Report progress on unfinished analysis at least this often, so that
+    /// progress bars stay fresh and the server does not reassign
+    /// long-running batches. Defaults to 60s, 0 to disable.
+    #[arg(long, value_name = "DURATION", global = true)]
+    pub keep_alive: Option<KeepAlive>,
+
+    /// Do not abort pending batches on shutdown. Leave them to time out on
+    /// the server instead, for example when restarting within seconds.
+    #[arg(long, global = true)]
+    pub no_abort_on_shutdown: bool,
+
+    /// Restart each engine process after it analysed the given number of
+    /// chunks, to guard against degraded state in very long uptimes.
+    #[arg(long, value_name = "N", global = true)]
+    pub recycle_engine_chunks: Option<NonZeroU32>,
+
+    /// Restart each engine process after it has been running for the given
+    /// duration, for example 12h. Never interrupts a search.
+    #[arg(long, value_name = "DURATION", global = true)]
+    pub recycle_engine_age: Option<EngineAge>,
+
+    /// Multiply node limits for a variant, for example crazyhouse=0.5.
+    /// Can be given multiple times. Only applied with private endpoints.
+    #[arg(long = "node-multiplier", value_name = "VARIANT=FACTOR", global = true)]
+    pub node_multipliers: Vec<NodeMultiplier>,
+
+    /// Opening book in PolyGlot format, to answer move requests for the
+    /// lowest levels instantly.
+    #[arg(long, value_parser = PathBufValueParser::new(), value_name = "FILE", global = true)]
+    pub book: Option<PathBuf>,
+
+    /// Directories with Syzygy tablebases, separated like PATH. Move
+    /// requests are then played perfectly in covered endgames. Analysis is
+    /// not affected.
+    #[arg(long, value_name = "PATH", global = true)]
+    pub syzygy_path: Option<OsString>,
+
+    /// Remember analysis results in the given file, so that common
+    /// positions (for example from openings) are answered without analysing
+    /// them again. Saved every 10 minutes and on shutdown.
+    #[arg(long, value_parser = PathBufValueParser::new(), value_name = "FILE", global = true)]
+    pub position_cache: Option<PathBuf>,
+
+    /// Maximum number of positions to keep in the position cache. Defaults
+    /// to 100000.
+    #[arg(
+        long,
+        value_name = "POSITIONS",
+        requires = "position_cache",
+        global = true
+    )]
+    pub position_cache_size: Option<usize>,
+
+    /// Append each complete analysis (game, scores, principal variations
+    /// and engine) as compressed JSON to daily files in this directory,
+    /// after submitting it. Also writes an annotated PGN of each game.
+    #[arg(long, value_parser = PathBufValueParser::new(), value_name = "DIR", global = true)]
+    pub archive_dir: Option<PathBuf>,
+
+    /// Record the UCI dialogue with the engines, and save a transcript to
+    /// this directory whenever a chunk fails or times out. Helpful for
+    /// reporting engine bugs.
+    #[arg(long, value_parser = PathBufValueParser::new(), value_name = "DIR", global = true)]
+    pub record_uci: Option<PathBuf>,
+
+    /// Also send each complete analysis to file:PATH (appending a line of
+    /// JSON) or to an http(s):// URL (posting JSON). Can be given multiple
+    /// times. Results are always submitted to the endpoint.
+    #[arg(long = "result-sink", value_name = "SINK", global = true)]
+    pub result_sinks: Vec<ResultSinkSpec>,
+
+    /// Keep the engine hash table between consecutive chunks of the same
+    /// batch, instead of starting each chunk with an empty hash.
+    #[arg(long, global = true)]
+    pub keep_hash: bool,
+
+    /// Time that the engine reserves for communication delays when playing
+    /// games, for example 500ms. Increase on high-latency connections if
+    /// bots lose on time in fast time controls.
+    #[arg(long, value_name = "DURATION", global = true)]
+    pub move_overhead: Option<MoveOverhead>,
+
+    /// Report analysis progress to the server after each position, rather
+    /// than after each chunk of positions. Reports for the same batch are
+    /// still at least 2s apart.
+    #[arg(long, global = true)]
+    pub report_each_position: bool,
+
     #[command(flatten)]
     pub backlog: BacklogOpt,
 
@@ -75,8 +322,14 @@ pub struct Opt {
 }
 
 impl Opt {
+    /// Primary endpoint.
     pub fn endpoint(&self) -> Endpoint {
-        self.endpoint.clone().unwrap_or_default()
+        self.endpoint.first().cloned().unwrap_or_default()
+    }
+
+    /// Endpoints to fail over to, in order.
+    pub fn fallback_endpoints(&self) -> &[Endpoint] {
+        self.endpoint.get(1..).unwrap_or_default()
     }
 
     pub fn conf(&self) -> &Path {
@@ -85,6 +338,24 @@ impl Opt {
             None => Path::new("fishnet.ini"),
         }
     }
+
+    pub fn control_socket(&self) -> PathBuf {
+        self.control_socket
+            .clone()
+            .unwrap_or_else(control::default_socket_path)
+    }
+
+    pub fn progress_json_on_stdout(&self) -> bool {
+        self.progress_json.as_deref() == Some(Path::new("-"))
+    }
+
+    /// Stdout is reserved for machine readable output, so logs go to
+    /// stderr.
+    pub fn stdout_reserved(&self) -> bool {
+        self.progress_json_on_stdout()
+            || matches!(self.command, Some(Command::Replay { .. }))
+            || self.command.as_ref().is_some_and(Command::is_systemd)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -122,6 +393,20 @@ impl Endpoint {
     fn is_development(&self) -> bool {
         self.url.host_str() != Some("lichess.org")
     }
+
+    /// Page where donors can request a personal fishnet key.
+    fn key_page(&self) -> Url {
+        self.url.join("/get-fishnet").expect("key page url")
+    }
+
+    /// Host and port, to tell instances apart.
+    pub fn host(&self) -> String {
+        let host = self.url.host_str().unwrap_or_default();
+        match self.url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_owned(),
+        }
+    }
 }
 
 #[derive(Debug, Default, Copy, Clone, Parser)]
@@ -136,6 +421,9 @@ pub enum CpuPriority {
     Unchanged,
     #[default]
     Min,
+    /// Like min, but run engine processes only when the CPU is otherwise
+    /// idle.
+    Idle,
 }
 
 #[derive(Debug, Clone)]
@@ -174,6 +462,122 @@ impl FromStr for Key {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ContributorTag(pub String);
+
+#[derive(Debug)]
+pub struct InvalidContributorTag;
+
+impl fmt::Display for InvalidContributorTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected 1 to 32 alphanumeric characters, - or _")
+    }
+}
+
+impl Error for InvalidContributorTag {}
+
+impl FromStr for ContributorTag {
+    type Err = InvalidContributorTag;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if (1..=32).contains(&s.len())
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            Ok(ContributorTag(s.to_owned()))
+        } else {
+            Err(InvalidContributorTag)
+        }
+    }
+}
+
+impl fmt::Display for ContributorTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UserAgentSuffix(String);
+
+#[derive(Debug)]
+pub struct InvalidUserAgentSuffix;
+
+impl fmt::Display for InvalidUserAgentSuffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected non-empty printable ascii")
+    }
+}
+
+impl Error for InvalidUserAgentSuffix {}
+
+impl FromStr for UserAgentSuffix {
+    type Err = InvalidUserAgentSuffix;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+            Ok(UserAgentSuffix(s.to_owned()))
+        } else {
+            Err(InvalidUserAgentSuffix)
+        }
+    }
+}
+
+impl fmt::Display for UserAgentSuffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CpuFeatures(String);
+
+impl CpuFeatures {
+    /// Architectures of the bundled engines, as built by build.rs.
+    fn archs() -> impl Iterator<Item = &'static str> {
+        env!("FISHNET_ARCHS").split(',')
+    }
+
+    pub fn cpu(&self) -> Cpu {
+        Cpu::requirements(&format!("-{}", self.0))
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidCpuFeatures;
+
+impl fmt::Display for InvalidCpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected one of {}",
+            CpuFeatures::archs().collect::<Vec<_>>().join(", ")
+        )
+    }
+}
+
+impl Error for InvalidCpuFeatures {}
+
+impl FromStr for CpuFeatures {
+    type Err = InvalidCpuFeatures;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if CpuFeatures::archs().any(|arch| arch == s) {
+            Ok(CpuFeatures(s.to_owned()))
+        } else {
+            Err(InvalidCpuFeatures)
+        }
+    }
+}
+
+impl fmt::Display for CpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 #[derive(Debug, Default, Copy, Clone)]
 pub enum Cores {
     #[default]
@@ -207,15 +611,162 @@ impl fmt::Display for Cores {
 }
 
 impl Cores {
-    pub fn number(self) -> NonZeroUsize {
-        let num_cpus = available_parallelism().expect("num cpus");
+    pub fn number(self, topology_opt: TopologyOpt) -> NonZeroUsize {
+        let topology = Topology::detect();
+        let count = |performance_only: bool| {
+            let (logical, physical) = match topology.performance {
+                // Available parallelism respects CPU quotas, but the
+                // topology does not.
+                Some((logical, physical)) if performance_only => {
+                    (min(logical, topology.logical), physical)
+                }
+                _ => (topology.logical, topology.physical),
+            };
+            match physical {
+                Some(physical) if topology_opt.physical_cores_only => min(physical, logical),
+                _ => logical,
+            }
+        };
         match self {
             Cores::Number(n) => n,
-            Cores::Auto => NonZeroUsize::new(num_cpus.get() - 1)
+            // Leave the efficiency cores to other tasks, if any.
+            Cores::Auto if topology.performance.is_some() && !topology_opt.use_e_cores => {
+                count(true)
+            }
+            // The CPU quota of a container is already set aside for fishnet.
+            Cores::Auto if Container::detect().is_some() && Container::has_cpu_quota() => {
+                count(false)
+            }
+            Cores::Auto => NonZeroUsize::new(count(false).get() - 1)
                 .unwrap_or_else(|| NonZeroUsize::new(1).unwrap()),
-            Cores::All => num_cpus,
+            Cores::All => count(false),
+        }
+    }
+}
+
+/// Core counts, as far as they can be detected on this platform.
+struct Topology {
+    logical: NonZeroUsize,
+    physical: Option<NonZeroUsize>,
+    /// Logical and physical performance cores, if the CPU also has
+    /// efficiency cores.
+    performance: Option<(NonZeroUsize, Option<NonZeroUsize>)>,
+}
+
+impl Topology {
+    #[cfg(target_os = "linux")]
+    fn detect() -> Topology {
+        let online = fs::read_to_string("/sys/devices/system/cpu/online")
+            .ok()
+            .and_then(|list| parse_cpu_list(&list));
+        // Intel hybrid CPUs have separate PMUs for both kinds of cores.
+        let performance = fs::read_to_string("/sys/devices/cpu_core/cpus")
+            .ok()
+            .and_then(|list| parse_cpu_list(&list))
+            .filter(|_| {
+                fs::read_to_string("/sys/devices/cpu_atom/cpus")
+                    .ok()
+                    .and_then(|list| parse_cpu_list(&list))
+                    .is_some_and(|efficiency| !efficiency.is_empty())
+            });
+        Topology {
+            logical: available_parallelism().expect("num cpus"),
+            physical: online.as_deref().and_then(count_physical),
+            performance: performance
+                .and_then(|cpus| Some((NonZeroUsize::new(cpus.len())?, count_physical(&cpus)))),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn detect() -> Topology {
+        let count = |name| {
+            sysctl_u32(name)
+                .and_then(|n| usize::try_from(n).ok())
+                .and_then(NonZeroUsize::new)
+        };
+        // Apple Silicon reports performance levels from fastest to slowest.
+        let hybrid = sysctl_u32(c"hw.nperflevels").is_some_and(|levels| levels >= 2);
+        Topology {
+            logical: available_parallelism().expect("num cpus"),
+            physical: count(c"hw.physicalcpu"),
+            performance: count(c"hw.perflevel0.logicalcpu")
+                .filter(|_| hybrid)
+                .map(|logical| (logical, count(c"hw.perflevel0.physicalcpu"))),
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn detect() -> Topology {
+        Topology {
+            logical: available_parallelism().expect("num cpus"),
+            physical: None,
+            performance: None,
+        }
+    }
+}
+
+/// Parses a list of CPUs like `0-7,16,18-19`.
+#[cfg(target_os = "linux")]
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.parse().ok()?;
+                let last: usize = last.parse().ok()?;
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(range.parse().ok()?),
         }
     }
+    Some(cpus)
+}
+
+/// Counts the physical cores that the given logical CPUs belong to.
+#[cfg(target_os = "linux")]
+fn count_physical(cpus: &[usize]) -> Option<NonZeroUsize> {
+    let mut cores = std::collections::HashSet::new();
+    for cpu in cpus {
+        let siblings = fs::read_to_string(format!(
+            "/sys/devices/system/cpu/cpu{cpu}/topology/thread_siblings_list"
+        ))
+        .ok()?;
+        cores.insert(siblings.trim().to_owned());
+    }
+    NonZeroUsize::new(cores.len())
+}
+
+#[cfg(target_os = "macos")]
+#[allow(unsafe_code)]
+fn sysctl_u32(name: &std::ffi::CStr) -> Option<u32> {
+    let mut value: u32 = 0;
+    let mut size = size_of::<u32>();
+    let res = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr(),
+            &mut value as *mut u32 as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (res == 0 && size == size_of::<u32>()).then_some(value)
+}
+
+/// How to count cores for --cores auto and all.
+#[derive(Debug, Default, Copy, Clone, Parser)]
+pub struct TopologyOpt {
+    /// On CPUs with performance and efficiency cores (detected on Linux and
+    /// macOS), also count the efficiency cores for --cores auto. Mixed cores
+    /// make analysis speed inconsistent.
+    #[arg(long, global = true)]
+    pub use_e_cores: bool,
+
+    /// Count only physical cores for --cores auto and all, leaving
+    /// simultaneous multithreading (Hyper-Threading) siblings unused
+    /// (detected on Linux and macOS). Better nps per watt and per thread.
+    #[arg(long, global = true)]
+    pub physical_cores_only: bool,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -229,6 +780,11 @@ pub struct BacklogOpt {
     /// (for example 2h).
     #[arg(long = "system-backlog", global = true)]
     pub system: Option<Backlog>,
+
+    /// Minimum time between queue status requests while waiting for a
+    /// backlog, reusing the last response in between. Defaults to 10s.
+    #[arg(long, value_name = "DURATION", global = true)]
+    pub status_interval: Option<StatusInterval>,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -239,6 +795,13 @@ pub struct StatsOpt {
     /// Do not record local statistics to a file.
     #[arg(long, conflicts_with = "stats_file", global = true)]
     pub no_stats_file: bool,
+    /// Warn when throughput drops this many percent below its long-term
+    /// average, for example due to thermal throttling or background jobs.
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(1..100), global = true)]
+    pub nps_drop: Option<u8>,
+    /// Slow down acquiring work while throughput is reduced.
+    #[arg(long, requires = "nps_drop", global = true)]
+    pub pause_on_nps_drop: bool,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -317,207 +880,1095 @@ impl From<MaxBackoff> for Duration {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Parser)]
-pub enum Command {
-    /// Donate CPU time by running analysis (default).
-    Run,
-    /// Run interactive configuration.
-    Configure,
-    /// Generate a systemd service file.
-    Systemd,
-    /// Generate a systemd user service file.
-    SystemdUser,
-    /// Show GPLv3 license.
-    License,
+#[derive(Debug, Copy, Clone)]
+pub struct StatusInterval(Duration);
+
+impl Default for StatusInterval {
+    fn default() -> StatusInterval {
+        StatusInterval(Duration::from_secs(10))
+    }
 }
 
-impl Command {
-    pub fn is_systemd(self) -> bool {
-        matches!(self, Command::Systemd | Command::SystemdUser)
+impl FromStr for StatusInterval {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(StatusInterval)
     }
 }
 
-fn parse_duration(s: &str) -> Result<Duration, ParseIntError> {
-    let (s, factor) = if let Some(s) = s.strip_suffix('d') {
-        (s, 1000 * 60 * 60 * 24)
-    } else if let Some(s) = s.strip_suffix('h') {
-        (s, 1000 * 60 * 60)
-    } else if let Some(s) = s.strip_suffix('m') {
-        (s, 1000 * 60)
-    } else if let Some(s) = s.strip_suffix("ms") {
-        (s, 1)
-    } else {
-        (s.strip_suffix('s').unwrap_or(s), 1000)
-    };
-    Ok(Duration::from_millis(
-        u64::from(s.trim().parse::<u32>()?) * factor,
-    ))
+impl fmt::Display for StatusInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}s", self.0.as_secs())
+    }
 }
 
-#[derive(Debug, Default, Copy, Clone)]
-enum Toggle {
-    Yes,
-    No,
-    #[default]
-    Default,
+impl From<StatusInterval> for Duration {
+    fn from(StatusInterval(duration): StatusInterval) -> Duration {
+        duration
+    }
 }
 
-impl FromStr for Toggle {
-    type Err = ();
+#[derive(Debug, Copy, Clone)]
+pub struct FailoverAfter(Duration);
+
+impl Default for FailoverAfter {
+    fn default() -> FailoverAfter {
+        FailoverAfter(Duration::from_secs(5 * 60))
+    }
+}
+
+impl FromStr for FailoverAfter {
+    type Err = ParseIntError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.trim().to_lowercase();
-        match s.as_str() {
-            "y" | "j" | "yes" | "yep" | "yay" | "true" | "t" | "1" | "ok" => Ok(Toggle::Yes),
-            "n" | "no" | "nop" | "nope" | "nay" | "f" | "false" | "0" => Ok(Toggle::No),
-            "" => Ok(Toggle::Default),
-            _ => Err(()),
-        }
+        parse_duration(s).map(FailoverAfter)
     }
 }
 
-#[rustfmt::skip]
-fn intro() {
-    println!(r#"#   _________         .    ."#);
-    println!(r#"#  (..       \_    ,  |\  /|"#);
-    println!(r#"#   \       O  \  /|  \ \/ /"#);
-    println!(r#"#    \______    \/ |   \  /      _____ _     _     _   _      _"#);
-    println!(r#"#       vvvv\    \ |   /  |     |  ___(_)___| |__ | \ | | ___| |_"#);
-    println!(r#"#       \^^^^  ==   \_/   |     | |_  | / __| '_ \|  \| |/ _ \ __|"#);
-    println!(r#"#        `\_   ===    \.  |     |  _| | \__ \ | | | |\  |  __/ |_"#);
-    println!(r#"#        / /\_   \ /      |     |_|   |_|___/_| |_|_| \_|\___|\__| {}"#, env!("CARGO_PKG_VERSION"));
-    println!(r#"#        |/   \_  \|      /"#);
-    println!(r#"#               \________/      Distributed Stockfish analysis for lichess.org"#);
+impl fmt::Display for FailoverAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}s", self.0.as_secs())
+    }
 }
 
-pub async fn parse_and_configure(client: &Client) -> Opt {
-    let mut opt = Opt::parse();
+impl From<FailoverAfter> for Duration {
+    fn from(FailoverAfter(duration): FailoverAfter) -> Duration {
+        duration
+    }
+}
 
-    // Show intro and configure logger.
-    let is_systemd = opt.command.is_some_and(Command::is_systemd);
-    let logger = Logger::new(opt.verbose, is_systemd);
-    if !is_systemd {
-        intro();
+#[derive(Debug, Copy, Clone)]
+pub struct ConnectTimeout(Duration);
+
+impl FromStr for ConnectTimeout {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(ConnectTimeout)
     }
+}
 
-    // Handle key file.
-    if !is_systemd {
-        if let Some(key_file) = opt.key_file.take() {
-            opt.key = Some(
-                fs::read_to_string(key_file)
-                    .expect("read key file")
-                    .trim()
-                    .parse()
-                    .expect("valid key from key file"),
-            );
-        }
+impl fmt::Display for ConnectTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}ms", self.0.as_millis())
     }
+}
 
-    // Handle config file.
-    if opt.command == Some(Command::Configure)
-        || (opt.command != Some(Command::License) && !opt.no_conf)
-    {
-        let mut ini = Ini::new();
-        ini.set_default_section("Fishnet");
+impl From<ConnectTimeout> for Duration {
+    fn from(ConnectTimeout(duration): ConnectTimeout) -> Duration {
+        duration
+    }
+}
 
-        // Load ini.
-        let file_found = match fs::read_to_string(opt.conf()) {
-            Ok(contents) => {
-                ini.read(contents).expect("parse config file");
-                true
-            }
-            Err(err) if err.kind() == io::ErrorKind::NotFound => false,
-            Err(err) => panic!("failed to open config file: {err}"),
-        };
+#[derive(Debug, Copy, Clone)]
+pub struct KeepAlive(Duration);
 
-        // Configuration dialog.
-        if (!file_found && opt.command != Some(Command::Run))
-            || opt.command == Some(Command::Configure)
-        {
-            logger.headline("Configuration");
+impl Default for KeepAlive {
+    fn default() -> KeepAlive {
+        KeepAlive(Duration::from_secs(60))
+    }
+}
+
+impl KeepAlive {
+    pub fn interval(self) -> Option<Duration> {
+        Some(self.0).filter(|d| !d.is_zero())
+    }
+}
+
+impl FromStr for KeepAlive {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(KeepAlive)
+    }
+}
+
+impl fmt::Display for KeepAlive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}s", self.0.as_secs())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct EngineAge(Duration);
+
+impl FromStr for EngineAge {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(EngineAge)
+    }
+}
+
+impl fmt::Display for EngineAge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}s", self.0.as_secs())
+    }
+}
+
+impl From<EngineAge> for Duration {
+    fn from(EngineAge(duration): EngineAge) -> Duration {
+        duration
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ProgressInterval(Duration);
+
+impl FromStr for ProgressInterval {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(ProgressInterval)
+    }
+}
+
+impl fmt::Display for ProgressInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}s", self.0.as_secs())
+    }
+}
+
+impl From<ProgressInterval> for Duration {
+    fn from(ProgressInterval(duration): ProgressInterval) -> Duration {
+        duration
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MoveOverhead(Duration);
+
+impl FromStr for MoveOverhead {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(MoveOverhead)
+    }
+}
+
+impl fmt::Display for MoveOverhead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}ms", self.0.as_millis())
+    }
+}
+
+impl From<MoveOverhead> for Duration {
+    fn from(MoveOverhead(duration): MoveOverhead) -> Duration {
+        duration
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SpotCheckRate(f64);
+
+#[derive(Debug, Clone)]
+pub struct ParseSpotCheckRateError;
+
+impl fmt::Display for ParseSpotCheckRateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected fraction between 0 and 1, or percentage")
+    }
+}
+
+impl Error for ParseSpotCheckRateError {}
+
+impl FromStr for SpotCheckRate {
+    type Err = ParseSpotCheckRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rate = match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+            None => s.parse::<f64>(),
+        }
+        .map_err(|_| ParseSpotCheckRateError)?;
+        if (0.0..=1.0).contains(&rate) {
+            Ok(SpotCheckRate(rate))
+        } else {
+            Err(ParseSpotCheckRateError)
+        }
+    }
+}
+
+impl fmt::Display for SpotCheckRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.0 * 100.0)
+    }
+}
+
+impl From<SpotCheckRate> for f64 {
+    fn from(SpotCheckRate(rate): SpotCheckRate) -> f64 {
+        rate
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct UploadRate(NonZeroU64);
+
+#[derive(Debug, Clone)]
+pub struct ParseUploadRateError;
+
+impl fmt::Display for ParseUploadRateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected positive number of bytes per second, optionally with k or M suffix")
+    }
+}
+
+impl Error for ParseUploadRateError {}
+
+impl FromStr for UploadRate {
+    type Err = ParseUploadRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, multiplier) = if let Some(kilo) = s.strip_suffix(['k', 'K']) {
+            (kilo, 1_000)
+        } else if let Some(mega) = s.strip_suffix('M') {
+            (mega, 1_000_000)
+        } else {
+            (s, 1)
+        };
+        number
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .and_then(NonZeroU64::new)
+            .map(UploadRate)
+            .ok_or(ParseUploadRateError)
+    }
+}
+
+impl fmt::Display for UploadRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl From<UploadRate> for NonZeroU64 {
+    fn from(UploadRate(rate): UploadRate) -> NonZeroU64 {
+        rate
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryBudget(NonZeroU64);
+
+#[derive(Debug, Clone)]
+pub struct ParseMemoryBudgetError;
+
+impl fmt::Display for ParseMemoryBudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected positive number of bytes, optionally with k, M or G suffix")
+    }
+}
+
+impl Error for ParseMemoryBudgetError {}
+
+impl FromStr for MemoryBudget {
+    type Err = ParseMemoryBudgetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (number, multiplier) = if let Some(kilo) = s.strip_suffix(['k', 'K']) {
+            (kilo, 1_000)
+        } else if let Some(mega) = s.strip_suffix('M') {
+            (mega, 1_000_000)
+        } else if let Some(giga) = s.strip_suffix('G') {
+            (giga, 1_000_000_000)
+        } else {
+            (s, 1)
+        };
+        number
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .and_then(NonZeroU64::new)
+            .map(MemoryBudget)
+            .ok_or(ParseMemoryBudgetError)
+    }
+}
+
+impl fmt::Display for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl From<MemoryBudget> for NonZeroU64 {
+    fn from(MemoryBudget(bytes): MemoryBudget) -> NonZeroU64 {
+        bytes
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct NodeMultiplier {
+    pub variant: Variant,
+    pub factor: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseNodeMultiplierError;
+
+impl fmt::Display for ParseNodeMultiplierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected variant=factor, with a positive factor")
+    }
+}
+
+impl Error for ParseNodeMultiplierError {}
+
+impl FromStr for NodeMultiplier {
+    type Err = ParseNodeMultiplierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (variant, factor) = s.split_once('=').ok_or(ParseNodeMultiplierError)?;
+        let variant = Variant::from_uci(variant.trim()).map_err(|_| ParseNodeMultiplierError)?;
+        let factor = factor
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| ParseNodeMultiplierError)?;
+        if factor.is_finite() && factor > 0.0 {
+            Ok(NodeMultiplier { variant, factor })
+        } else {
+            Err(ParseNodeMultiplierError)
+        }
+    }
+}
+
+impl fmt::Display for NodeMultiplier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.variant.uci(), self.factor)
+    }
+}
+
+/// Additional destination for complete analysis.
+#[derive(Debug, Clone)]
+pub enum ResultSinkSpec {
+    File(PathBuf),
+    Webhook(Url),
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseResultSinkError;
+
+impl fmt::Display for ParseResultSinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected file:PATH or an http:// or https:// URL")
+    }
+}
+
+impl Error for ParseResultSinkError {}
+
+impl FromStr for ResultSinkSpec {
+    type Err = ParseResultSinkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("file:") {
+            if path.is_empty() {
+                return Err(ParseResultSinkError);
+            }
+            return Ok(ResultSinkSpec::File(PathBuf::from(path)));
+        }
+        match Url::parse(s) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(ResultSinkSpec::Webhook(url)),
+            _ => Err(ParseResultSinkError),
+        }
+    }
+}
+
+impl fmt::Display for ResultSinkSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResultSinkSpec::File(path) => write!(f, "file:{}", path.display()),
+            ResultSinkSpec::Webhook(url) => fmt::Display::fmt(url, f),
+        }
+    }
+}
+
+/// Node limit adjustments by variant.
+#[derive(Debug, Clone, Default)]
+pub struct NodeMultipliers(Vec<NodeMultiplier>);
+
+impl From<Vec<NodeMultiplier>> for NodeMultipliers {
+    fn from(multipliers: Vec<NodeMultiplier>) -> NodeMultipliers {
+        NodeMultipliers(multipliers)
+    }
+}
+
+impl NodeMultipliers {
+    pub fn get(&self, variant: Variant) -> f64 {
+        self.0
+            .iter()
+            .rev()
+            .find(|m| m.variant == variant)
+            .map_or(1.0, |m| m.factor)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+pub enum Command {
+    /// Donate CPU time by running analysis (default).
+    Run,
+    /// Run interactive configuration.
+    Configure {
+        /// Update a single setting in fishnet.ini without the interactive
+        /// dialog, for example cores=4. Can be given multiple times.
+        /// Supported: endpoint, key, cores, user-backlog, system-backlog.
+        #[arg(long, value_name = "SETTING=VALUE")]
+        set: Vec<Setting>,
+    },
+    /// Generate a systemd service file.
+    Systemd,
+    /// Generate a systemd user service file.
+    SystemdUser,
+    /// Generate a systemd socket file to activate the service from the
+    /// control socket.
+    SystemdSocket,
+    /// Generate a systemd user socket file to activate the user service
+    /// from the control socket.
+    SystemdUserSocket,
+    /// Install this executable and the configuration to a standard
+    /// location, and register a service that starts fishnet automatically
+    /// (systemd, launchd, or a scheduled task on Windows).
+    Install {
+        /// Install for all users. Requires administrator privileges. The
+        /// service still runs as the user who invoked sudo, never as root.
+        /// Not supported on Windows.
+        #[arg(long = "system", id = "install_system")]
+        system: bool,
+    },
+    /// Stop and remove the service, and delete the installed executable,
+    /// configuration, key file, stats file and leftover engine files.
+    Uninstall {
+        /// Uninstall for all users. Requires administrator privileges.
+        #[arg(long = "system", id = "uninstall_system")]
+        system: bool,
+        /// Do not ask for confirmation.
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    /// Show GPLv3 license.
+    License,
+    /// Show status of a running instance on this host.
+    Status,
+    /// Show contributions of the configured key, as counted by the server.
+    Account,
+    /// Show the current length of the server queues, to decide whether
+    /// more clients are needed right now.
+    QueueStatus,
+    /// Check that the bundled engines run on this machine and search as
+    /// expected, by comparing their bench signatures.
+    Selftest,
+    /// Analyse a recorded acquire response (JSON, as sent by the server)
+    /// without any network requests, and print what would be submitted.
+    Replay {
+        /// File with the recorded response.
+        fixture: PathBuf,
+        /// Run the bundled engines, instead of a stub that answers
+        /// instantly and deterministically.
+        #[arg(long)]
+        engine: bool,
+    },
+    /// Inspect the engines and eval files embedded in this binary.
+    Assets {
+        #[command(subcommand)]
+        command: AssetsCommand,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Parser)]
+pub enum AssetsCommand {
+    /// List embedded files, and mark those selected for this machine.
+    List,
+    /// Extract the engines and eval files selected for this machine, for
+    /// example to use them in other chess GUIs.
+    Extract {
+        /// Directory to extract to. Existing files are not overwritten.
+        dir: PathBuf,
+        /// Extract all embedded files, not only those selected for this
+        /// machine.
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+impl Command {
+    pub fn is_systemd(&self) -> bool {
+        matches!(
+            self,
+            Command::Systemd
+                | Command::SystemdUser
+                | Command::SystemdSocket
+                | Command::SystemdUserSocket
+        )
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, ParseIntError> {
+    let (s, factor) = if let Some(s) = s.strip_suffix('d') {
+        (s, 1000 * 60 * 60 * 24)
+    } else if let Some(s) = s.strip_suffix('h') {
+        (s, 1000 * 60 * 60)
+    } else if let Some(s) = s.strip_suffix('m') {
+        (s, 1000 * 60)
+    } else if let Some(s) = s.strip_suffix("ms") {
+        (s, 1)
+    } else {
+        (s.strip_suffix('s').unwrap_or(s), 1000)
+    };
+    Ok(Duration::from_millis(
+        u64::from(s.trim().parse::<u32>()?) * factor,
+    ))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SettingName {
+    Endpoint,
+    Key,
+    Cores,
+    UserBacklog,
+    SystemBacklog,
+}
+
+impl SettingName {
+    fn ini_key(self) -> &'static str {
+        match self {
+            SettingName::Endpoint => "Endpoint",
+            SettingName::Key => "Key",
+            SettingName::Cores => "Cores",
+            SettingName::UserBacklog => "UserBacklog",
+            SettingName::SystemBacklog => "SystemBacklog",
+        }
+    }
+}
+
+/// Single fishnet.ini entry to update, like `cores=4`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting {
+    name: SettingName,
+    value: String,
+}
+
+#[derive(Debug)]
+pub struct InvalidSetting;
+
+impl fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            "expected SETTING=VALUE, with endpoint, key, cores, user-backlog or system-backlog",
+        )
+    }
+}
+
+impl Error for InvalidSetting {}
+
+impl FromStr for Setting {
+    type Err = InvalidSetting;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once('=').ok_or(InvalidSetting)?;
+        let name = match name.trim().to_lowercase().replace(['-', '_'], "").as_str() {
+            "endpoint" => SettingName::Endpoint,
+            "key" => SettingName::Key,
+            "cores" | "threads" => SettingName::Cores,
+            "userbacklog" => SettingName::UserBacklog,
+            "systembacklog" => SettingName::SystemBacklog,
+            _ => return Err(InvalidSetting),
+        };
+        Ok(Setting {
+            name,
+            value: value.trim().to_owned(),
+        })
+    }
+}
+
+/// Sets a value in the Fishnet section of an ini file, leaving all other
+/// lines (including comments) untouched.
+fn set_ini_value(contents: &str, key: &str, value: &str) -> String {
+    let mut lines: Vec<String> = contents.lines().map(str::to_owned).collect();
+    let mut in_section = false;
+    let mut section_end = None;
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim();
+        if let Some(section) = trimmed.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            in_section = section.trim().eq_ignore_ascii_case("fishnet");
+            if in_section {
+                section_end = Some(i + 1);
+            }
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        if !trimmed.is_empty() && !trimmed.starts_with([';', '#']) {
+            section_end = Some(i + 1);
+        }
+        if let Some((k, v)) = line.split_once(['=', ':']) {
+            if k.trim().eq_ignore_ascii_case(key) {
+                let separator = &line[k.len()..k.len() + 1];
+                let padding = &v[..v.len() - v.trim_start().len()];
+                *line = format!("{k}{separator}{padding}{value}");
+                return lines.join("\n") + "\n";
+            }
+        }
+    }
+
+    let entry = format!("{key}={value}");
+    match section_end {
+        Some(i) => lines.insert(i, entry),
+        None => {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push("[Fishnet]".to_owned());
+            lines.push(entry);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Validates settings like the interactive dialog, then updates them in
+/// the given ini and the config file.
+async fn apply_settings(
+    opt: &Opt,
+    settings: &[Setting],
+    ini: &mut Ini,
+    client: &Client,
+    logger: &Logger,
+) {
+    let invalid =
+        |msg: String| -> ! { Opt::command().error(ErrorKind::ValueValidation, msg).exit() };
+
+    let mut contents = match fs::read_to_string(opt.conf()) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => panic!("failed to open config file: {err}"),
+    };
+
+    for setting in settings {
+        let value = match setting.name {
+            SettingName::Endpoint => {
+                let endpoint = Endpoint::from_str(&setting.value)
+                    .unwrap_or_else(|err| invalid(format!("Invalid endpoint: {err}")));
+                ensure_encrypted(&endpoint, opt.development);
+                endpoint.to_string()
+            }
+            SettingName::Key => {
+                let (key, network) = match setting.value.strip_suffix('!') {
+                    Some(key) => (key, false),
+                    None => (setting.value.as_str(), true),
+                };
+                let key =
+                    Key::from_str(key).unwrap_or_else(|err| invalid(format!("Invalid key: {err}")));
+                if network {
+                    let endpoint: Endpoint = opt
+                        .endpoint
+                        .first()
+                        .cloned()
+                        .or_else(|| {
+                            ini.get("Fishnet", "Endpoint")
+                                .map(|e| e.parse().expect("valid endpoint from fishnet.ini"))
+                        })
+                        .unwrap_or_default();
+                    ensure_encrypted(&endpoint, opt.development);
+                    let mut api =
+                        api::spawn(endpoint, Some(key.clone()), client.clone(), logger.clone());
+                    match api.check_key().await {
+                        Some(Ok(())) => (),
+                        Some(Err(err)) => invalid(format!("Invalid key: {err}")),
+                        None => {
+                            invalid("Could not check key (append ! to skip the check)".to_owned())
+                        }
+                    }
+                }
+                key.0
+            }
+            SettingName::Cores => {
+                let all = Cores::All.number(opt.topology);
+                match Cores::from_str(&setting.value) {
+                    Ok(Cores::Number(n)) if n > all => invalid(format!(
+                        "At most {all} logical cores available on your machine"
+                    )),
+                    Ok(cores) => cores.to_string(),
+                    Err(err) => invalid(format!("Invalid cores: {err}")),
+                }
+            }
+            SettingName::UserBacklog | SettingName::SystemBacklog => {
+                Backlog::from_str(&setting.value)
+                    .unwrap_or_else(|err| invalid(format!("Invalid backlog: {err}")));
+                setting.value.clone()
+            }
+        };
+
+        contents = set_ini_value(&contents, setting.name.ini_key(), &value);
+        ini.set("Fishnet", setting.name.ini_key(), Some(value));
+    }
+
+    fs::write(opt.conf(), contents).expect("write config");
+    eprintln!("Updated {:?}", opt.conf());
+}
+
+/// Asks for a personal key until a valid one is entered. Returns `None` if
+/// the current key should be kept, or no key is required.
+async fn prompt_key(
+    endpoint: &Endpoint,
+    current: Option<String>,
+    client: &Client,
+    logger: &Logger,
+) -> Option<Key> {
+    loop {
+        let mut key = String::new();
+        let required = if let Some(ref current) = current {
+            eprint!(
+                "Personal fishnet key (append ! to force, default: keep {}): ",
+                "*".repeat(current.chars().count())
+            );
+            false
+        } else if endpoint.is_development() {
+            eprint!("Personal fishnet key (append ! to force, probably not required): ");
+            false
+        } else {
+            eprint!(
+                "Personal fishnet key (append ! to force, {}): ",
+                endpoint.key_page()
+            );
+            true
+        };
+
+        io::stderr().flush().expect("flush stderr");
+        io::stdin()
+            .read_line(&mut key)
+            .expect("read key from stdin");
+
+        let key = key.trim();
+        let (key, network) = if key.is_empty() {
+            if required {
+                eprintln!("Key required.");
+                continue;
+            }
+            return None;
+        } else if let Some(key) = key.strip_suffix('!') {
+            (key, false)
+        } else {
+            (key, true)
+        };
+
+        let key = match Key::from_str(key) {
+            Ok(key) if !network => Ok(key),
+            Ok(key) => {
+                let mut api = api::spawn(
+                    endpoint.clone(),
+                    Some(key.clone()),
+                    client.clone(),
+                    logger.clone(),
+                );
+                match api.check_key().await {
+                    Some(Ok(())) => Ok(key),
+                    Some(Err(err)) => Err(err),
+                    None if keycheck::is_recently_validated(endpoint, &key) => {
+                        eprintln!("Could not check key, but it was accepted recently.");
+                        Ok(key)
+                    }
+                    None => continue, // server/network arror already logged
+                }
+            }
+            Err(err) => Err(err),
+        };
+
+        match key {
+            Ok(key) => return Some(key),
+            Err(err) => eprintln!("Invalid: {err}"),
+        }
+    }
+}
+
+/// Writes a file that contains the key, readable only by the owner.
+pub fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+/// Settings suggested by a short benchmark of this machine.
+struct Suggestion {
+    cores: NonZeroUsize,
+    keep_idle: bool,
+}
+
+async fn run_benchmark(opt: &Opt) -> Option<Suggestion> {
+    const PHASE: Duration = Duration::from_secs(5);
+
+    let cpu = opt
+        .cpu_features
+        .as_ref()
+        .map_or_else(Cpu::detect, CpuFeatures::cpu);
+    let assets = match Assets::prepare(cpu) {
+        Ok(assets) => assets,
+        Err(err) => {
+            eprintln!("Failed to prepare engine for benchmark: {err}");
+            return None;
+        }
+    };
+    let sf = assets.stockfish.get(EngineFlavor::Official).as_ref()?;
+    let all = Cores::All.number(opt.topology);
+    let auto = Cores::Auto.number(opt.topology);
+
+    // Measure once with all cores, and again after the machine had some
+    // time to heat up, to detect thermal throttling.
+    eprintln!("Benchmarking with {all} threads ...");
+    let mut measurements = Vec::new();
+    for _ in 0..3 {
+        match stockfish::bench_nps(&sf.path, all, PHASE).await {
+            Ok(Some(nps)) => measurements.push(nps),
+            Ok(None) => {
+                eprintln!("Benchmark did not report nodes per second.");
+                return None;
+            }
+            Err(err) => {
+                eprintln!("Benchmark failed: {err}");
+                return None;
+            }
+        }
+    }
+    let (first, sustained) = (measurements[0], measurements[2]);
+    let throttling = 1.0 - sustained as f64 / max(first, 1) as f64;
+    let nps_per_core = sustained / all.get() as u64;
+    eprintln!(
+        "Measured {} knps/core, {:.0}% slower after warming up.",
+        nps_per_core / 1000,
+        throttling.max(0.0) * 100.0
+    );
+
+    // Leave more headroom if the machine cannot sustain full load.
+    let cores = if throttling > 0.1 {
+        NonZeroUsize::new(auto.get() / 2).unwrap_or(NonZeroUsize::MIN)
+    } else {
+        auto
+    };
+
+    // Estimate how long the suggested cores would take for a batch of 60
+    // positions at 1_450_000 nodes each. Slow machines are more useful
+    // when helping out with a backlog.
+    let batch_seconds = 60 * 1_450_000 / max(1, nps_per_core * cores.get() as u64);
+    let keep_idle = throttling > 0.1 || batch_seconds > 60;
+
+    eprintln!(
+        "Suggesting {cores} cores{}.",
+        if keep_idle {
+            ", and to keep the client idle unless a backlog is building up"
+        } else {
+            ""
+        }
+    );
+    Some(Suggestion { cores, keep_idle })
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+enum Toggle {
+    Yes,
+    No,
+    #[default]
+    Default,
+}
+
+impl FromStr for Toggle {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        match s.as_str() {
+            "y" | "j" | "yes" | "yep" | "yay" | "true" | "t" | "1" | "ok" => Ok(Toggle::Yes),
+            "n" | "no" | "nop" | "nope" | "nay" | "f" | "false" | "0" => Ok(Toggle::No),
+            "" => Ok(Toggle::Default),
+            _ => Err(()),
+        }
+    }
+}
+
+#[rustfmt::skip]
+fn intro() {
+    println!(r#"#   _________         .    ."#);
+    println!(r#"#  (..       \_    ,  |\  /|"#);
+    println!(r#"#   \       O  \  /|  \ \/ /"#);
+    println!(r#"#    \______    \/ |   \  /      _____ _     _     _   _      _"#);
+    println!(r#"#       vvvv\    \ |   /  |     |  ___(_)___| |__ | \ | | ___| |_"#);
+    println!(r#"#       \^^^^  ==   \_/   |     | |_  | / __| '_ \|  \| |/ _ \ __|"#);
+    println!(r#"#        `\_   ===    \.  |     |  _| | \__ \ | | | |\  |  __/ |_"#);
+    println!(r#"#        / /\_   \ /      |     |_|   |_|___/_| |_|_| \_|\___|\__| {}"#, env!("CARGO_PKG_VERSION"));
+    println!(r#"#        |/   \_  \|      /"#);
+    println!(r#"#               \________/      Distributed Stockfish analysis for lichess.org"#);
+}
+
+pub fn parse() -> Opt {
+    let opt = Opt::parse();
+
+    if opt.auto_update && !cfg!(feature = "auto-update") {
+        Opt::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--auto-update is not available in this build. Use the package manager that \
+                 installed fishnet to update it",
+            )
+            .exit();
+    }
+
+    if opt.http3 && !cfg!(feature = "http3") {
+        Opt::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--http3 is not available in this build. Build with --features http3",
+            )
+            .exit();
+    }
+
+    if opt.event_log && !cfg!(windows) {
+        Opt::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--event-log is only available on Windows",
+            )
+            .exit();
+    }
+
+    opt
+}
+
+/// Exits, unless the endpoint uses TLS or plain text was explicitly allowed.
+fn ensure_encrypted(endpoint: &Endpoint, development: bool) {
+    if endpoint.url.scheme() == "http" && !development {
+        Opt::command()
+            .error(
+                ErrorKind::ValueValidation,
+                "Plain http:// endpoints are only allowed with --development",
+            )
+            .exit();
+    }
+}
+
+pub async fn configure(mut opt: Opt, client: &Client) -> Opt {
+    // Show intro and configure logger.
+    let is_systemd = opt.command.as_ref().is_some_and(Command::is_systemd);
+    let is_status = opt.command == Some(Command::Status);
+    let settings = match opt.command {
+        Some(Command::Configure { ref set }) => Some(set.clone()),
+        _ => None,
+    };
+    let is_assets = matches!(
+        opt.command,
+        Some(Command::Assets { .. } | Command::Selftest | Command::Replay { .. })
+    );
+    let is_uninstall = matches!(opt.command, Some(Command::Uninstall { .. }));
+    let logger = Logger::new(opt.verbose, opt.stdout_reserved());
+    if !is_status && !opt.stdout_reserved() {
+        intro();
+    }
+
+    // Handle key file.
+    if !is_systemd && !is_uninstall {
+        if let Some(key_file) = opt.key_file.take() {
+            opt.key = Some(
+                fs::read_to_string(key_file)
+                    .expect("read key file")
+                    .trim()
+                    .parse()
+                    .expect("valid key from key file"),
+            );
+        }
+    }
+
+    // Handle config file.
+    if settings.is_some()
+        || (opt.command != Some(Command::License)
+            && !is_status
+            && !is_assets
+            && !is_uninstall
+            && !opt.no_conf)
+    {
+        let mut ini = Ini::new();
+        ini.set_default_section("Fishnet");
+
+        // Load ini.
+        let file_found = match fs::read_to_string(opt.conf()) {
+            Ok(contents) => {
+                ini.read(contents).expect("parse config file");
+                true
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+            Err(err) => panic!("failed to open config file: {err}"),
+        };
+
+        // Non-interactive updates.
+        if let Some(ref settings) = settings {
+            if !settings.is_empty() {
+                apply_settings(&opt, settings, &mut ini, client, &logger).await;
+            }
+        }
+
+        // Configuration dialog. Containers usually have no interactive
+        // terminal, so they are configured with arguments only.
+        if (!file_found
+            && opt.command != Some(Command::Run)
+            && !matches!(opt.command, Some(Command::Account | Command::QueueStatus))
+            && settings.is_none()
+            && Container::detect().is_none())
+            || settings.as_ref().is_some_and(Vec::is_empty)
+        {
+            logger.headline("Configuration");
 
             // Step 1: Endpoint.
             let endpoint: Endpoint = opt
                 .endpoint
-                .clone()
+                .first()
+                .cloned()
                 .or_else(|| {
                     ini.get("Fishnet", "Endpoint")
                         .map(|e| e.parse().expect("valid endpoint from fishnet.ini"))
                 })
                 .unwrap_or_default();
+            ensure_encrypted(&endpoint, opt.development);
 
             // Step 2: Key.
-            loop {
-                let mut key = String::new();
-                let required = if let Some(current) = ini.get("Fishnet", "Key") {
-                    eprint!(
-                        "Personal fishnet key (append ! to force, default: keep {}): ",
-                        "*".repeat(current.chars().count())
-                    );
-                    false
-                } else if endpoint.is_development() {
-                    eprint!("Personal fishnet key (append ! to force, probably not required): ");
-                    false
-                } else {
-                    eprint!(
-                        "Personal fishnet key (append ! to force, https://lichess.org/get-fishnet): "
-                    );
-                    true
-                };
+            if let Some(Key(key)) =
+                prompt_key(&endpoint, ini.get("Fishnet", "Key"), client, &logger).await
+            {
+                ini.set("Fishnet", "Key", Some(key));
+            }
 
+            // Step 3: Optional benchmark.
+            eprintln!();
+            let suggestion = loop {
+                let mut benchmark = String::new();
+                eprint!("Run a 15 second benchmark to suggest settings? (default: no) ");
                 io::stderr().flush().expect("flush stderr");
                 io::stdin()
-                    .read_line(&mut key)
-                    .expect("read key from stdin");
+                    .read_line(&mut benchmark)
+                    .expect("read benchmark from stdin");
 
-                let key = key.trim();
-                let (key, network) = if key.is_empty() {
-                    if required {
-                        eprintln!("Key required.");
-                        continue;
-                    }
-                    break;
-                } else if let Some(key) = key.strip_suffix('!') {
-                    (key, false)
-                } else {
-                    (key, true)
-                };
-
-                let key = match Key::from_str(key) {
-                    Ok(key) if !network => Ok(key),
-                    Ok(key) => {
-                        let mut api = api::spawn(
-                            endpoint.clone(),
-                            Some(key.clone()),
-                            client.clone(),
-                            logger.clone(),
-                        );
-                        match api.check_key().await {
-                            Some(Ok(())) => Ok(key),
-                            Some(Err(err)) => Err(err),
-                            None => continue, // server/network arror already logged
-                        }
-                    }
-                    Err(err) => Err(err),
-                };
-
-                match key {
-                    Ok(Key(key)) => {
-                        ini.set("Fishnet", "Key", Some(key));
-                        break;
-                    }
-                    Err(err) => eprintln!("Invalid: {err}"),
+                match Toggle::from_str(&benchmark) {
+                    Ok(Toggle::Yes) => break run_benchmark(&opt).await,
+                    Ok(Toggle::No | Toggle::Default) => break None,
+                    Err(_) => (),
                 }
-            }
+            };
 
-            // Step 3: Cores.
+            // Step 4: Cores.
             eprintln!();
             loop {
                 let mut cores = String::new();
-                let all = Cores::All.number();
-                let auto = Cores::Auto.number();
+                let all = Cores::All.number(opt.topology);
+                let default = suggestion
+                    .as_ref()
+                    .map_or(Cores::Auto, |s| Cores::Number(s.cores));
                 eprint!(
-                    "Number of logical cores to use for engine threads (default {auto}, max {all}): "
+                    "Number of logical cores to use for engine threads (default {}, max {all}): ",
+                    default.number(opt.topology)
                 );
                 io::stderr().flush().expect("flush stderr");
                 io::stdin()
@@ -526,7 +1977,7 @@ pub async fn parse_and_configure(client: &Client) -> Opt {
 
                 match Some(cores.trim())
                     .filter(|c| !c.is_empty())
-                    .map_or(Ok(Cores::Auto), Cores::from_str)
+                    .map_or(Ok(default), Cores::from_str)
                 {
                     Ok(Cores::Number(n)) if n > all => {
                         eprintln!("At most {all} logical cores available on your machine.");
@@ -539,20 +1990,27 @@ pub async fn parse_and_configure(client: &Client) -> Opt {
                 }
             }
 
-            // Step 4: Backlog.
+            // Step 5: Backlog.
             eprintln!();
             eprintln!("You can choose to not join unless a backlog is building up. Examples:");
             eprintln!("* Rented server exclusively for fishnet: choose no");
             eprintln!("* Running on a laptop: choose yes");
+            let keep_idle = suggestion.as_ref().is_some_and(|s| s.keep_idle);
             loop {
                 let mut backlog = String::new();
-                eprint!("Would you prefer to keep your client idle? (default: no) ");
+                eprint!(
+                    "Would you prefer to keep your client idle? (default: {}) ",
+                    if keep_idle { "yes" } else { "no" }
+                );
                 io::stderr().flush().expect("flush stderr");
                 io::stdin()
                     .read_line(&mut backlog)
                     .expect("read backlog from stdin");
 
-                match Toggle::from_str(&backlog) {
+                match Toggle::from_str(&backlog).map(|toggle| match toggle {
+                    Toggle::Default if keep_idle => Toggle::Yes,
+                    toggle => toggle,
+                }) {
                     Ok(Toggle::Yes) => {
                         ini.setstr("Fishnet", "UserBacklog", Some("short"));
                         ini.setstr("Fishnet", "SystemBacklog", Some("long"));
@@ -567,7 +2025,7 @@ pub async fn parse_and_configure(client: &Client) -> Opt {
                 }
             }
 
-            // Step 5: Write config.
+            // Step 6: Write config.
             eprintln!();
             loop {
                 let mut write = String::new();
@@ -603,16 +2061,23 @@ pub async fn parse_and_configure(client: &Client) -> Opt {
 
         // Merge config file into command line arguments.
         if !is_systemd {
-            opt.endpoint = opt.endpoint.or_else(|| {
-                ini.get("Fishnet", "Endpoint")
-                    .map(|e| e.parse().expect("valid endpoint"))
-            });
+            if opt.endpoint.is_empty() {
+                opt.endpoint.extend(
+                    ini.get("Fishnet", "Endpoint")
+                        .map(|e| e.parse().expect("valid endpoint")),
+                );
+            }
 
             opt.key = opt.key.or_else(|| {
                 ini.get("Fishnet", "Key")
                     .map(|k| k.parse().expect("valid key"))
             });
 
+            opt.contributor_tag = opt.contributor_tag.or_else(|| {
+                ini.get("Fishnet", "ContributorTag")
+                    .map(|t| t.parse().expect("valid contributor tag"))
+            });
+
             opt.cores = opt.cores.or_else(|| {
                 ini.get("Fishnet", "Cores")
                     .map(|c| c.parse().expect("valid cores"))
@@ -626,11 +2091,36 @@ pub async fn parse_and_configure(client: &Client) -> Opt {
                 ini.get("Fishnet", "SystemBacklog")
                     .map(|b| b.parse().expect("valid system backlog"))
             });
+
+            if opt.node_multipliers.is_empty() {
+                if let Some(section) = ini.get_map_ref().get("nodemultipliers") {
+                    opt.node_multipliers = section
+                        .iter()
+                        .filter_map(|(variant, factor)| {
+                            factor.as_ref().map(|factor| {
+                                format!("{variant}={factor}")
+                                    .parse()
+                                    .expect("valid node multiplier")
+                            })
+                        })
+                        .collect();
+                }
+            }
+
+            if opt.result_sinks.is_empty() {
+                if let Some(section) = ini.get_map_ref().get("resultsinks") {
+                    opt.result_sinks = section
+                        .values()
+                        .flatten()
+                        .map(|sink| sink.parse().expect("valid result sink"))
+                        .collect();
+                }
+            }
         }
     }
 
     // Validate number of cores.
-    let all = Cores::All.number();
+    let all = Cores::All.number(opt.topology);
     match opt.cores {
         Some(Cores::Number(n)) if n > all => {
             logger.warn(&format!(
@@ -641,5 +2131,36 @@ pub async fn parse_and_configure(client: &Client) -> Opt {
         _ => (),
     }
 
+    // Do not send the key in plain text by accident.
+    for endpoint in iter::once(opt.endpoint()).chain(opt.fallback_endpoints().iter().cloned()) {
+        ensure_encrypted(&endpoint, opt.development);
+        if endpoint.url.scheme() == "http" {
+            logger.warn(&format!(
+                "Connecting to {endpoint} WITHOUT ENCRYPTION. Use only for development!"
+            ));
+        }
+    }
+    if opt.insecure_skip_tls_verify {
+        logger.warn("NOT VERIFYING TLS CERTIFICATES. Use only for development!");
+    }
+
+    // Node limits are part of the contract with lichess.org.
+    if !opt.node_multipliers.is_empty() && !opt.endpoint().is_development() {
+        logger.warn("Node multipliers are only supported for private endpoints. Ignored.");
+        opt.node_multipliers.clear();
+    }
+
+    // Containers are typically configured with environment variables.
+    if let Some(container) = Container::detect() {
+        if opt.key.is_none()
+            && !opt.endpoint().is_development()
+            && matches!(opt.command, None | Some(Command::Run))
+        {
+            logger.warn(&format!(
+                "Running in {container} without a key. Provide one with --key or --key-file (or KEY or KEY_FILE for the official image)."
+            ));
+        }
+    }
+
     opt
 }
@@ -0,0 +1,103 @@
+use std::{env, fmt, sync::OnceLock};
+
+/// Container runtime that fishnet appears to be running in.
+///
+/// Containers are usually run without an interactive terminal or a
+/// persistent configuration file, and often with CPU quotas set by the
+/// orchestrator, so some defaults are adjusted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Container {
+    Docker,
+    Podman,
+    Kubernetes,
+    Other,
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Container::Docker => "Docker",
+            Container::Podman => "Podman",
+            Container::Kubernetes => "Kubernetes",
+            Container::Other => "container",
+        })
+    }
+}
+
+impl Container {
+    /// Detects the container runtime once, from well-known environment
+    /// variables and markers.
+    pub fn detect() -> Option<Container> {
+        static DETECTED: OnceLock<Option<Container>> = OnceLock::new();
+        *DETECTED.get_or_init(Container::detect_uncached)
+    }
+
+    fn detect_uncached() -> Option<Container> {
+        if env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+            return Some(Container::Kubernetes);
+        }
+        Container::detect_markers().or_else(|| {
+            // Set by podman, systemd-nspawn, LXC and others.
+            env::var_os("container")
+                .filter(|name| !name.is_empty())
+                .map(|_| Container::Other)
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn detect_markers() -> Option<Container> {
+        use std::{fs, path::Path};
+
+        if Path::new("/.dockerenv").exists() {
+            return Some(Container::Docker);
+        }
+        if Path::new("/run/.containerenv").exists() {
+            return Some(Container::Podman);
+        }
+        let cgroup = fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+        if cgroup.contains("kubepods") {
+            Some(Container::Kubernetes)
+        } else if cgroup.contains("docker") {
+            Some(Container::Docker)
+        } else if cgroup.contains("libpod") {
+            Some(Container::Podman)
+        } else if cgroup.contains("containerd") || cgroup.contains("lxc") {
+            Some(Container::Other)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn detect_markers() -> Option<Container> {
+        None
+    }
+
+    /// Whether the CPU usage of this container is limited by a cgroup quota,
+    /// so that the available CPUs are already set aside for fishnet.
+    #[cfg(target_os = "linux")]
+    pub fn has_cpu_quota() -> bool {
+        use std::fs;
+
+        // cgroup v2: "max 100000" if unlimited, "<quota> <period>" otherwise.
+        if let Ok(max) = fs::read_to_string("/sys/fs/cgroup/cpu.max") {
+            return max
+                .split_whitespace()
+                .next()
+                .is_some_and(|quota| quota != "max");
+        }
+        // cgroup v1: -1 if unlimited.
+        [
+            "/sys/fs/cgroup/cpu/cpu.cfs_quota_us",
+            "/sys/fs/cgroup/cpu,cpuacct/cpu.cfs_quota_us",
+        ]
+        .into_iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .any(|quota| quota.trim().parse::<i64>().is_ok_and(|quota| quota > 0))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn has_cpu_quota() -> bool {
+        false
+    }
+}
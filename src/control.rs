@@ -0,0 +1,463 @@
+use std::{
+    collections::VecDeque,
+    env, fmt, io,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_with::{DurationSeconds, DurationSecondsWithFrac, serde_as};
+
+use crate::{
+    logger::ProgressAt,
+    stats::{NpsRecorder, Stats},
+    util::dot_thousands,
+};
+
+/// Default location of the control socket that `fishnet status` connects
+/// to.
+pub fn default_socket_path() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(private_temp_dir)
+        .join("fishnet.sock")
+}
+
+/// Directory for this user in the shared temporary directory, so that
+/// other users can neither connect to nor squat the socket.
+#[cfg(unix)]
+fn private_temp_dir() -> PathBuf {
+    env::temp_dir().join(format!("fishnet-{}", current_uid()))
+}
+
+#[cfg(not(unix))]
+fn private_temp_dir() -> PathBuf {
+    env::temp_dir()
+}
+
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn current_uid() -> u32 {
+    // SAFETY: Always successful, without side effects.
+    unsafe { libc::getuid() }
+}
+
+/// Creates the directory of the socket if missing, accessible only by this
+/// user. An existing directory must belong to this user or root.
+#[cfg(unix)]
+fn prepare_socket_dir(path: &std::path::Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt as _, MetadataExt as _};
+
+    let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    match std::fs::metadata(dir) {
+        Ok(metadata) if metadata.uid() == current_uid() || metadata.uid() == 0 => Ok(()),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} belongs to another user", dir.display()),
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir),
+        Err(err) => Err(err),
+    }
+}
+
+/// Live information about the running client that is not tracked by the
+/// queue itself.
+#[derive(Clone)]
+pub struct StatusBoard {
+    started: Instant,
+    endpoint: String,
+    state: Arc<Mutex<BoardState>>,
+}
+
+struct BoardState {
+    workers: Vec<Option<WorkerActivity>>,
+    api_errors: VecDeque<(Instant, String)>,
+    acquire_backoff_until: Option<Instant>,
+    api_backoff_until: Option<Instant>,
+}
+
+struct WorkerActivity {
+    context: String,
+    since: Instant,
+}
+
+impl StatusBoard {
+    const MAX_API_ERRORS: usize = 5;
+
+    pub fn new(cores: NonZeroUsize, endpoint: String) -> StatusBoard {
+        StatusBoard {
+            started: Instant::now(),
+            endpoint,
+            state: Arc::new(Mutex::new(BoardState {
+                workers: (0..cores.get()).map(|_| None).collect(),
+                api_errors: VecDeque::with_capacity(StatusBoard::MAX_API_ERRORS),
+                acquire_backoff_until: None,
+                api_backoff_until: None,
+            })),
+        }
+    }
+
+    pub fn worker_busy(&self, i: usize, context: &ProgressAt) {
+        let mut state = self.state.lock().expect("status board");
+        if let Some(worker) = state.workers.get_mut(i) {
+            *worker = Some(WorkerActivity {
+                context: context.to_string(),
+                since: Instant::now(),
+            });
+        }
+    }
+
+    pub fn worker_idle(&self, i: usize) {
+        let mut state = self.state.lock().expect("status board");
+        if let Some(worker) = state.workers.get_mut(i) {
+            *worker = None;
+        }
+    }
+
+    pub fn api_error(&self, message: String) {
+        let mut state = self.state.lock().expect("status board");
+        if state.api_errors.len() >= StatusBoard::MAX_API_ERRORS {
+            state.api_errors.pop_front();
+        }
+        state.api_errors.push_back((Instant::now(), message));
+    }
+
+    /// The queue is waiting before asking for work again, because none was
+    /// available. `None` when no longer waiting.
+    pub fn acquire_backoff(&self, backoff: Option<Duration>) {
+        let mut state = self.state.lock().expect("status board");
+        state.acquire_backoff_until = backoff.map(|backoff| Instant::now() + backoff);
+    }
+
+    /// Requests to the endpoint are suspended after errors. `None` when no
+    /// longer suspended.
+    pub fn api_backoff(&self, backoff: Option<Duration>) {
+        let mut state = self.state.lock().expect("status board");
+        state.api_backoff_until = backoff.map(|backoff| Instant::now() + backoff);
+    }
+
+    pub fn report(&self, queued: usize, stats: Stats, nnue_nps: &NpsRecorder) -> StatusReport {
+        let state = self.state.lock().expect("status board");
+        StatusReport {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            uptime: self.started.elapsed(),
+            queued,
+            endpoint: self.endpoint.clone(),
+            nnue_nps: nnue_nps.to_string(),
+            stats,
+            workers: state
+                .workers
+                .iter()
+                .map(|worker| WorkerReport {
+                    context: worker.as_ref().map(|w| w.context.clone()),
+                    busy: worker.as_ref().map(|w| w.since.elapsed()),
+                })
+                .collect(),
+            api_errors: state
+                .api_errors
+                .iter()
+                .map(|(at, message)| ApiErrorReport {
+                    ago: at.elapsed(),
+                    message: message.clone(),
+                })
+                .collect(),
+            acquire_backoff: remaining(state.acquire_backoff_until),
+            api_backoff: remaining(state.api_backoff_until),
+        }
+    }
+}
+
+fn remaining(until: Option<Instant>) -> Option<Duration> {
+    until
+        .map(|until| until.saturating_duration_since(Instant::now()))
+        .filter(|remaining| !remaining.is_zero())
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct StatusReport {
+    pub version: String,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub uptime: Duration,
+    pub queued: usize,
+    #[serde(default)]
+    pub endpoint: String,
+    pub nnue_nps: String,
+    pub stats: Stats,
+    pub workers: Vec<WorkerReport>,
+    pub api_errors: Vec<ApiErrorReport>,
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    pub acquire_backoff: Option<Duration>,
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    pub api_backoff: Option<Duration>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct WorkerReport {
+    pub context: Option<String>,
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub busy: Option<Duration>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct ApiErrorReport {
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub ago: Duration,
+    pub message: String,
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Version: v{}", self.version)?;
+        writeln!(
+            f,
+            "Uptime: {:?}",
+            Duration::from_secs(self.uptime.as_secs())
+        )?;
+        writeln!(f, "Queued: {} positions", self.queued)?;
+        writeln!(f, "Speed: {} (nnue)", self.nnue_nps)?;
+        writeln!(
+            f,
+            "Total ({}): {} batches, {} positions, {} nodes",
+            self.endpoint,
+            dot_thousands(self.stats.total_batches),
+            dot_thousands(self.stats.total_positions),
+            dot_thousands(self.stats.total_nodes)
+        )?;
+        writeln!(f, "API errors: {}", self.stats.api_errors)?;
+        writeln!(f, "Failures: {}", self.stats.failures)?;
+        match self.acquire_backoff {
+            Some(backoff) => writeln!(
+                f,
+                "Acquire backoff: {backoff:.1?} remaining (no work available)"
+            )?,
+            None => writeln!(f, "Acquire backoff: none")?,
+        }
+        match self.api_backoff {
+            Some(backoff) => writeln!(f, "API error backoff: {backoff:.1?} remaining")?,
+            None => writeln!(f, "API error backoff: none")?,
+        }
+        writeln!(f, "Workers:")?;
+        for (i, worker) in self.workers.iter().enumerate() {
+            match (&worker.context, worker.busy) {
+                (Some(context), Some(busy)) => writeln!(
+                    f,
+                    "  {i}: {context} (for {:?})",
+                    Duration::from_secs(busy.as_secs())
+                )?,
+                (Some(context), None) => writeln!(f, "  {i}: {context}")?,
+                (None, _) => writeln!(f, "  {i}: idle")?,
+            }
+        }
+        if self.api_errors.is_empty() {
+            writeln!(f, "API errors: none")?;
+        } else {
+            writeln!(f, "API errors:")?;
+            for err in &self.api_errors {
+                writeln!(
+                    f,
+                    "  {:?} ago: {}",
+                    Duration::from_secs(err.ago.as_secs()),
+                    err.message
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Listening end of the control socket. Never yields connections on
+/// platforms without Unix domain sockets.
+pub struct ControlServer {
+    /// Listener and the socket file to clean up, unless the socket is owned
+    /// by systemd.
+    #[cfg(unix)]
+    inner: Option<(Option<PathBuf>, tokio::net::UnixListener)>,
+}
+
+pub struct ControlConnection {
+    #[cfg(unix)]
+    stream: tokio::net::UnixStream,
+}
+
+impl ControlServer {
+    /// A server that never accepts connections.
+    pub fn disabled() -> ControlServer {
+        ControlServer {
+            #[cfg(unix)]
+            inner: None,
+        }
+    }
+
+    /// Listens on the socket passed by systemd socket activation, or binds
+    /// to the given path otherwise.
+    #[cfg(unix)]
+    pub async fn bind(path: PathBuf) -> io::Result<ControlServer> {
+        use tokio::net::{UnixListener, UnixStream};
+
+        if let Some(listener) = systemd_listen_fd()? {
+            listener.set_nonblocking(true)?;
+            return Ok(ControlServer {
+                inner: Some((None, UnixListener::from_std(listener)?)),
+            });
+        }
+
+        prepare_socket_dir(&path)?;
+        if UnixStream::connect(&path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another instance is listening on {}", path.display()),
+            ));
+        }
+        // Remove stale socket left behind by an instance that did not
+        // shut down cleanly.
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+        let listener = UnixListener::bind(&path)?;
+        // Status includes the endpoint and batch URLs.
+        std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        Ok(ControlServer {
+            inner: Some((Some(path), listener)),
+        })
+    }
+
+    pub fn is_socket_activated(&self) -> bool {
+        #[cfg(unix)]
+        if let Some((None, _)) = self.inner {
+            return true;
+        }
+        false
+    }
+
+    #[cfg(not(unix))]
+    pub async fn bind(_path: PathBuf) -> io::Result<ControlServer> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "control socket requires Unix domain sockets",
+        ))
+    }
+
+    pub async fn accept(&self) -> io::Result<ControlConnection> {
+        #[cfg(unix)]
+        if let Some((_, ref listener)) = self.inner {
+            let (stream, _) = listener.accept().await?;
+            return Ok(ControlConnection { stream });
+        }
+        std::future::pending().await
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some((Some(ref path), _)) = self.inner {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Whether systemd passed a listening socket to this process, as recorded
+/// by [`take_systemd_listen_env()`].
+#[cfg(unix)]
+static SYSTEMD_LISTEN_FD: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Reads and clears the environment variables of sd_listen_fds(3), so that
+/// a restarted process that inherits the environment is not confused. Call
+/// at the very start of `main()`, before any other threads exist.
+#[cfg(unix)]
+#[allow(unsafe_code)]
+pub fn take_systemd_listen_env() {
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let fds = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<u32>().ok())
+        .unwrap_or(0);
+
+    // SAFETY: Called before any other threads are started, so nothing can
+    // read the environment concurrently.
+    unsafe {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+
+    SYSTEMD_LISTEN_FD.store(for_us && fds >= 1, std::sync::atomic::Ordering::Relaxed);
+}
+
+#[cfg(not(unix))]
+pub fn take_systemd_listen_env() {}
+
+/// Takes the first listening socket passed by systemd, following the
+/// protocol of sd_listen_fds(3).
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn systemd_listen_fd() -> io::Result<Option<std::os::unix::net::UnixListener>> {
+    use std::os::{fd::FromRawFd as _, unix::net::UnixListener};
+
+    const SD_LISTEN_FDS_START: libc::c_int = 3;
+
+    if !SYSTEMD_LISTEN_FD.swap(false, std::sync::atomic::Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    // Do not leak the socket into engine processes.
+    // SAFETY: File descriptor is valid and owned by us, as promised by
+    // LISTEN_PID.
+    if unsafe { libc::fcntl(SD_LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: Ownership of the listening socket is transferred to us.
+    Ok(Some(unsafe {
+        UnixListener::from_raw_fd(SD_LISTEN_FDS_START)
+    }))
+}
+
+impl ControlConnection {
+    pub async fn respond(self, report: &StatusReport) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            use tokio::io::AsyncWriteExt as _;
+
+            let mut stream = self.stream;
+            stream.write_all(&serde_json::to_vec(report)?).await?;
+            stream.shutdown().await?;
+        }
+        #[cfg(not(unix))]
+        let _ = report;
+        Ok(())
+    }
+}
+
+/// Connect to the control socket of a running instance and fetch its
+/// status.
+#[cfg(unix)]
+pub async fn query(path: PathBuf) -> io::Result<StatusReport> {
+    use tokio::{io::AsyncReadExt as _, net::UnixStream};
+
+    let mut stream = UnixStream::connect(&path).await?;
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await?;
+    Ok(serde_json::from_slice(&buf)?)
+}
+
+#[cfg(not(unix))]
+pub async fn query(_path: PathBuf) -> io::Result<StatusReport> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "control socket requires Unix domain sockets",
+    ))
+}
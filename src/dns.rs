@@ -0,0 +1,42 @@
+use std::{error::Error, fmt, io, net::SocketAddr};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use tokio::net::lookup_host;
+
+/// Failed to resolve a hostname. Recognizable in the source chain of
+/// request errors, unlike errors of the default resolver.
+#[derive(Debug)]
+pub struct DnsError(io::Error);
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to resolve host")
+    }
+}
+
+impl Error for DnsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Looks up hostnames with the system resolver, like the default resolver
+/// of the HTTP client.
+#[derive(Debug, Clone, Default)]
+pub struct SystemResolver;
+
+impl SystemResolver {
+    pub async fn lookup(name: &str, port: u16) -> Result<Vec<SocketAddr>, DnsError> {
+        Ok(lookup_host((name, port)).await.map_err(DnsError)?.collect())
+    }
+}
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            // The connector fills in the port of the request.
+            let addrs = SystemResolver::lookup(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
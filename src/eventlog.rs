@@ -0,0 +1,79 @@
+//! Mirrors warnings and errors to the Windows Event Log, where they remain
+//! visible when running as a service without a console.
+//!
+//! The event source should be registered once, from an elevated PowerShell:
+//! `New-EventLog -LogName Application -Source fishnet`. Otherwise Event
+//! Viewer still shows the messages, but prefixed with a note about the
+//! missing source.
+
+use windows::{
+    Win32::{
+        Foundation::HANDLE,
+        System::EventLog::{
+            DeregisterEventSource, EVENTLOG_ERROR_TYPE, EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+            RegisterEventSourceW, ReportEventW,
+        },
+    },
+    core::{HSTRING, PCWSTR, w},
+};
+
+#[derive(Debug, Copy, Clone)]
+pub enum EventLevel {
+    Warning,
+    Error,
+}
+
+impl EventLevel {
+    fn event_type(self) -> REPORT_EVENT_TYPE {
+        match self {
+            EventLevel::Warning => EVENTLOG_WARNING_TYPE,
+            EventLevel::Error => EVENTLOG_ERROR_TYPE,
+        }
+    }
+}
+
+pub struct EventLog {
+    handle: HANDLE,
+}
+
+// SAFETY: Event log handles can be used from any thread.
+#[allow(unsafe_code)]
+unsafe impl Send for EventLog {}
+#[allow(unsafe_code)]
+unsafe impl Sync for EventLog {}
+
+impl EventLog {
+    const EVENT_ID: u32 = 1;
+
+    #[allow(unsafe_code)]
+    pub fn register() -> windows::core::Result<EventLog> {
+        let handle = unsafe { RegisterEventSourceW(PCWSTR::null(), w!("fishnet")) }?;
+        Ok(EventLog { handle })
+    }
+
+    #[allow(unsafe_code)]
+    pub fn report(&self, level: EventLevel, message: &str) {
+        let message = HSTRING::from(message);
+        let strings = [PCWSTR(message.as_ptr())];
+        // Best effort. There is nowhere else to report failure.
+        let _ = unsafe {
+            ReportEventW(
+                self.handle,
+                level.event_type(),
+                0,
+                EventLog::EVENT_ID,
+                None,
+                0,
+                Some(&strings),
+                None,
+            )
+        };
+    }
+}
+
+impl Drop for EventLog {
+    #[allow(unsafe_code)]
+    fn drop(&mut self) {
+        let _ = unsafe { DeregisterEventSource(self.handle) };
+    }
+}
@@ -0,0 +1,70 @@
+//! Fatal conditions with distinct exit codes, so that wrappers can tell
+//! conditions that need reconfiguration apart from transient failures.
+
+use std::process;
+
+use serde::Serialize;
+
+use crate::logger::Logger;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fatal {
+    /// The endpoint does not accept the key. Needs a new key.
+    InvalidKey,
+    /// The endpoint rejected this client, for example because the version
+    /// is no longer supported. Needs an update or reconfiguration.
+    ClientRejected,
+    /// None of the bundled engines can run on this machine.
+    NoEngine,
+    /// Extracting the bundled engines failed, for example because the
+    /// temporary directory is full or not writable.
+    AssetExtraction,
+}
+
+impl Fatal {
+    /// Exit code, following the conventions of sysexits.h.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Fatal::InvalidKey => 78,      // EX_CONFIG
+            Fatal::ClientRejected => 76,  // EX_PROTOCOL
+            Fatal::NoEngine => 69,        // EX_UNAVAILABLE
+            Fatal::AssetExtraction => 74, // EX_IOERR
+        }
+    }
+
+    pub const ALL: [Fatal; 4] = [
+        Fatal::InvalidKey,
+        Fatal::ClientRejected,
+        Fatal::NoEngine,
+        Fatal::AssetExtraction,
+    ];
+
+    pub fn from_exit_code(code: i32) -> Option<Fatal> {
+        Fatal::ALL
+            .into_iter()
+            .find(|fatal| fatal.exit_code() == code)
+    }
+
+    /// Logs the message, prints a final line of JSON to stderr, and exits.
+    pub fn exit(self, message: &str, logger: &Logger) -> ! {
+        logger.error(message);
+        eprintln!(
+            "{}",
+            serde_json::to_string(&FatalLine {
+                fatal: self,
+                exit_code: self.exit_code(),
+                message,
+            })
+            .expect("serialize fatal error")
+        );
+        process::exit(self.exit_code());
+    }
+}
+
+#[derive(Serialize)]
+struct FatalLine<'a> {
+    fatal: Fatal,
+    exit_code: i32,
+    message: &'a str,
+}
@@ -0,0 +1,539 @@
+use std::{
+    collections::HashSet,
+    env, fs, io,
+    io::Write as _,
+    path::{Path, PathBuf},
+    process,
+    process::Command,
+};
+
+use fishnet::{
+    assets,
+    configure::{Opt, write_private},
+    logger::Logger,
+    stats,
+};
+
+use crate::systemd::{self, Invocation};
+
+/// Where the executable and configuration are installed.
+struct Layout {
+    exe: PathBuf,
+    conf: PathBuf,
+}
+
+impl Layout {
+    #[cfg(unix)]
+    fn new(system: bool) -> Option<Layout> {
+        Some(if system {
+            Layout {
+                exe: PathBuf::from("/usr/local/bin/fishnet"),
+                conf: PathBuf::from("/etc/fishnet.ini"),
+            }
+        } else {
+            let home = env::home_dir()?;
+            Layout {
+                exe: home.join(".local").join("bin").join("fishnet"),
+                conf: config_home(&home).join("fishnet").join("fishnet.ini"),
+            }
+        })
+    }
+
+    #[cfg(windows)]
+    fn new(system: bool) -> Option<Layout> {
+        let dir = if system {
+            PathBuf::from(env::var_os("ProgramFiles")?).join("fishnet")
+        } else {
+            PathBuf::from(env::var_os("LOCALAPPDATA")?).join("fishnet")
+        };
+        Some(Layout {
+            exe: dir.join("fishnet.exe"),
+            conf: dir.join("fishnet.ini"),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn config_home(home: &Path) -> PathBuf {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(|| home.join(".config"))
+}
+
+/// Copies the running executable and configuration to a standard location,
+/// and registers a service to start fishnet automatically.
+pub fn install(mut opt: Opt, system: bool, logger: &Logger) {
+    let Some(layout) = Layout::new(system) else {
+        logger.error("Could not determine installation directory");
+        process::exit(1);
+    };
+
+    #[cfg(unix)]
+    if system && systemd::service_user().is_none() {
+        logger.error("Refusing to run the service as root");
+        logger.error("Install with sudo from the account that should run fishnet");
+        process::exit(1);
+    }
+
+    #[cfg(windows)]
+    if system {
+        logger.error("Refusing to run the scheduled task as SYSTEM");
+        logger.error("Install without --system to start fishnet on logon");
+        process::exit(1);
+    }
+
+    // Step 1: Executable.
+    if let Err(err) = install_exe(&layout.exe) {
+        logger.error(&format!(
+            "Failed to install {}: {err}",
+            layout.exe.display()
+        ));
+        if err.kind() == io::ErrorKind::PermissionDenied && system {
+            logger.error("Installing for all users requires administrator privileges");
+        }
+        process::exit(1);
+    }
+    logger.fishnet_info(&format!("Installed {}", layout.exe.display()));
+
+    // Step 2: Configuration.
+    if !opt.no_conf {
+        match install_conf(opt.conf(), &layout.conf, system) {
+            Ok(true) => {
+                logger.fishnet_info(&format!("Wrote configuration to {}", layout.conf.display()));
+                opt.conf = Some(layout.conf);
+            }
+            Ok(false) => logger.warn(&format!(
+                "No configuration file at {}. Running with command line options only.",
+                opt.conf().display()
+            )),
+            Err(err) => {
+                logger.error(&format!(
+                    "Failed to write configuration to {}: {err}",
+                    layout.conf.display()
+                ));
+                process::exit(1);
+            }
+        }
+    }
+
+    // Step 3: Service.
+    if let Err(err) = install_service(&Invocation::Installed(layout.exe), opt, system, logger) {
+        logger.error(&format!("Failed to install service: {err}"));
+        process::exit(1);
+    }
+}
+
+/// Stops and removes the service, and deletes files that fishnet created,
+/// after confirmation.
+pub fn uninstall(opt: Opt, system: bool, yes: bool, logger: &Logger) {
+    let mut files = service_files(system);
+    if let Some(layout) = Layout::new(system) {
+        files.push(layout.exe);
+        files.push(layout.conf);
+    }
+    files.push(opt.conf().to_owned());
+    files.extend(opt.key_file.clone());
+    files.extend(
+        opt.stats
+            .stats_file
+            .clone()
+            .or_else(stats::default_stats_file),
+    );
+    files.extend(opt.position_cache.clone());
+    let mut seen = HashSet::new();
+    files.retain(|file| fs::canonicalize(file).is_ok_and(|path| seen.insert(path)));
+
+    // Engines and eval files extracted by processes that did not exit
+    // cleanly.
+    let leftovers: Vec<PathBuf> = fs::read_dir(env::temp_dir())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("fishnet-"))
+        .map(|entry| entry.path())
+        .filter(|path| is_stale_temp_dir(path))
+        .collect();
+
+    eprintln!("Will stop and disable the fishnet service, if any, and delete:");
+    for path in files.iter().chain(&leftovers) {
+        eprintln!("  {}", path.display());
+    }
+    if !yes && !confirm("Uninstall now? (default: no) ") {
+        logger.fishnet_info("Nothing removed.");
+        return;
+    }
+
+    if let Err(err) = uninstall_service(system, logger) {
+        logger.warn(&format!("Failed to uninstall service: {err}"));
+    }
+
+    let mut failed = false;
+    for path in files.iter().chain(&leftovers) {
+        let res = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+        match res {
+            Ok(()) => logger.fishnet_info(&format!("Removed {}", path.display())),
+            Err(err) => {
+                logger.error(&format!("Failed to remove {}: {err}", path.display()));
+                failed = true;
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    if service_files(system)
+        .iter()
+        .any(|unit| files.contains(unit))
+    {
+        if let Err(err) = systemctl(system, &["daemon-reload"]) {
+            logger.warn(&format!("Failed to reload systemd: {err}"));
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Whether the directory was created by fishnet, and the process that used
+/// it is gone.
+fn is_stale_temp_dir(path: &Path) -> bool {
+    let Ok(marker) = fs::read_to_string(path.join(assets::TEMP_DIR_MARKER)) else {
+        return false;
+    };
+    marker.trim().parse().is_ok_and(|pid| !is_running(pid))
+}
+
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks whether the process exists.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    // Windows refuses to delete the engines of running processes anyway.
+    false
+}
+
+fn confirm(prompt: &str) -> bool {
+    let mut answer = String::new();
+    eprint!("{prompt}");
+    io::stderr().flush().expect("flush stderr");
+    io::stdin()
+        .read_line(&mut answer)
+        .expect("read confirmation from stdin");
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn install_exe(target: &Path) -> io::Result<()> {
+    let exe = env::current_exe()?;
+    if fs::canonicalize(&exe).ok() == fs::canonicalize(target).ok() {
+        return Ok(());
+    }
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Rename into place, so that a running copy is not disturbed.
+    let tmp = target.with_extension("new");
+    fs::copy(&exe, &tmp)?;
+    fs::rename(&tmp, target)
+}
+
+fn install_conf(source: &Path, target: &Path, system: bool) -> io::Result<bool> {
+    let contents = match fs::read_to_string(source) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::canonicalize(source).ok() != fs::canonicalize(target).ok() {
+        write_private(target, &contents)?;
+    }
+    if system {
+        give_to_service_user(target)?;
+    }
+    Ok(true)
+}
+
+/// Hands a file created with sudo over to the account of the service.
+#[cfg(unix)]
+fn give_to_service_user(path: &Path) -> io::Result<()> {
+    let user = systemd::service_user().ok_or_else(|| io::Error::other("no service user"))?;
+    run("chown", &[&user, &path.to_string_lossy()])
+}
+
+#[cfg(not(unix))]
+fn give_to_service_user(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn run(program: &str, args: &[&str]) -> io::Result<()> {
+    let status = Command::new(program).args(args).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{program} exited with {status}")))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn install_service(
+    invocation: &Invocation,
+    mut opt: Opt,
+    system: bool,
+    logger: &Logger,
+) -> io::Result<()> {
+    let unit = if system {
+        if opt.control_socket.is_none() {
+            opt.control_socket = Some(systemd::SYSTEM_CONTROL_SOCKET.into());
+        }
+        systemd::system_unit(invocation, &opt)
+    } else {
+        systemd::user_unit(invocation, &opt)
+    };
+    let dir = unit_dir(system)?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("fishnet.service");
+    fs::write(&path, unit)?;
+    logger.fishnet_info(&format!("Wrote {}", path.display()));
+
+    systemctl(system, &["daemon-reload"])?;
+    systemctl(system, &["enable", "--now", "fishnet.service"])?;
+    logger.fishnet_info("Enabled and started fishnet.service");
+    logger.info(&format!(
+        "Live view of log: journalctl {} fishnet --follow",
+        if system {
+            "--unit"
+        } else {
+            "--user --user-unit"
+        }
+    ));
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn unit_dir(system: bool) -> io::Result<PathBuf> {
+    if system {
+        Ok(PathBuf::from("/etc/systemd/system"))
+    } else {
+        let home = env::home_dir().ok_or_else(|| io::Error::other("no home directory"))?;
+        Ok(config_home(&home).join("systemd").join("user"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn systemctl(system: bool, args: &[&str]) -> io::Result<()> {
+    let mut all = if system { Vec::new() } else { vec!["--user"] };
+    all.extend_from_slice(args);
+    run("systemctl", &all)
+}
+
+#[cfg(target_os = "linux")]
+fn service_files(system: bool) -> Vec<PathBuf> {
+    unit_dir(system)
+        .map(|dir| vec![dir.join("fishnet.service"), dir.join("fishnet.socket")])
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "linux")]
+fn uninstall_service(system: bool, logger: &Logger) -> io::Result<()> {
+    let units: Vec<String> = service_files(system)
+        .into_iter()
+        .filter(|file| file.exists())
+        .filter_map(|file| Some(file.file_name()?.to_str()?.to_owned()))
+        .collect();
+    if !units.is_empty() {
+        let mut args = vec!["disable", "--now"];
+        args.extend(units.iter().map(String::as_str));
+        systemctl(system, &args)?;
+        logger.fishnet_info(&format!("Stopped and disabled {}", units.join(", ")));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn install_service(
+    invocation: &Invocation,
+    opt: Opt,
+    system: bool,
+    logger: &Logger,
+) -> io::Result<()> {
+    let home = env::home_dir().ok_or_else(|| io::Error::other("no home directory"))?;
+    let path = plist_path(system)?;
+    let log = if system {
+        PathBuf::from("/var/log/fishnet.log")
+    } else {
+        home.join("Library/Logs/fishnet.log")
+    };
+    let command = format!("exec {} run", systemd::exec_start(invocation, &opt));
+    let user_name = match systemd::service_user() {
+        Some(user) if system => {
+            // Otherwise launchd would create the log owned by root.
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&log)?;
+            give_to_service_user(&log)?;
+            format!(
+                "    <key>UserName</key>\n    <string>{}</string>\n",
+                xml_escape(&user)
+            )
+        }
+        _ => String::new(),
+    };
+    let plist = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>org.lichess.fishnet</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>/bin/sh</string>
+        <string>-c</string>
+        <string>{}</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ProcessType</key>
+    <string>Background</string>
+{}    <key>StandardOutPath</key>
+    <string>{}</string>
+    <key>StandardErrorPath</key>
+    <string>{}</string>
+</dict>
+</plist>
+"#,
+        xml_escape(&command),
+        user_name,
+        xml_escape(&log.to_string_lossy()),
+        xml_escape(&log.to_string_lossy()),
+    );
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, plist)?;
+    logger.fishnet_info(&format!("Wrote {}", path.display()));
+
+    let path = path.to_str().expect("printable plist path");
+    let _ = run("launchctl", &["unload", path]);
+    run("launchctl", &["load", "-w", path])?;
+    logger.fishnet_info("Loaded org.lichess.fishnet");
+    logger.info(&format!("Log: {}", log.display()));
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn plist_path(system: bool) -> io::Result<PathBuf> {
+    if system {
+        Ok(PathBuf::from(
+            "/Library/LaunchDaemons/org.lichess.fishnet.plist",
+        ))
+    } else {
+        let home = env::home_dir().ok_or_else(|| io::Error::other("no home directory"))?;
+        Ok(home.join("Library/LaunchAgents/org.lichess.fishnet.plist"))
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn service_files(system: bool) -> Vec<PathBuf> {
+    plist_path(system).into_iter().collect()
+}
+
+#[cfg(target_os = "macos")]
+fn uninstall_service(system: bool, logger: &Logger) -> io::Result<()> {
+    let path = plist_path(system)?;
+    if path.exists() {
+        run(
+            "launchctl",
+            &["unload", "-w", path.to_str().expect("printable plist path")],
+        )?;
+        logger.fishnet_info("Unloaded org.lichess.fishnet");
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Registers a scheduled task, because fishnet does not implement the
+/// protocol of Windows services.
+#[cfg(windows)]
+fn install_service(
+    invocation: &Invocation,
+    opt: Opt,
+    _system: bool,
+    logger: &Logger,
+) -> io::Result<()> {
+    let command = format!("{} run", systemd::exec_start(invocation, &opt));
+    run(
+        "schtasks",
+        &[
+            "/Create", "/F", "/TN", "fishnet", "/TR", &command, "/SC", "ONLOGON", "/RL", "LIMITED",
+        ],
+    )?;
+    run("schtasks", &["/Run", "/TN", "fishnet"])?;
+    logger.fishnet_info("Registered and started scheduled task fishnet");
+    Ok(())
+}
+
+#[cfg(windows)]
+fn service_files(_system: bool) -> Vec<PathBuf> {
+    Vec::new()
+}
+
+#[cfg(windows)]
+fn uninstall_service(_system: bool, logger: &Logger) -> io::Result<()> {
+    if run("schtasks", &["/Query", "/TN", "fishnet"]).is_ok() {
+        let _ = run("schtasks", &["/End", "/TN", "fishnet"]);
+        run("schtasks", &["/Delete", "/F", "/TN", "fishnet"])?;
+        logger.fishnet_info("Removed scheduled task fishnet");
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn service_files(_system: bool) -> Vec<PathBuf> {
+    Vec::new()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn uninstall_service(_system: bool, _logger: &Logger) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn install_service(
+    _invocation: &Invocation,
+    _opt: Opt,
+    _system: bool,
+    _logger: &Logger,
+) -> io::Result<()> {
+    Err(io::Error::other(
+        "no supported service manager on this platform",
+    ))
+}
@@ -1,5 +1,6 @@
-use std::{num::NonZeroU8, time::Duration};
+use std::{num::NonZeroU8, ops::Deref, sync::Arc, time::Duration};
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use shakmaty::{fen::Fen, uci::UciMove, variant::Variant};
 use tokio::{sync::oneshot, time::Instant};
 use url::Url;
@@ -10,17 +11,35 @@ use crate::{
     util::grow_with_and_get_mut,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chunk {
     pub work: Work,
     pub deadline: Instant,
     pub variant: Variant,
     pub flavor: EngineFlavor,
     pub positions: Vec<Position>,
+    /// Speed of this machine, if known with some confidence.
+    pub nnue_nps: Option<u32>,
+    /// Already failed once, and is being retried with a fresh engine.
+    pub retried: bool,
 }
 
 impl Chunk {
     pub const MAX_POSITIONS: usize = 6;
+
+    pub fn affinity(&self) -> Affinity {
+        Affinity {
+            flavor: self.flavor,
+            variant: self.variant,
+        }
+    }
+}
+
+/// Engine and variant that a worker is already set up for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Affinity {
+    pub flavor: EngineFlavor,
+    pub variant: Variant,
 }
 
 #[derive(Debug, Clone)]
@@ -31,7 +50,44 @@ pub struct Position {
     pub skip: bool,
 
     pub root_fen: Fen,
-    pub moves: Vec<UciMove>,
+    pub moves: Moves,
+}
+
+/// Moves from the root position. Positions of the same game share the
+/// moves of the entire game, rather than each holding a copy of its
+/// prefix, which would take quadratic memory for long games.
+#[derive(Debug, Clone)]
+pub struct Moves {
+    game: Arc<[UciMove]>,
+    len: usize,
+}
+
+impl Moves {
+    /// The first `len` moves of the same game.
+    pub fn prefix(&self, len: usize) -> Moves {
+        assert!(len <= self.game.len(), "prefix of moves out of bounds");
+        Moves {
+            game: Arc::clone(&self.game),
+            len,
+        }
+    }
+}
+
+impl From<Vec<UciMove>> for Moves {
+    fn from(moves: Vec<UciMove>) -> Moves {
+        Moves {
+            len: moves.len(),
+            game: moves.into(),
+        }
+    }
+}
+
+impl Deref for Moves {
+    type Target = [UciMove];
+
+    fn deref(&self) -> &[UciMove] {
+        &self.game[..self.len]
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +103,12 @@ pub struct PositionResponse {
     pub nodes: u64,
     pub time: Duration,
     pub nps: Option<u32>,
+    /// The search was stopped before reaching its limits, so the result is
+    /// shallower than requested.
+    pub stopped_early: bool,
+    /// Answered from the position cache, rather than searched by this
+    /// client.
+    pub cached: bool,
 }
 
 impl PositionResponse {
@@ -63,8 +125,8 @@ impl PositionResponse {
 
     pub fn into_matrix(self) -> AnalysisPart {
         AnalysisPart::Matrix {
-            pv: self.pvs.matrix,
-            score: self.scores.matrix,
+            pv: self.pvs.into_dense(),
+            score: self.scores.into_dense(),
             depth: self.depth,
             nodes: self.nodes,
             time: self.time.as_millis() as u64,
@@ -73,37 +135,105 @@ impl PositionResponse {
     }
 }
 
+/// Largest search depth accepted from engines. Stockfish never searches
+/// deeper than its `MAX_PLY`.
+pub const MAX_DEPTH: u8 = 245;
+
+/// Values by multipv and depth. Only the depths actually reported are
+/// stored, but (de)serialization uses the dense format of the protocol.
 #[derive(Debug, Clone)]
 pub struct Matrix<T> {
-    matrix: Vec<Vec<Option<T>>>,
+    rows: Vec<Vec<(u8, T)>>,
+}
+
+impl<T> Default for Matrix<T> {
+    fn default() -> Matrix<T> {
+        Matrix::new()
+    }
 }
 
 impl<T> Matrix<T> {
     pub fn new() -> Matrix<T> {
-        Matrix { matrix: Vec::new() }
+        Matrix { rows: Vec::new() }
     }
 
     pub fn set(&mut self, multipv: NonZeroU8, depth: u8, v: T) {
-        let row = grow_with_and_get_mut(&mut self.matrix, usize::from(multipv.get() - 1), Vec::new);
-        *grow_with_and_get_mut(row, usize::from(depth), || None) = Some(v);
+        let row = grow_with_and_get_mut(&mut self.rows, usize::from(multipv.get() - 1), Vec::new);
+        match row.binary_search_by_key(&depth, |&(d, _)| d) {
+            Ok(i) => row[i].1 = v,
+            Err(i) => row.insert(i, (depth, v)),
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.rows.iter().flatten().map(|(_, v)| v)
     }
 
     pub fn best(&self) -> Option<&T> {
-        self.matrix
-            .first()
-            .and_then(|row| row.last().and_then(|v| v.as_ref()))
+        self.rows.first().and_then(|row| row.last().map(|(_, v)| v))
+    }
+
+    pub fn into_dense(self) -> Vec<Vec<Option<T>>> {
+        self.rows.into_iter().map(densify).collect()
+    }
+}
+
+fn densify<T>(row: impl IntoIterator<Item = (u8, T)>) -> Vec<Option<T>> {
+    let mut dense = Vec::new();
+    for (depth, v) in row {
+        *grow_with_and_get_mut(&mut dense, usize::from(depth), || None) = Some(v);
+    }
+    dense
+}
+
+impl<T: Serialize> Serialize for Matrix<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let dense: Vec<Vec<Option<&T>>> = self
+            .rows
+            .iter()
+            .map(|row| densify(row.iter().map(|(depth, v)| (*depth, v))))
+            .collect();
+        dense.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Matrix<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Matrix<T>, D::Error> {
+        let dense = Vec::<Vec<Option<T>>>::deserialize(deserializer)?;
+        Ok(Matrix {
+            rows: dense
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .enumerate()
+                        .take(usize::from(MAX_DEPTH) + 1)
+                        .filter_map(|(depth, v)| Some((depth as u8, v?)))
+                        .collect()
+                })
+                .collect(),
+        })
     }
 }
 
 #[derive(Debug)]
 pub struct ChunkFailed {
     pub batch_id: BatchId,
+    /// The engine did not finish before the deadline, as opposed to
+    /// crashing.
+    pub timed_out: bool,
+    /// Chunk to try once more, if the batch is still worth completing.
+    pub retry: Option<Chunk>,
+    /// Positions of the chunk that were completed before the failure, to
+    /// be salvaged with a final progress report.
+    pub completed: Vec<PositionResponse>,
 }
 
 #[derive(Debug)]
 pub struct Pull {
     pub responses: Result<Vec<PositionResponse>, ChunkFailed>,
     pub callback: oneshot::Sender<Chunk>,
+    /// Preferably continue with chunks for the same engine and variant.
+    pub affinity: Option<Affinity>,
 }
 
 impl Pull {
@@ -112,7 +242,8 @@ impl Pull {
     ) -> (
         Result<Vec<PositionResponse>, ChunkFailed>,
         oneshot::Sender<Chunk>,
+        Option<Affinity>,
     ) {
-        (self.responses, self.callback)
+        (self.responses, self.callback, self.affinity)
     }
 }
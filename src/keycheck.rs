@@ -0,0 +1,82 @@
+//! Remembers successful key validations, so that a transient outage of the
+//! endpoint does not prevent starting with a key that was recently accepted.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::configure::{Endpoint, Key};
+
+/// How long a successful validation is trusted while the endpoint is
+/// unreachable.
+pub const GRACE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+fn default_key_check_file() -> Option<PathBuf> {
+    env::home_dir().map(|dir| dir.join(".fishnet-key-check"))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Validation {
+    key_sha256: String,
+    validated_at: u64,
+}
+
+fn key_sha256(key: &Key) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, key.0.as_bytes());
+    digest.as_ref().iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").expect("write to string");
+        hex
+    })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn load(path: &Path) -> BTreeMap<String, Validation> {
+    fs::read(path)
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Records that the endpoint just accepted the key. Only a hash of the key
+/// is stored.
+pub fn record(endpoint: &Endpoint, key: &Key) {
+    let Some(path) = default_key_check_file() else {
+        return;
+    };
+    let mut validations = load(&path);
+    validations.insert(
+        endpoint.host(),
+        Validation {
+            key_sha256: key_sha256(key),
+            validated_at: unix_now(),
+        },
+    );
+    if let Ok(contents) = serde_json::to_vec(&validations) {
+        let _ = fs::write(&path, contents);
+    }
+}
+
+/// Time since the endpoint last accepted the key, if known.
+pub fn last_validated(endpoint: &Endpoint, key: &Key) -> Option<Duration> {
+    let validations = load(&default_key_check_file()?);
+    let validation = validations.get(&endpoint.host())?;
+    (validation.key_sha256 == key_sha256(key))
+        .then(|| Duration::from_secs(unix_now().saturating_sub(validation.validated_at)))
+}
+
+/// Whether the endpoint accepted the key within the grace period.
+pub fn is_recently_validated(endpoint: &Endpoint, key: &Key) -> bool {
+    last_validated(endpoint, key).is_some_and(|age| age < GRACE_PERIOD)
+}
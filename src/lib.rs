@@ -0,0 +1,36 @@
+//! Distributed Stockfish analysis for lichess.org.
+//!
+//! The `fishnet` binary is a thin layer on top of this library. The analysis
+//! pipeline (API client, queue, engine management and bundled assets) can
+//! also be embedded into other projects.
+
+#![deny(unsafe_code)]
+
+pub mod admin;
+pub mod api;
+pub mod archive;
+pub mod assets;
+pub mod book;
+pub mod cache;
+pub mod client;
+pub mod configure;
+pub mod container;
+pub mod control;
+pub mod dns;
+#[cfg(windows)]
+pub mod eventlog;
+pub mod fatal;
+pub mod ipc;
+pub mod keycheck;
+pub mod logger;
+pub mod netwatch;
+pub mod queue;
+pub mod replay;
+pub mod sink;
+pub mod spotcheck;
+pub mod stats;
+pub mod stockfish;
+pub mod suspend;
+pub mod syzygy;
+pub mod transcript;
+pub mod util;
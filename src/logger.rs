@@ -4,14 +4,20 @@ use std::{
     io::{IsTerminal as _, Write as _},
     num::NonZeroUsize,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+use serde::Serialize;
+use serde_with::{DisplayFromStr, DurationMilliSeconds, serde_as};
 use shakmaty::variant::Variant;
 use url::Url;
 
+#[cfg(windows)]
+use crate::eventlog::{EventLevel, EventLog};
 use crate::{
     api::{BatchId, PositionIndex},
     configure::Verbose,
+    container::Container,
     ipc::{Chunk, Position, PositionResponse},
     util::NevermindExt as _,
 };
@@ -21,7 +27,11 @@ pub struct Logger {
     verbose: Verbose,
     stderr: bool,
     terminal: bool,
+    progress_interval: Option<Duration>,
     state: Arc<Mutex<LoggerState>>,
+    events: Option<Arc<Mutex<Box<dyn io::Write + Send>>>>,
+    #[cfg(windows)]
+    event_log: Option<Arc<EventLog>>,
 }
 
 impl Logger {
@@ -29,11 +39,41 @@ impl Logger {
         Logger {
             verbose,
             stderr,
-            terminal: io::stdout().is_terminal(),
-            state: Arc::new(Mutex::new(LoggerState { progress_line: 0 })),
+            // Container logs would be cluttered with progress bar updates,
+            // even when a pseudo terminal is allocated.
+            terminal: io::stdout().is_terminal() && Container::detect().is_none(),
+            progress_interval: None,
+            state: Arc::new(Mutex::new(LoggerState {
+                progress_line: 0,
+                progress_logged: None,
+            })),
+            events: None,
+            #[cfg(windows)]
+            event_log: None,
         }
     }
 
+    #[cfg(windows)]
+    pub fn with_event_log(mut self, event_log: EventLog) -> Logger {
+        self.event_log = Some(Arc::new(event_log));
+        self
+    }
+
+    pub fn with_progress_json(mut self, sink: Box<dyn io::Write + Send>, stdout: bool) -> Logger {
+        if stdout {
+            // Keep the progress bar from interleaving with events.
+            self.terminal = false;
+        }
+        self.events = Some(Arc::new(Mutex::new(sink)));
+        self
+    }
+
+    /// Log progress at most this often, when not on a terminal.
+    pub fn with_progress_interval(mut self, interval: Duration) -> Logger {
+        self.progress_interval = Some(interval);
+        self
+    }
+
     fn println(&self, line: &str) {
         let mut state = self.state.lock().expect("logger state");
         state.line_feed();
@@ -72,10 +112,18 @@ impl Logger {
     }
 
     pub fn warn(&self, line: &str) {
+        #[cfg(windows)]
+        if let Some(ref event_log) = self.event_log {
+            event_log.report(EventLevel::Warning, line);
+        }
         self.println(&format!("W: {line}"));
     }
 
     pub fn error(&self, line: &str) {
+        #[cfg(windows)]
+        if let Some(ref event_log) = self.event_log {
+            event_log.report(EventLevel::Error, line);
+        }
         self.println(&format!("E: {line}"));
     }
 
@@ -83,12 +131,10 @@ impl Logger {
     where
         P: Into<ProgressAt>,
     {
+        let progress = progress.into();
         let line = format!(
             "{} {} cores, {} queued, latest: {}",
-            queue,
-            queue.cores,
-            queue.pending,
-            progress.into()
+            queue, queue.cores, queue.pending, progress
         );
         if self.terminal {
             let mut state = self.state.lock().expect("logger state");
@@ -101,10 +147,85 @@ impl Logger {
             state.progress_line = line.len();
         } else if self.verbose.level > 0 {
             self.println(&line);
+        } else if let Some(interval) = self.progress_interval {
+            let now = Instant::now();
+            let due = {
+                let mut state = self.state.lock().expect("logger state");
+                let due = state
+                    .progress_logged
+                    .is_none_or(|logged| logged + interval <= now);
+                if due {
+                    state.progress_logged = Some(now);
+                }
+                due
+            };
+            if due {
+                self.println(&format!(
+                    "P: {} cores, {} queued, latest: {}",
+                    queue.cores, queue.pending, progress
+                ));
+            }
+        }
+    }
+
+    pub fn event(&self, event: ProgressEvent) {
+        if let Some(ref events) = self.events {
+            let mut sink = events.lock().expect("progress json sink");
+            let mut line = serde_json::to_vec(&event).expect("serialize progress event");
+            line.push(b'\n');
+            // Write errors are not fatal, e.g. when the reading end of a
+            // FIFO has gone away.
+            sink.write_all(&line).nevermind("write progress event");
+            sink.flush().nevermind("flush progress events");
         }
     }
 }
 
+#[serde_as]
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    BatchAcquired {
+        #[serde_as(as = "DisplayFromStr")]
+        batch_id: BatchId,
+        #[serde_as(as = "Option<DisplayFromStr>")]
+        url: Option<Url>,
+        #[serde_as(as = "DisplayFromStr")]
+        variant: Variant,
+        positions: usize,
+        queued: usize,
+    },
+    PositionDone {
+        #[serde_as(as = "DisplayFromStr")]
+        batch_id: BatchId,
+        position_index: usize,
+        queued: usize,
+    },
+    BatchCompleted {
+        #[serde_as(as = "DisplayFromStr")]
+        batch_id: BatchId,
+        #[serde_as(as = "Option<DisplayFromStr>")]
+        url: Option<Url>,
+        positions: u64,
+        nodes: u64,
+        nps: Option<u32>,
+    },
+    Idle {
+        #[serde_as(as = "DurationMilliSeconds<u64>")]
+        wait_ms: Duration,
+        reason: IdleReason,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleReason {
+    Backlog,
+    NoJob,
+    ServerPause,
+    MemoryBudget,
+}
+
 pub struct ProgressAt {
     pub batch_id: BatchId,
     pub batch_url: Option<Url>,
@@ -161,6 +282,7 @@ impl From<&PositionResponse> for ProgressAt {
 
 struct LoggerState {
     pub progress_line: usize,
+    pub progress_logged: Option<Instant>,
 }
 
 impl LoggerState {
@@ -1,23 +1,19 @@
 #![deny(unsafe_code)]
 
-mod api;
-mod assets;
-mod configure;
-mod ipc;
-mod logger;
-mod queue;
-mod stats;
-mod stockfish;
+mod install;
+mod supervise;
 mod systemd;
+#[cfg(feature = "auto-update")]
 mod update;
-mod util;
 
 use std::{
-    env, io,
+    env, fs, io,
     io::IsTerminal as _,
-    path::PathBuf,
+    mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
     process,
-    sync::Arc,
+    sync::{Arc, Mutex, OnceLock},
     thread,
     time::{Duration, Instant},
 };
@@ -26,30 +22,100 @@ use reqwest::Client;
 use shell_escape::escape;
 use tokio::{
     signal,
-    sync::{mpsc, oneshot},
-    task::JoinSet,
-    time::{sleep, sleep_until},
+    sync::{mpsc, oneshot, watch},
+    task,
+    task::{JoinHandle, JoinSet},
+    time::{sleep, sleep_until, timeout},
 };
 
-use crate::{
+#[cfg(feature = "auto-update")]
+use crate::update::{UpdateSuccess, auto_update};
+#[cfg(windows)]
+use fishnet::eventlog::EventLog;
+use fishnet::{
+    admin,
+    admin::{AdminCommand, AdminServer, AdminState},
+    api,
+    api::{AcquireResponseBody, ApiStub, BatchId, PositionIndex},
+    archive::Archive,
     assets::{Assets, ByEngineFlavor, Cpu, EngineFlavor},
-    configure::{Command, Cores, CpuPriority, Opt},
-    ipc::{Chunk, ChunkFailed, Pull},
+    book::PolyglotBook,
+    cache::{CacheKey, PositionCache},
+    client::AnalysisClient,
+    configure,
+    configure::{AssetsCommand, Command, Cores, CpuFeatures, CpuPriority, NodeMultipliers, Opt},
+    container::Container,
+    control,
+    control::{ControlServer, StatusBoard},
+    dns::SystemResolver,
+    fatal::Fatal,
+    ipc::{Chunk, ChunkFailed, PositionResponse, Pull},
+    keycheck,
     logger::{Logger, ProgressAt},
-    update::{UpdateSuccess, auto_update},
+    netwatch::NetworkWatcher,
+    queue, replay,
+    replay::ReplayEngine,
+    sink,
+    sink::{ArchiveSink, ResultSink},
+    stockfish,
+    stockfish::{EngineId, EngineOptions, StockfishStub},
+    suspend::SuspendDetector,
+    syzygy::Tablebases,
+    transcript::Transcript,
+    util,
     util::{RandomizedBackoff, dot_thousands},
 };
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
-    let client = configure_client();
-    let opt = configure::parse_and_configure(&client).await;
-    let logger = Logger::new(opt.verbose, opt.command.is_some_and(Command::is_systemd));
+fn main() {
+    control::take_systemd_listen_env();
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("tokio runtime")
+        .block_on(run_main());
+}
 
+async fn run_main() {
+    let opt = configure::parse();
+    if let Some(seed) = opt.backoff_seed {
+        util::set_backoff_seed(seed);
+    }
+    let client = configure_client(&opt);
+    let opt = configure::configure(opt, &client).await;
+    let mut logger = Logger::new(opt.verbose, opt.stdout_reserved());
+    if let Some(interval) = opt.progress_interval {
+        logger = logger.with_progress_interval(interval.into());
+    }
+    #[cfg(windows)]
+    if opt.event_log {
+        match EventLog::register() {
+            Ok(event_log) => logger = logger.with_event_log(event_log),
+            Err(err) => logger.warn(&format!("Failed to open Windows Event Log: {err}")),
+        }
+    }
+    if let Some(ref path) = opt.progress_json {
+        if opt.progress_json_on_stdout() {
+            logger = logger.with_progress_json(Box::new(io::stdout()), true);
+        } else {
+            // Blocks until a reader attaches, if the path is a FIFO.
+            match fs::OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => logger = logger.with_progress_json(Box::new(file), false),
+                Err(err) => {
+                    logger.error(&format!(
+                        "Failed to open progress json file {}: {err}",
+                        path.display()
+                    ));
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "auto-update")]
     if opt.auto_update {
         let current_exe = env::current_exe().expect("current exe");
         match auto_update(
-            !opt.command.is_some_and(Command::is_systemd),
+            !opt.command.as_ref().is_some_and(Command::is_systemd),
             &client,
             &logger,
         )
@@ -67,11 +133,214 @@ async fn main() {
     }
 
     match opt.command {
+        Some(Command::Run) | None if opt.supervise && !supervise::is_supervised() => {
+            supervise::supervise(&logger).await
+        }
         Some(Command::Run) | None => run(opt, &client, &logger).await,
         Some(Command::Systemd) => systemd::systemd_system(opt),
         Some(Command::SystemdUser) => systemd::systemd_user(opt),
-        Some(Command::Configure) => (),
+        Some(Command::SystemdSocket) => systemd::systemd_system_socket(opt),
+        Some(Command::SystemdUserSocket) => systemd::systemd_user_socket(opt),
+        Some(Command::Install { system }) => install::install(opt, system, &logger),
+        Some(Command::Uninstall { system, yes }) => install::uninstall(opt, system, yes, &logger),
+        Some(Command::Configure { .. }) => (),
         Some(Command::License) => license(&logger),
+        Some(Command::Status) => status(opt, &logger).await,
+        Some(Command::Account) => account(opt, &client, &logger).await,
+        Some(Command::QueueStatus) => queue_status(opt, &client, &logger).await,
+        Some(Command::Selftest) => selftest(&opt, &logger).await,
+        Some(Command::Replay {
+            ref fixture,
+            engine,
+        }) => replay(&opt, fixture, engine, &logger).await,
+        Some(Command::Assets {
+            command: AssetsCommand::List,
+        }) => list_assets(&opt, &logger),
+        Some(Command::Assets {
+            command: AssetsCommand::Extract { ref dir, all },
+        }) => extract_assets(&opt, dir, all, &logger),
+    }
+}
+
+async fn selftest(opt: &Opt, logger: &Logger) {
+    let cpu = asset_cpu(opt);
+    logger.info(&format!("CPU features: {cpu}"));
+    let assets = Assets::prepare(cpu).unwrap_or_else(|err| assets_failed(err, logger));
+
+    let mut failed = false;
+    for flavor in [EngineFlavor::Official, EngineFlavor::MultiVariant] {
+        let Some(ref sf) = *assets.stockfish.get(flavor) else {
+            continue;
+        };
+        logger.info(&format!("Running bench with {} ...", sf.name));
+        match (stockfish::bench(&sf.path).await, flavor.bench_signature()) {
+            (Ok(Some(nodes)), Some(expected)) if nodes == expected => {
+                logger.fishnet_info(&format!("{}: bench {nodes} as expected", sf.name));
+            }
+            (Ok(Some(nodes)), Some(expected)) => {
+                logger.error(&format!(
+                    "{}: bench {nodes}, but expected {expected}. The engine may be miscompiled or incompatible with this CPU.",
+                    sf.name
+                ));
+                failed = true;
+            }
+            (Ok(Some(nodes)), None) => {
+                logger.warn(&format!(
+                    "{}: bench {nodes}, but no signature was recorded when building",
+                    sf.name
+                ));
+            }
+            (Ok(None), _) => {
+                logger.error(&format!("{}: bench did not report nodes searched", sf.name));
+                failed = true;
+            }
+            (Err(err), _) => {
+                logger.error(&format!("{}: bench failed: {err}", sf.name));
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn assets_failed(err: io::Error, logger: &Logger) -> ! {
+    if err.kind() == io::ErrorKind::Unsupported {
+        Fatal::NoEngine.exit(&format!("Unsupported CPU: {err}"), logger)
+    } else {
+        Fatal::AssetExtraction.exit(
+            &format!("Failed to extract bundled Stockfish: {err}"),
+            logger,
+        )
+    }
+}
+
+async fn account(opt: Opt, client: &Client, logger: &Logger) {
+    let endpoint = opt.endpoint();
+    let mut api = api::spawn(endpoint.clone(), opt.key, client.clone(), logger.clone());
+    match api.account().await {
+        Some(Ok(account)) => {
+            println!("Endpoint: {endpoint}");
+            print!("{account}");
+        }
+        Some(Err(err)) => {
+            logger.error(&format!("Failed to query account at {endpoint}: {err}"));
+            process::exit(1);
+        }
+        None => process::exit(1), // server/network error already logged
+    }
+}
+
+async fn queue_status(opt: Opt, client: &Client, logger: &Logger) {
+    let endpoint = opt.endpoint();
+    let mut api = api::spawn(endpoint.clone(), None, client.clone(), logger.clone());
+    match api.status().await {
+        Some(status) => {
+            println!("Endpoint: {endpoint}");
+            println!("User queue: {}", status.user);
+            println!("System queue: {}", status.system);
+        }
+        None => {
+            logger.error(&format!("Queue status not available at {endpoint}"));
+            process::exit(1);
+        }
+    }
+}
+
+fn asset_cpu(opt: &Opt) -> Cpu {
+    opt.cpu_features
+        .as_ref()
+        .map_or_else(Cpu::detect, CpuFeatures::cpu)
+}
+
+async fn replay(opt: &Opt, fixture: &Path, engine: bool, logger: &Logger) {
+    let body: AcquireResponseBody = match fs::read(fixture)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|err| err.to_string()))
+    {
+        Ok(body) => body,
+        Err(err) => {
+            logger.error(&format!("Failed to read {}: {err}", fixture.display()));
+            process::exit(1);
+        }
+    };
+
+    let engine = if engine {
+        let cpu = asset_cpu(opt);
+        logger.info(&format!("CPU features: {cpu}"));
+        let assets = Assets::prepare(cpu).unwrap_or_else(|err| assets_failed(err, logger));
+        let cores = opt.cores.unwrap_or(Cores::Auto).number(opt.topology);
+        ReplayEngine::Real(AnalysisClient::new(assets, cores, logger.clone()))
+    } else {
+        ReplayEngine::Stub
+    };
+
+    match replay::replay(body, &engine, logger).await {
+        Ok(replay) => println!(
+            "{}",
+            serde_json::to_string_pretty(&replay).expect("serialize replay")
+        ),
+        Err(err) => {
+            logger.error(&format!("Replay of {} failed: {err}", fixture.display()));
+            process::exit(1);
+        }
+    }
+}
+
+fn extract_assets(opt: &Opt, dir: &Path, all: bool, logger: &Logger) {
+    match Assets::export(asset_cpu(opt), dir, all) {
+        Ok(names) => {
+            for name in names {
+                logger.info(&format!("Extracted {}", dir.join(name).display()));
+            }
+        }
+        Err(err) => {
+            logger.error(&format!(
+                "Failed to extract assets to {}: {err}",
+                dir.display()
+            ));
+            process::exit(1);
+        }
+    }
+}
+
+fn list_assets(opt: &Opt, logger: &Logger) {
+    let cpu = asset_cpu(opt);
+    logger.headline(&format!(
+        "Embedded assets (* selected for CPU features: {cpu})"
+    ));
+    match Assets::list(cpu) {
+        Ok(entries) => {
+            for entry in entries {
+                println!(
+                    "{} {:<48} {:>8.1} MiB  {}",
+                    if entry.selected { '*' } else { ' ' },
+                    entry.name,
+                    entry.size as f64 / (1024.0 * 1024.0),
+                    entry.requirements,
+                );
+            }
+        }
+        Err(err) => {
+            logger.error(&format!("Failed to read embedded assets: {err}"));
+            process::exit(1);
+        }
+    }
+}
+
+async fn status(opt: Opt, logger: &Logger) {
+    let path = opt.control_socket();
+    match control::query(path.clone()).await {
+        Ok(report) => print!("{report}"),
+        Err(err) => {
+            logger.error(&format!(
+                "Failed to query running instance at {}: {err}",
+                path.display()
+            ));
+            process::exit(1);
+        }
     }
 }
 
@@ -80,6 +349,16 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
 
     let endpoint = opt.endpoint();
     logger.info(&format!("Endpoint: {endpoint}"));
+    for fallback in opt.fallback_endpoints() {
+        logger.info(&format!("Fallback endpoint: {fallback}"));
+    }
+    for result_sink in &opt.result_sinks {
+        logger.info(&format!("Result sink: {result_sink}"));
+    }
+
+    if let Some(container) = Container::detect() {
+        logger.info(&format!("Container: {container}"));
+    }
 
     logger.info(&format!(
         "Backlog: Join queue if user backlog >= {:?} or system backlog >= {:?}",
@@ -87,14 +366,34 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
         Duration::from(opt.backlog.system.unwrap_or_default())
     ));
 
-    let cpu = Cpu::detect();
-    logger.info(&format!("CPU features: {cpu}"));
+    let cpu = match opt.cpu_features {
+        Some(ref features) => {
+            let cpu = features.cpu();
+            logger.warn(&format!(
+                "CPU features: {cpu} (overridden with --cpu-features {features})"
+            ));
+            cpu
+        }
+        None => {
+            let cpu = Cpu::detect();
+            logger.info(&format!("CPU features: {cpu}"));
+            cpu
+        }
+    };
 
-    let assets = Assets::prepare(cpu).expect("prepared bundled stockfish");
+    let assets = match env::var_os(supervise::ASSET_DIR_VAR) {
+        Some(dir) => Assets::prepare_in(cpu, Path::new(&dir)),
+        None => Assets::prepare(cpu),
+    }
+    .unwrap_or_else(|err| assets_failed(err, logger));
     logger.info(&format!(
-        "Engines: {}, {} (for GPLv3, run: {} license)",
-        assets.stockfish.official.name,
-        assets.stockfish.multi_variant.name,
+        "Engines: {} (for GPLv3, run: {} license)",
+        [EngineFlavor::Official, EngineFlavor::MultiVariant]
+            .into_iter()
+            .filter_map(|flavor| assets.stockfish.get(flavor).as_ref())
+            .map(|stockfish| stockfish.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
         escape(
             env::args_os()
                 .next()
@@ -104,15 +403,227 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
         )
     ));
 
-    let cores = opt.cores.unwrap_or(Cores::Auto).number();
+    let cores = opt.cores.unwrap_or(Cores::Auto).number(opt.topology);
     logger.info(&format!("Cores: {cores}"));
+    if let Some(spot_check) = opt.spot_check {
+        logger.info(&format!("Spot checks: {spot_check} of positions"));
+    }
+    if !opt.node_multipliers.is_empty() {
+        logger.info(&format!(
+            "Node multipliers: {}",
+            opt.node_multipliers
+                .iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    // Check key. If the endpoint is unreachable, start anyway if the key
+    // was accepted recently, otherwise wait until it can be checked.
+    if let Some(ref key) = opt.key {
+        let mut api = api::spawn(
+            endpoint.clone(),
+            Some(key.clone()),
+            client.clone(),
+            logger.clone(),
+        );
+        let mut backoff = RandomizedBackoff::default();
+        loop {
+            match api.check_key().await {
+                Some(Ok(())) => {
+                    logger.info("Key: accepted");
+                    break;
+                }
+                Some(Err(err)) => Fatal::InvalidKey.exit(
+                    &format!("Key: {err}. Get a new key and run: fishnet configure"),
+                    logger,
+                ),
+                None => match keycheck::last_validated(&endpoint, key) {
+                    Some(age) if age < keycheck::GRACE_PERIOD => {
+                        logger.warn(&format!(
+                            "Key: could not check, but accepted {}h ago. Starting anyway.",
+                            age.as_secs() / 3600
+                        ));
+                        break;
+                    }
+                    _ => {
+                        let backoff = backoff.next();
+                        logger.warn(&format!(
+                            "Key: could not check, and not accepted recently. Retrying in {backoff:?}."
+                        ));
+                        sleep(backoff).await;
+                    }
+                },
+            }
+        }
+    }
+
+    // Open opening book.
+    let book = opt
+        .book
+        .as_ref()
+        .and_then(|path| match PolyglotBook::open(path) {
+            Ok(book) => {
+                logger.info(&format!(
+                    "Opening book: {} entries in {}",
+                    book.len(),
+                    path.display()
+                ));
+                Some(Arc::new(book))
+            }
+            Err(err) => {
+                logger.error(&format!(
+                    "Failed to open opening book {}: {err}",
+                    path.display()
+                ));
+                None
+            }
+        });
+
+    // Find tablebases.
+    let tablebases =
+        opt.syzygy_path
+            .clone()
+            .and_then(|path| match Tablebases::scan(path.clone()) {
+                Ok(tablebases) => {
+                    logger.info(&format!(
+                        "Tablebases: {} tables in {}",
+                        tablebases.len(),
+                        path.to_string_lossy()
+                    ));
+                    Some(Arc::new(tablebases))
+                }
+                Err(err) => {
+                    logger.error(&format!(
+                        "Failed to scan tablebases in {}: {err}",
+                        path.to_string_lossy()
+                    ));
+                    None
+                }
+            });
+
+    // Open position cache.
+    let position_cache = opt.position_cache.clone().and_then(|path| {
+        match PositionCache::open(path.clone(), opt.position_cache_size.unwrap_or(100_000)) {
+            Ok(cache) => {
+                logger.info(&format!(
+                    "Position cache: {} positions in {}",
+                    cache.len(),
+                    path.display()
+                ));
+                Some(Arc::new(Mutex::new(cache)))
+            }
+            Err(err) => {
+                logger.error(&format!(
+                    "Failed to open position cache {}: {err}",
+                    path.display()
+                ));
+                None
+            }
+        }
+    });
+    // Open analysis archive.
+    let archive = opt
+        .archive_dir
+        .clone()
+        .and_then(|dir| match Archive::new(dir.clone()) {
+            Ok(archive) => {
+                logger.info(&format!("Archive: {}", dir.display()));
+                Some(archive)
+            }
+            Err(err) => {
+                logger.error(&format!(
+                    "Failed to open archive directory {}: {err}",
+                    dir.display()
+                ));
+                None
+            }
+        });
+
+    let shortcuts = Shortcuts {
+        book,
+        cache: position_cache,
+    };
+
+    // Save the position cache periodically, so that it survives crashes.
+    if let Some(cache) = shortcuts.cache.clone() {
+        let logger = logger.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(Duration::from_secs(10 * 60)).await;
+                let cache = cache.clone();
+                let res =
+                    task::spawn_blocking(move || cache.lock().expect("position cache").save())
+                        .await
+                        .expect("join");
+                if let Err(err) = res {
+                    logger.error(&format!("Failed to save position cache: {err}"));
+                }
+            }
+        });
+    }
+
+    // Open control socket for fishnet status.
+    let status_board = StatusBoard::new(cores, endpoint.host());
+    let control = match ControlServer::bind(opt.control_socket()).await {
+        Ok(control) => {
+            if control.is_socket_activated() {
+                logger.debug("Control socket: inherited from systemd");
+            } else {
+                logger.debug(&format!(
+                    "Control socket: {}",
+                    opt.control_socket().display()
+                ));
+            }
+            control
+        }
+        Err(err) => {
+            if err.kind() == io::ErrorKind::Unsupported {
+                logger.debug(&format!("No control socket: {err}"));
+            } else {
+                logger.warn(&format!(
+                    "Failed to open control socket {}: {err}",
+                    opt.control_socket().display()
+                ));
+            }
+            ControlServer::disabled()
+        }
+    };
+
+    // Open admin API.
+    let mut admin = match (opt.admin_port, opt.admin_token_file.as_deref()) {
+        (Some(port), Some(token_file)) => match admin::load_or_create_token(token_file) {
+            Ok(token) => match AdminServer::bind(port, token, logger.clone()).await {
+                Ok(admin) => {
+                    logger.info(&format!(
+                        "Admin API: http://127.0.0.1:{port} (token in {})",
+                        token_file.display()
+                    ));
+                    admin
+                }
+                Err(err) => {
+                    logger.error(&format!("Failed to bind admin API to port {port}: {err}"));
+                    AdminServer::disabled()
+                }
+            },
+            Err(err) => {
+                logger.error(&format!(
+                    "Failed to read admin token from {}: {err}",
+                    token_file.display()
+                ));
+                AdminServer::disabled()
+            }
+        },
+        _ => AdminServer::disabled(),
+    };
 
     // Install handler for SIGTERM.
     #[cfg(unix)]
     let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate())
         .expect("install handler for sigterm");
     #[cfg(windows)]
-    let mut sig_term = signal::windows::ctrl_break().expect("install handler for ctrl+break");
+    let mut sig_term = ConsoleStop::new().expect("install handlers for console events");
 
     // Install handler for SIGINT.
     #[cfg(unix)]
@@ -123,10 +634,43 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
 
     // To wait for workers and API actor before shutdown.
     let mut join_set = JoinSet::new();
+    let mut workers = JoinSet::new();
+
+    // Engines report their names once started.
+    let engine_ids = Arc::new(ByEngineFlavor {
+        official: EngineId {
+            arch: assets
+                .stockfish
+                .official
+                .as_ref()
+                .map(|sf| sf.arch().to_owned()),
+            name: OnceLock::new(),
+        },
+        multi_variant: EngineId {
+            arch: assets
+                .stockfish
+                .multi_variant
+                .as_ref()
+                .map(|sf| sf.arch().to_owned()),
+            name: OnceLock::new(),
+        },
+    });
 
     // Spawn API actor.
+    let fallback_endpoints = opt.fallback_endpoints().to_vec();
     let (api, api_actor) = api::channel(endpoint.clone(), opt.key, client.clone(), logger.clone());
-    join_set.spawn(api_actor.run());
+    join_set.spawn(
+        api_actor
+            .with_status_board(status_board.clone())
+            .with_engine_ids(engine_ids.clone())
+            .with_contributor_tag(opt.contributor_tag.clone())
+            .with_failover(
+                fallback_endpoints,
+                opt.failover_after.unwrap_or_default().into(),
+            )
+            .with_max_upload_rate(opt.max_upload_rate.map(Into::into))
+            .run(),
+    );
 
     let to_stop = if io::stdout().is_terminal() {
         "CTRL-C"
@@ -135,27 +679,93 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
     };
     logger.headline(&format!("Running ({to_stop} to stop) ..."));
 
+    // Engines may report analysed positions ahead of complete chunks.
+    let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+
+    let engine_options = EngineOptions {
+        node_multipliers: NodeMultipliers::from(opt.node_multipliers),
+        tablebases,
+        ids: engine_ids.clone(),
+        recycle_chunks: opt.recycle_engine_chunks,
+        recycle_age: opt.recycle_engine_age.map(Duration::from),
+        cpu_priority: opt.cpu_priority.unwrap_or_default(),
+        keep_hash: opt.keep_hash,
+        move_overhead: opt.move_overhead.map(Duration::from),
+        progress: opt.report_each_position.then_some(progress_tx),
+        record_uci: opt.record_uci.clone(),
+    };
+
+    // Optionally scale the number of busy workers with the server queue.
+    let (active_tx, active) = watch::channel(ActiveWorkers {
+        scaled: cores.get(),
+        limit: cores.get(),
+        paused: false,
+    });
+    if let Some(min_cores) = opt.min_cores.filter(|&min_cores| min_cores < cores) {
+        active_tx.send_modify(|active| active.scaled = min_cores.get());
+        tokio::spawn(scale_workers(
+            api.clone(),
+            min_cores,
+            cores,
+            active_tx.clone(),
+            logger.clone(),
+        ));
+    }
+
     // Spawn queue actor.
+    let summary_api = api.clone();
     let (mut queue, queue_actor) = queue::channel(
         opt.stats,
         opt.backlog,
         cores,
         api,
         opt.max_backoff.unwrap_or_default(),
+        opt.spot_check.unwrap_or_default(),
         logger.clone(),
     );
-    join_set.spawn(queue_actor.run());
+    join_set.spawn(
+        queue_actor
+            .with_max_multipv(opt.max_multipv)
+            .with_max_queued_chunks(opt.max_queued_chunks)
+            .with_max_pending_memory(opt.max_pending_memory)
+            .with_keep_alive(opt.keep_alive.unwrap_or_default())
+            .with_status_board(status_board.clone())
+            .with_result_sinks(
+                sink::from_specs(opt.result_sinks, client, logger)
+                    .into_iter()
+                    .chain(archive.map(|archive| -> Box<dyn ResultSink> {
+                        Box::new(ArchiveSink::spawn(archive, engine_ids, logger.clone()))
+                    }))
+                    .collect(),
+            )
+            .run(),
+    );
 
     // Spawn workers. Workers handle engine processes and send their results
     // to tx, thereby requesting more work.
+    let engines = Engines {
+        assets: Arc::new(assets),
+        options: engine_options,
+        idle: Arc::default(),
+    };
     let mut rx = {
-        let assets = Arc::new(assets);
         let (tx, rx) = mpsc::channel::<Pull>(cores.get());
         for i in 0..cores.get() {
-            let assets = assets.clone();
+            let engines = engines.clone();
             let tx = tx.clone();
             let logger = logger.clone();
-            join_set.spawn(worker(i, assets, tx, logger));
+            let status_board = status_board.clone();
+            let shortcuts = shortcuts.clone();
+            let active = active.clone();
+            workers.spawn(worker(
+                i,
+                engines,
+                tx,
+                status_board,
+                shortcuts,
+                active,
+                logger,
+            ));
         }
         rx
     };
@@ -163,21 +773,29 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
     // Set scheduling priority.
     match opt.cpu_priority.unwrap_or_default() {
         CpuPriority::Unchanged => (),
-        CpuPriority::Min => {
+        CpuPriority::Min | CpuPriority::Idle => {
             if let Err(err) = set_current_process_min_priority() {
                 logger.warn(&format!("Failed to decrease CPU priority: {err:?}"));
             }
         }
     }
 
-    let mut restart = None;
+    let mut restart: Option<PathBuf> = None;
+    #[cfg(feature = "auto-update")]
     let mut up_to_date = Instant::now();
     let mut summarized = Instant::now();
+    let mut resumed_rx = SuspendDetector::new().spawn();
+    let mut network_watcher = NetworkWatcher::spawn().unwrap_or_else(|err| {
+        logger.debug(&format!("Not watching for network changes: {err}"));
+        NetworkWatcher::disabled()
+    });
     let mut shutdown_soon = false;
+    let mut finish_in_flight = false;
 
     loop {
         // Check for updates from time to time.
         let now = Instant::now();
+        #[cfg(feature = "auto-update")]
         if opt.auto_update
             && !shutdown_soon
             && now.duration_since(up_to_date) >= Duration::from_secs(60 * 60 * 5)
@@ -194,7 +812,7 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
                         .fishnet_info(&format!("Fishnet updated to v{version}. Will restart soon"));
                     restart = Some(current_exe);
                     shutdown_soon = true;
-                    queue.shutdown_soon().await;
+                    queue.shutdown_soon(!opt.no_abort_on_shutdown).await;
                 }
             }
         }
@@ -203,14 +821,29 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
         if now.duration_since(summarized) >= Duration::from_secs(120) {
             summarized = now;
             let (stats, nnue_nps) = queue.stats().await;
-            logger.fishnet_info(&format!(
+            let mut summary = format!(
                 "v{}: {} (nnue), {} batches, {} positions, {} total nodes",
                 env!("CARGO_PKG_VERSION"),
                 nnue_nps,
                 dot_thousands(stats.total_batches),
                 dot_thousands(stats.total_positions),
                 dot_thousands(stats.total_nodes),
-            ));
+            );
+            if !stats.api_errors.is_empty() {
+                summary.push_str(&format!(", api errors: {}", stats.api_errors));
+            }
+            if !stats.failures.is_empty() {
+                summary.push_str(&format!(", failures: {}", stats.failures));
+            }
+            // Do not hold up the main loop while the server is slow.
+            let mut api = summary_api.clone();
+            let logger = logger.clone();
+            tokio::spawn(async move {
+                if let Ok(Some(status)) = timeout(Duration::from_secs(10), api.status()).await {
+                    summary.push_str(&format!(", queue: {status}"));
+                }
+                logger.fishnet_info(&summary);
+            });
         }
 
         // Main loop. Handles signals, forwards worker results from rx to the
@@ -219,13 +852,17 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
             res = sig_int.recv() => {
                 res.expect("sigint handler installed");
                 logger.clear_echo();
-                if shutdown_soon {
+                if !shutdown_soon {
+                    logger.headline(&format!("Stopping soon. {to_stop} again to finish only the current searches ..."));
+                    queue.shutdown_soon(!opt.no_abort_on_shutdown).await;
+                    shutdown_soon = true;
+                } else if !finish_in_flight {
+                    logger.headline(&format!("Finishing current searches. {to_stop} again to abort pending batches ..."));
+                    queue.finish_in_flight().await;
+                    finish_in_flight = true;
+                } else {
                     logger.fishnet_info("Stopping now.");
                     rx.close();
-                } else {
-                    logger.headline(&format!("Stopping soon. {to_stop} again to abort pending batches ..."));
-                    queue.shutdown_soon().await;
-                    shutdown_soon = true;
                 }
             }
             res = sig_term.recv() => {
@@ -234,6 +871,7 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
                 shutdown_soon = true;
                 rx.close();
             }
+            Some(res) = progress_rx.recv() => queue.progress(res).await,
             res = rx.recv() => {
                 if let Some(res) = res {
                     queue.pull(res).await;
@@ -242,113 +880,516 @@ async fn run(opt: Opt, client: &Client, logger: &Logger) {
                     break;
                 }
             }
+            Some(resumed) = resumed_rx.recv() => {
+                logger.warn(&format!("System resumed after being suspended for about {:?} ({resumed}). Discarding stale work.", Duration::from_secs(resumed.suspended.as_secs())));
+                if !shutdown_soon {
+                    queue.resumed().await;
+                }
+            }
+            _ = network_watcher.changed() => {
+                logger.debug("Network changed. Retrying without backoff.");
+                if !shutdown_soon {
+                    queue.reset_backoff().await;
+                }
+            }
+            res = control.accept() => {
+                match res {
+                    Ok(conn) => {
+                        let (stats, nnue_nps) = queue.stats().await;
+                        let report = status_board.report(queue.queued().await, stats, &nnue_nps);
+                        let logger = logger.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = conn.respond(&report).await {
+                                logger.debug(&format!("Failed to respond on control socket: {err}"));
+                            }
+                        });
+                    }
+                    Err(err) => logger.warn(&format!("Failed to accept control connection: {err}")),
+                }
+            }
+            req = admin.recv() => {
+                let status = match req.command {
+                    AdminCommand::State => 200,
+                    AdminCommand::Pause => {
+                        logger.info("Paused via admin API");
+                        active_tx.send_modify(|active| active.paused = true);
+                        200
+                    }
+                    AdminCommand::Resume => {
+                        logger.info("Resumed via admin API");
+                        active_tx.send_modify(|active| active.paused = false);
+                        200
+                    }
+                    AdminCommand::Cores(n) if n < 1 || n > cores.get() => {
+                        req.reject(400, &format!("cores must be between 1 and {cores}"));
+                        continue;
+                    }
+                    AdminCommand::Cores(n) => {
+                        logger.info(&format!("Limited to {n} of {cores} workers via admin API"));
+                        active_tx.send_modify(|active| active.limit = n);
+                        200
+                    }
+                    AdminCommand::Shutdown => {
+                        if !shutdown_soon {
+                            logger.headline("Stopping soon (requested via admin API) ...");
+                            queue.shutdown_soon(!opt.no_abort_on_shutdown).await;
+                            shutdown_soon = true;
+                        }
+                        202
+                    }
+                };
+                let (stats, nnue_nps) = queue.stats().await;
+                let active = *active_tx.borrow();
+                req.respond(status, &AdminState {
+                    paused: active.paused,
+                    shutting_down: shutdown_soon,
+                    cores: cores.get(),
+                    active_cores: active.get(),
+                    status: status_board.report(queue.queued().await, stats, &nnue_nps),
+                });
+            }
             _ = sleep(Duration::from_secs(120)) => (),
         }
     }
 
+    // Wait for all workers, and submit what they managed to complete.
+    while let Some(res) = workers.join_next().await {
+        queue.salvage(res.expect("join")).await;
+    }
+    engines.shutdown(logger).await;
+
     // Shutdown queue to abort remaining chunks.
-    queue.shutdown().await;
+    let fatal = queue.fatal().await;
+    queue.shutdown(!opt.no_abort_on_shutdown).await;
 
-    // Wait for all workers.
+    // Wait for API and queue actors.
     while let Some(res) = join_set.join_next().await {
         res.expect("join");
     }
 
+    // Persist position cache.
+    if let Some(cache) = shortcuts.cache {
+        let cache = cache.lock().expect("position cache");
+        if let Err(err) = cache.save() {
+            logger.error(&format!("Failed to save position cache: {err}"));
+        }
+    }
+
+    // Exit with a distinct code, so that supervisors do not keep restarting
+    // a client that cannot continue.
+    if let Some(fatal) = fatal {
+        fatal.exit("Stopped, because the endpoint rejected this client", logger);
+    }
+
     // Restart.
     if let Some(restart) = restart.take() {
         restart_process(restart, logger);
     }
 }
 
-async fn worker(i: usize, assets: Arc<Assets>, tx: mpsc::Sender<Pull>, logger: Logger) {
+/// Ways to answer positions without running the engine.
+#[derive(Clone)]
+struct Shortcuts {
+    book: Option<Arc<PolyglotBook>>,
+    cache: Option<Arc<Mutex<PositionCache>>>,
+}
+
+impl Shortcuts {
+    /// Removes positions that can be answered immediately from the chunk.
+    fn answer(
+        &self,
+        chunk: &mut Chunk,
+        node_multiplier: f64,
+        engine: Option<&str>,
+    ) -> (Vec<PositionResponse>, Vec<(PositionIndex, CacheKey)>) {
+        if let Some(res) = self.book.as_ref().and_then(|book| book.answer(chunk)) {
+            chunk.positions.clear();
+            return (vec![res], Vec::new());
+        }
+        match self.cache {
+            Some(ref cache) => {
+                cache
+                    .lock()
+                    .expect("position cache")
+                    .split(chunk, node_multiplier, engine)
+            }
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+
+    fn remember(&self, keys: Vec<(PositionIndex, CacheKey)>, responses: &[PositionResponse]) {
+        if let Some(ref cache) = self.cache {
+            cache
+                .lock()
+                .expect("position cache")
+                .remember(keys, responses);
+        }
+    }
+}
+
+/// Usage of an engine process, to decide when to recycle it.
+struct EngineUsage {
+    started: Instant,
+    chunks: u32,
+}
+
+impl EngineUsage {
+    fn new() -> EngineUsage {
+        EngineUsage {
+            started: Instant::now(),
+            chunks: 0,
+        }
+    }
+
+    fn exhausted(&self, options: &EngineOptions) -> bool {
+        options
+            .recycle_chunks
+            .is_some_and(|chunks| self.chunks >= chunks.get())
+            || options
+                .recycle_age
+                .is_some_and(|age| self.started.elapsed() >= age)
+    }
+}
+
+/// Engine process, its actor and its usage.
+type Engine = (StockfishStub, JoinHandle<()>, EngineUsage);
+
+/// Bundled engines and the options to run them with.
+#[derive(Clone)]
+struct Engines {
+    assets: Arc<Assets>,
+    options: EngineOptions,
+    /// Idle engine processes, so that any worker can pick up a warm engine
+    /// of the required flavor.
+    idle: Arc<Mutex<ByEngineFlavor<Vec<Engine>>>>,
+}
+
+impl Engines {
+    fn take_idle(&self, flavor: EngineFlavor) -> Option<Engine> {
+        self.idle
+            .lock()
+            .expect("idle engines")
+            .get_mut(flavor)
+            .pop()
+    }
+
+    fn put_idle(&self, flavor: EngineFlavor, engine: Engine) {
+        self.idle
+            .lock()
+            .expect("idle engines")
+            .get_mut(flavor)
+            .push(engine);
+    }
+
+    /// Waits for all idle engine processes to shut down.
+    async fn shutdown(&self, logger: &Logger) {
+        let idle = mem::take(&mut *self.idle.lock().expect("idle engines"));
+        for (flavor, engines) in [
+            (EngineFlavor::Official, idle.official),
+            (EngineFlavor::MultiVariant, idle.multi_variant),
+        ] {
+            if !engines.is_empty() {
+                logger.debug(&format!(
+                    "Waiting for {} idle {flavor:?} engines to shut down",
+                    engines.len()
+                ));
+            }
+            for (sf, join_handle, _) in engines {
+                drop(sf);
+                join_handle.await.expect("join");
+            }
+        }
+    }
+
+    /// Saves the dialogue of a failed chunk, if enabled with --record-uci.
+    fn save_transcript(
+        &self,
+        transcript: Option<&Transcript>,
+        batch_id: BatchId,
+        reason: &str,
+        logger: &Logger,
+    ) {
+        let (Some(dir), Some(transcript)) = (&self.options.record_uci, transcript) else {
+            return;
+        };
+        match transcript.save(dir, batch_id, reason) {
+            Ok(path) => logger.info(&format!("Saved engine transcript to {}", path.display())),
+            Err(err) => logger.warn(&format!(
+                "Failed to save engine transcript to {}: {err}",
+                dir.display()
+            )),
+        }
+    }
+
+    /// Starts an engine process and spawns its actor.
+    fn spawn(&self, flavor: EngineFlavor, logger: &Logger) -> (StockfishStub, JoinHandle<()>) {
+        let exe = self
+            .assets
+            .stockfish
+            .get(flavor)
+            .as_ref()
+            .expect("queue only hands out chunks for bundled engines")
+            .path
+            .clone();
+        let (sf, sf_actor) = stockfish::channel(exe, self.options.clone(), logger.clone());
+        (sf, tokio::spawn(sf_actor.run()))
+    }
+}
+
+/// Number of workers that take new work.
+#[derive(Debug, Copy, Clone)]
+struct ActiveWorkers {
+    /// Scaled with the server queue, see --min-cores.
+    scaled: usize,
+    /// Set via the admin API.
+    limit: usize,
+    paused: bool,
+}
+
+impl ActiveWorkers {
+    fn get(self) -> usize {
+        if self.paused {
+            0
+        } else {
+            self.scaled.min(self.limit)
+        }
+    }
+}
+
+/// Periodically polls the server queue and sets the number of workers that
+/// should accept work: one more than the minimum for each queued batch.
+async fn scale_workers(
+    mut api: ApiStub,
+    min_cores: NonZeroUsize,
+    cores: NonZeroUsize,
+    active: watch::Sender<ActiveWorkers>,
+    logger: Logger,
+) {
+    loop {
+        if let Some(status) = api.status().await {
+            let queued = usize::try_from(status.user.queued.max(0) + status.system.queued.max(0))
+                .unwrap_or(usize::MAX);
+            let n = min_cores.get().saturating_add(queued).min(cores.get());
+            let previous = active.borrow().scaled;
+            active.send_modify(|active| active.scaled = n);
+            if n != previous {
+                logger.info(&format!(
+                    "Scaling to {n} of {cores} workers ({queued} batches queued)"
+                ));
+            }
+        }
+
+        tokio::select! {
+            _ = active.closed() => break,
+            _ = sleep(Duration::from_secs(60)) => (),
+        }
+    }
+}
+
+async fn worker(
+    i: usize,
+    engines: Engines,
+    tx: mpsc::Sender<Pull>,
+    status_board: StatusBoard,
+    shortcuts: Shortcuts,
+    mut active: watch::Receiver<ActiveWorkers>,
+    logger: Logger,
+) -> Vec<PositionResponse> {
     logger.debug(&format!("Started worker {i}."));
 
+    // Results of searches stopped early due to shutdown.
+    let mut salvaged = Vec::new();
+
     let mut chunk: Option<Chunk> = None;
-    let mut engine = ByEngineFlavor {
-        official: None,
-        multi_variant: None,
-    };
+    let mut affinity = None;
     let mut engine_backoff = RandomizedBackoff::default();
 
+    // Start an official engine right away, so that the first batch is not
+    // delayed by engines starting up all at once.
+    let (sf, join_handle) = engines.spawn(EngineFlavor::Official, &logger);
+    sf.warm_up(EngineFlavor::Official).await;
+    engines.put_idle(
+        EngineFlavor::Official,
+        (sf, join_handle, EngineUsage::new()),
+    );
+
     loop {
-        let responses = if let Some(chunk) = chunk.take() {
-            // Ensure engine process is ready.
-            let flavor = chunk.flavor;
-            let context = ProgressAt::from(&chunk);
-            let (mut sf, join_handle) = if let Some((sf, join_handle)) =
-                engine.get_mut(flavor).take()
-            {
-                (sf, join_handle)
+        let responses = if let Some(mut chunk) = chunk.take() {
+            // Engine crashes can be transient, so keep a copy to try once
+            // more.
+            let retry = (!chunk.retried).then(|| Chunk {
+                retried: true,
+                ..chunk.clone()
+            });
+
+            // Answer positions from the opening book or cache.
+            let node_multiplier = engines.options.node_multipliers.get(chunk.variant);
+            let engine = engines.options.ids.get(chunk.flavor).name.get().cloned();
+            let (mut answered, misses) =
+                shortcuts.answer(&mut chunk, node_multiplier, engine.as_deref());
+            if chunk.positions.is_empty() {
+                Ok(answered)
             } else {
-                // Backoff before starting engine.
-                let backoff = engine_backoff.next();
-                if backoff >= Duration::from_secs(5) {
-                    logger.info(&format!(
-                        "Waiting {backoff:?} before attempting to start engine"
-                    ));
+                // Ensure engine process is ready.
+                let flavor = chunk.flavor;
+                affinity = Some(chunk.affinity());
+                let context = ProgressAt::from(&chunk);
+                status_board.worker_busy(i, &context);
+                let idle = if chunk.retried {
+                    None
                 } else {
-                    logger.debug(&format!(
-                        "Waiting {backoff:?} before attempting to start engine"
-                    ));
-                }
-                tokio::select! {
-                    _ = tx.closed() => break,
-                    _ = sleep(engine_backoff.next()) => (),
-                }
+                    engines.take_idle(flavor)
+                };
+                let (sf, join_handle, mut usage) = if let Some(idle) = idle {
+                    idle
+                } else {
+                    // Backoff before starting engine.
+                    let backoff = engine_backoff.next();
+                    if backoff >= Duration::from_secs(5) {
+                        logger.info(&format!(
+                            "Waiting {backoff:?} before attempting to start engine"
+                        ));
+                    } else {
+                        logger.debug(&format!(
+                            "Waiting {backoff:?} before attempting to start engine"
+                        ));
+                    }
+                    tokio::select! {
+                        _ = tx.closed() => break,
+                        _ = sleep(engine_backoff.next()) => (),
+                    }
 
-                // Start engine and spawn actor.
-                let (sf, sf_actor) =
-                    stockfish::channel(assets.stockfish.get(flavor).path.clone(), logger.clone());
-                let join_handle = tokio::spawn(sf_actor.run());
-                (sf, join_handle)
-            };
-
-            // Analyse or play.
-            let batch_id = chunk.work.id();
-            let res = tokio::select! {
-                _ = tx.closed() => {
-                    logger.debug(&format!("Worker {i} shutting down engine early"));
-                    drop(sf);
-                    join_handle.await.expect("join");
-                    break;
-                }
-                _ = sleep_until(chunk.deadline) => {
-                    logger.warn(&match flavor {
-                        EngineFlavor::Official => format!("Official Stockfish timed out in worker {i}. If this happens frequently it is better to stop and defer to clients with better hardware. Context: {context}"),
-                        EngineFlavor::MultiVariant => format!("Fairy-Stockfish timed out in worker {i}. Context: {context}"),
-                    });
-                    drop(sf);
-                    join_handle.await.expect("join");
-                    Err(ChunkFailed { batch_id })
-                }
-                res = sf.go_multiple(chunk) => {
-                    match res {
-                        Ok(res) => {
-                            *engine.get_mut(flavor) = Some((sf, join_handle));
-                            engine_backoff.reset();
-                            Ok(res)
+                    let (sf, join_handle) = engines.spawn(flavor, &logger);
+                    (sf, join_handle, EngineUsage::new())
+                };
+
+                // Analyse or play.
+                let batch_id = chunk.work.id();
+                let deadline = chunk.deadline;
+                let is_analysis = chunk.work.is_analysis();
+                let mut go = Box::pin(sf.go_multiple(chunk));
+                let res = tokio::select! {
+                    _ = tx.closed() => {
+                        // Stop the search, but keep the best results so far.
+                        logger.debug(&format!("Worker {i} stopping engine early"));
+                        sf.stop();
+                        if let Ok(Ok(responses)) = timeout(Duration::from_secs(5), &mut go).await {
+                            shortcuts.remember(misses, &responses);
+                            salvaged.extend(responses);
                         }
-                        Err(failed) => {
-                            drop(sf);
-                            logger.warn(&format!("Worker {i} waiting for engine to shut down after error. Context: {context}"));
-                            join_handle.await.expect("join");
-                            Err(failed)
-                        },
+                        salvaged.extend(answered);
+                        drop(go);
+                        drop(sf);
+                        join_handle.await.expect("join");
+                        break;
                     }
-                }
-            };
+                    _ = sleep_until(deadline) => {
+                        logger.warn(&match flavor {
+                            EngineFlavor::Official => format!("Official Stockfish timed out in worker {i}. If this happens frequently it is better to stop and defer to clients with better hardware. Context: {context}"),
+                            EngineFlavor::MultiVariant => format!("Fairy-Stockfish timed out in worker {i}. Context: {context}"),
+                        });
+                        // Stop the search, but keep the positions that were
+                        // completed in time, if the engine still responds.
+                        sf.stop();
+                        let mut completed: Vec<_> = match timeout(Duration::from_secs(2), &mut go).await {
+                            // The search cut short by the stop does not
+                            // count as completed.
+                            Ok(Ok(responses)) if is_analysis => responses
+                                .into_iter()
+                                .filter(|res| !res.stopped_early)
+                                .collect(),
+                            _ => Vec::new(),
+                        };
+                        if is_analysis {
+                            completed.append(&mut answered);
+                        }
+                        let transcript = sf.transcript().cloned();
+                        drop(go);
+                        drop(sf);
+                        join_handle.await.expect("join");
+                        engines.save_transcript(transcript.as_ref(), batch_id, "timed out", &logger);
+                        Err(ChunkFailed {
+                            batch_id,
+                            timed_out: true,
+                            retry: None,
+                            completed,
+                        })
+                    }
+                    res = &mut go => {
+                        drop(go);
+                        match res {
+                            Ok(res) => {
+                                usage.chunks += 1;
+                                if usage.exhausted(&engines.options) {
+                                    // Between chunks, so no search is interrupted.
+                                    logger.debug(&format!("Worker {i} recycling engine after {} chunks", usage.chunks));
+                                    drop(sf);
+                                    join_handle.await.expect("join");
+                                } else {
+                                    engines.put_idle(flavor, (sf, join_handle, usage));
+                                }
+                                engine_backoff.reset();
+                                Ok(res)
+                            }
+                            Err(failed) => {
+                                let transcript = sf.transcript().cloned();
+                                drop(sf);
+                                logger.warn(&format!("Worker {i} waiting for engine to shut down after error. Context: {context}"));
+                                join_handle.await.expect("join");
+                                engines.save_transcript(transcript.as_ref(), batch_id, "engine error", &logger);
+                                Err(ChunkFailed { retry, ..failed })
+                            },
+                        }
+                    }
+                };
 
-            res
+                res.map(|mut responses| {
+                    shortcuts.remember(misses, &responses);
+                    responses.extend(answered);
+                    responses
+                })
+            }
         } else {
             Ok(Vec::new())
         };
 
+        status_board.worker_idle(i);
+
+        if i >= active.borrow().get() {
+            // Scaled down. Hand in the results, but drop the callback, so
+            // that no new work is accepted until scaled up again.
+            let (callback, _) = oneshot::channel();
+            if tx
+                .send(Pull {
+                    responses,
+                    callback,
+                    affinity,
+                })
+                .await
+                .is_err()
+            {
+                break;
+            }
+            logger.debug(&format!("Worker {i} paused"));
+            tokio::select! {
+                _ = tx.closed() => break,
+                res = active.wait_for(|active| i < active.get()) => {
+                    if res.is_err() {
+                        break;
+                    }
+                }
+            }
+            logger.debug(&format!("Worker {i} resumed"));
+            continue;
+        }
+
         let (callback, waiter) = oneshot::channel();
 
         if tx
             .send(Pull {
                 responses,
                 callback,
+                affinity,
             })
             .await
             .is_err()
@@ -370,24 +1411,41 @@ async fn worker(i: usize, assets: Arc<Assets>, tx: mpsc::Sender<Pull>, logger: L
         }
     }
 
-    if let Some((sf, join_handle)) = engine.get_mut(EngineFlavor::Official).take() {
-        logger.debug(&format!(
-            "Worker {i} waiting for standard engine to shut down"
-        ));
-        drop(sf);
-        join_handle.await.expect("join");
-    }
+    logger.debug(&format!("Stopped worker {i}"));
+    drop(tx);
+    salvaged
+}
 
-    if let Some((sf, join_handle)) = engine.get_mut(EngineFlavor::MultiVariant).take() {
-        logger.debug(&format!(
-            "Worker {i} waiting for multi-variant engine to shut down"
-        ));
-        drop(sf);
-        join_handle.await.expect("join");
+/// Console events that stop fishnet right away, like SIGTERM: Ctrl+Break,
+/// and closing the console window, logging off or shutting down. Windows
+/// terminates the process a few seconds after the latter.
+#[cfg(windows)]
+struct ConsoleStop {
+    ctrl_break: signal::windows::CtrlBreak,
+    ctrl_close: signal::windows::CtrlClose,
+    ctrl_logoff: signal::windows::CtrlLogoff,
+    ctrl_shutdown: signal::windows::CtrlShutdown,
+}
+
+#[cfg(windows)]
+impl ConsoleStop {
+    fn new() -> io::Result<ConsoleStop> {
+        Ok(ConsoleStop {
+            ctrl_break: signal::windows::ctrl_break()?,
+            ctrl_close: signal::windows::ctrl_close()?,
+            ctrl_logoff: signal::windows::ctrl_logoff()?,
+            ctrl_shutdown: signal::windows::ctrl_shutdown()?,
+        })
     }
 
-    logger.debug(&format!("Stopped worker {i}"));
-    drop(tx);
+    async fn recv(&mut self) -> Option<()> {
+        tokio::select! {
+            res = self.ctrl_break.recv() => res,
+            res = self.ctrl_close.recv() => res,
+            res = self.ctrl_logoff.recv() => res,
+            res = self.ctrl_shutdown.recv() => res,
+        }
+    }
 }
 
 fn license(logger: &Logger) {
@@ -457,33 +1515,107 @@ fn set_current_process_min_priority() -> windows::core::Result<()> {
     unsafe { SetPriorityClass(GetCurrentProcess(), BELOW_NORMAL_PRIORITY_CLASS) }
 }
 
-fn configure_client() -> Client {
-    // Build TLS backend that supports SSLKEYLOGFILE.
-    let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(
-        rustls::crypto::ring::default_provider(),
-    ))
-    .with_safe_default_protocol_versions()
-    .expect("default tls versions supported")
-    .with_root_certificates(rustls::RootCertStore {
-        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
-    })
-    .with_no_client_auth();
+fn configure_client(opt: &Opt) -> Client {
+    // Build TLS backend, optionally supporting SSLKEYLOGFILE.
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("default tls versions supported");
+    let mut tls = if opt.insecure_skip_tls_verify {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipServerVerification(provider)))
+            .with_no_client_auth()
+    } else {
+        builder
+            .with_root_certificates(rustls::RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            })
+            .with_no_client_auth()
+    };
 
     tls.alpn_protocols = vec!["h2".into(), "http/1.1".into()];
-    tls.key_log = Arc::new(rustls::KeyLogFile::new());
+    if opt.tls_keylog {
+        tls.key_log = Arc::new(rustls::KeyLogFile::new());
+    }
 
     // Configure client.
-    Client::builder()
-        .user_agent(format!(
-            "{}-{}-{}/{}",
-            env!("CARGO_PKG_NAME"),
-            env::consts::OS,
-            env::consts::ARCH,
-            env!("CARGO_PKG_VERSION")
-        ))
+    let mut user_agent = format!(
+        "{}-{}-{}/{}",
+        env!("CARGO_PKG_NAME"),
+        env::consts::OS,
+        env::consts::ARCH,
+        env!("CARGO_PKG_VERSION")
+    );
+    if let Some(ref suffix) = opt.user_agent_suffix {
+        user_agent.push(' ');
+        user_agent.push_str(&suffix.to_string());
+    }
+    let builder = Client::builder()
+        .user_agent(user_agent)
         .timeout(Duration::from_secs(30))
         .pool_idle_timeout(Duration::from_secs(25))
-        .use_preconfigured_tls(tls)
-        .build()
-        .expect("client")
+        .use_preconfigured_tls(tls);
+    let builder = match opt.connect_timeout {
+        Some(connect_timeout) => builder.connect_timeout(connect_timeout.into()),
+        None => builder,
+    };
+    let builder = builder.dns_resolver(Arc::new(SystemResolver));
+    #[cfg(feature = "http3")]
+    let builder = if opt.http3 {
+        builder.http3_prior_knowledge()
+    } else {
+        builder
+    };
+    builder.build().expect("client")
+}
+
+/// Accepts any server certificate, for --insecure-skip-tls-verify. Still
+/// checks handshake signatures.
+#[derive(Debug)]
+struct SkipServerVerification(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
 }
@@ -0,0 +1,152 @@
+use std::io;
+
+use tokio::sync::mpsc;
+
+/// Notifies about changes of the default route, so that backoffs can be
+/// cut short when connectivity returns. Other churn, like address
+/// refreshes or container interfaces coming and going, is ignored.
+///
+/// Currently only implemented on Linux (using netlink). Elsewhere, the
+/// watcher never fires.
+pub struct NetworkWatcher {
+    rx: Option<mpsc::Receiver<()>>,
+}
+
+impl NetworkWatcher {
+    /// A watcher that never fires.
+    pub fn disabled() -> NetworkWatcher {
+        NetworkWatcher { rx: None }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn spawn() -> io::Result<NetworkWatcher> {
+        let socket = linux::RouteSocket::bind()?;
+        let (tx, rx) = mpsc::channel(1);
+        std::thread::Builder::new()
+            .name("netwatch".to_owned())
+            .spawn(move || {
+                let mut buf = [0; 8192];
+                loop {
+                    match socket.recv(&mut buf) {
+                        Ok(n) if linux::default_route_changed(&buf[..n]) => (),
+                        Ok(_) => continue,
+                        // Missed some messages, possibly relevant.
+                        Err(err) if err.raw_os_error() == Some(libc::ENOBUFS) => (),
+                        Err(_) => break,
+                    }
+                    // Coalesce bursts of messages.
+                    if let Err(mpsc::error::TrySendError::Closed(_)) = tx.try_send(()) {
+                        break;
+                    }
+                }
+            })?;
+        Ok(NetworkWatcher { rx: Some(rx) })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn spawn() -> io::Result<NetworkWatcher> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "network change notifications not implemented on this platform",
+        ))
+    }
+
+    /// Completes after the next network change. Cancel safe.
+    pub async fn changed(&mut self) {
+        if let Some(ref mut rx) = self.rx {
+            if rx.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        cmp::min,
+        io, mem,
+        os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd},
+    };
+
+    pub struct RouteSocket {
+        fd: OwnedFd,
+    }
+
+    impl RouteSocket {
+        #[allow(unsafe_code)]
+        pub fn bind() -> io::Result<RouteSocket> {
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_ROUTE,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: Freshly created and owned by nobody else.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            // SAFETY: All-zero is a valid sockaddr_nl.
+            let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = (libc::RTMGRP_IPV4_ROUTE | libc::RTMGRP_IPV6_ROUTE) as u32;
+            if unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    (&raw const addr).cast(),
+                    mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            } != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(RouteSocket { fd })
+        }
+
+        #[allow(unsafe_code)]
+        pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                let n = unsafe {
+                    libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0)
+                };
+                if n >= 0 {
+                    return Ok(n as usize);
+                }
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Checks if a batch of netlink messages adds or removes a default
+    /// route in the main routing table.
+    pub fn default_route_changed(mut buf: &[u8]) -> bool {
+        const NLMSG_HDRLEN: usize = 16;
+        while buf.len() >= NLMSG_HDRLEN {
+            let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+            let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+            if len < NLMSG_HDRLEN || len > buf.len() {
+                break;
+            }
+            // struct rtmsg: family, dst_len, src_len, tos, table, ...
+            let payload = &buf[NLMSG_HDRLEN..len];
+            if (kind == libc::RTM_NEWROUTE || kind == libc::RTM_DELROUTE)
+                && payload.len() >= 5
+                && payload[1] == 0
+                && payload[4] == libc::RT_TABLE_MAIN
+            {
+                return true;
+            }
+            // Messages are aligned to 4 bytes.
+            buf = &buf[min(len.next_multiple_of(4), buf.len())..];
+        }
+        false
+    }
+}
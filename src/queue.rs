@@ -1,10 +1,11 @@
 use std::{
     cmp::{max, min},
-    collections::{HashMap, VecDeque, hash_map::Entry},
+    collections::{HashMap, HashSet, VecDeque, hash_map::Entry},
     error::Error,
     fmt,
     iter::{once, zip},
-    num::NonZeroUsize,
+    mem,
+    num::{NonZeroU8, NonZeroU64, NonZeroUsize},
     sync::Arc,
     time::Duration,
 };
@@ -17,36 +18,51 @@ use shakmaty::{
 };
 use tokio::{
     sync::{Mutex, Notify, mpsc, oneshot},
-    time::{Instant, sleep},
+    task,
+    time::{Instant, sleep, sleep_until},
 };
 use url::Url;
 
 use crate::{
+    api,
     api::{
-        AcquireQuery, AcquireResponseBody, Acquired, AnalysisPart, ApiStub, BatchId, PositionIndex,
-        Work,
+        AcquireQuery, AcquireResponseBody, Acquired, AnalysisPart, AnalysisStatus, ApiStub,
+        BatchId, LichessVariant, PositionIndex, Work,
     },
-    assets::{EngineFlavor, EvalFlavor},
-    configure::{BacklogOpt, Endpoint, MaxBackoff, StatsOpt},
-    ipc::{Chunk, ChunkFailed, Position, PositionResponse, Pull},
-    logger::{Logger, ProgressAt, QueueStatusBar, short_variant_name},
-    stats::{NpsRecorder, Stats, StatsRecorder},
+    assets::EngineFlavor,
+    configure::{BacklogOpt, KeepAlive, MaxBackoff, MemoryBudget, SpotCheckRate, StatsOpt},
+    control::StatusBoard,
+    fatal::Fatal,
+    ipc::{Affinity, Chunk, ChunkFailed, Moves, Position, PositionResponse, Pull},
+    logger::{IdleReason, Logger, ProgressAt, ProgressEvent, QueueStatusBar, short_variant_name},
+    sink::{AnalysisResult, ResultSink},
+    spotcheck::SpotChecker,
+    stats::{ApiErrorCounters, Failure, NpsChange, NpsRecorder, Stats, StatsRecorder},
     util::{NevermindExt as _, RandomizedBackoff, grow_with_and_get_mut},
 };
 
+/// Minimum time between progress reports for the same batch, so that
+/// reporting after each position does not flood the server.
+const MIN_PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
 pub fn channel(
     stats_opt: StatsOpt,
     backlog_opt: BacklogOpt,
     cores: NonZeroUsize,
     api: ApiStub,
     max_backoff: MaxBackoff,
+    spot_check: SpotCheckRate,
     logger: Logger,
 ) -> (QueueStub, QueueActor) {
     let (tx, rx) = mpsc::unbounded_channel();
     let interrupt = Arc::new(Notify::new());
     let state = Arc::new(Mutex::new(QueueState::new(
         stats_opt,
+        api.endpoint().host(),
         cores,
+        api.error_counters(),
+        spot_check,
+        vec![Box::new(api.clone())],
         logger.clone(),
     )));
     let stub = QueueStub {
@@ -61,8 +77,15 @@ pub fn channel(
         state,
         api,
         backlog_opt,
+        last_status: None,
+        max_multipv: None,
+        max_queued_chunks: None,
+        max_pending_memory: None,
+        keep_alive: KeepAlive::default(),
+        status_board: None,
         logger,
         backoff: RandomizedBackoff::new(max_backoff),
+        paused_until: None,
     };
     (stub, actor)
 }
@@ -78,11 +101,11 @@ pub struct QueueStub {
 impl QueueStub {
     pub async fn pull(&mut self, pull: Pull) {
         let mut state = self.state.lock().await;
-        let (responses, callback) = pull.split();
+        let (responses, callback, affinity) = pull.split();
         state.handle_position_responses(self, responses);
-        if let Err(callback) = state.try_pull(callback) {
+        if let Err(callback) = state.try_pull(callback, affinity) {
             if let Some(ref mut tx) = self.tx {
-                tx.send(QueueMessage::Pull { callback })
+                tx.send(QueueMessage::Pull { callback, affinity })
                     .nevermind("queue dropped");
             }
         }
@@ -97,26 +120,148 @@ impl QueueStub {
         }
     }
 
-    pub async fn shutdown_soon(&mut self) {
+    /// Stops acquiring new work and drops batches that have not been
+    /// started. If `abort` is set, they are returned to the server, so that
+    /// other clients can pick them up right away. Otherwise they are left to
+    /// time out on the server.
+    pub async fn shutdown_soon(&mut self, abort: bool) {
         let mut state = self.state.lock().await;
         state.shutdown_soon = true;
+
+        let state = &mut *state;
+        let unstarted: Vec<BatchId> = state
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.started.is_none())
+            .map(|(&batch_id, _)| batch_id)
+            .collect();
+        if !unstarted.is_empty() {
+            state.logger.info(&if abort {
+                format!(
+                    "Returning {} unstarted batch(es) to the server",
+                    unstarted.len()
+                )
+            } else {
+                format!(
+                    "Dropping {} unstarted batch(es). They will time out on the server.",
+                    unstarted.len()
+                )
+            });
+        }
+        for batch_id in unstarted {
+            state.pending.remove(&batch_id);
+            state.spot_checker.forget(batch_id);
+            if abort {
+                state.stats_recorder.record_failure(Failure::Aborted);
+                self.api.abort(batch_id);
+            }
+        }
+
+        // Do not hold up shutdown for pending spot checks either.
+        state
+            .incoming
+            .retain(|chunk| state.pending.contains_key(&chunk.work.id()));
+
         self.tx.take();
         self.interrupt.notify_one();
     }
 
-    pub async fn shutdown(mut self) {
-        self.shutdown_soon().await;
+    /// Drops all chunks that have not been handed to an engine yet, so that
+    /// only the searches in progress are completed. Incomplete batches are
+    /// aborted on shutdown. Call after [`QueueStub::shutdown_soon()`],
+    /// which already dropped the batches that were not started.
+    pub async fn finish_in_flight(&mut self) {
+        let mut state = self.state.lock().await;
+        let dropped = state.incoming.len();
+        state.incoming.clear();
+        if dropped > 0 {
+            state.logger.info(&format!(
+                "Dropped {dropped} queued chunk(s) of incomplete batches"
+            ));
+        }
+    }
+
+    /// The condition that stopped the queue, if it cannot continue without
+    /// reconfiguration.
+    pub async fn fatal(&self) -> Option<Fatal> {
+        self.state.lock().await.fatal
+    }
+
+    /// Handles results that were completed after the workers stopped
+    /// pulling, for example from searches stopped early.
+    pub async fn salvage(&mut self, responses: Vec<PositionResponse>) {
+        if !responses.is_empty() {
+            let mut state = self.state.lock().await;
+            state.handle_position_responses(self, Ok(responses));
+        }
+    }
+
+    /// Handles a single position ahead of the chunk it belongs to.
+    pub async fn progress(&mut self, res: PositionResponse) {
+        let mut state = self.state.lock().await;
+        if !state.spot_checker.is_spot_check(res.work.id()) {
+            state.handle_position_responses(self, Ok(vec![res]));
+        }
+    }
+
+    pub async fn shutdown(mut self, abort: bool) {
+        self.shutdown_soon(abort).await;
 
         let mut state = self.state.lock().await;
-        for (k, _) in state.pending.drain() {
+        if !abort && !state.pending.is_empty() {
+            state.logger.info(&format!(
+                "Not aborting {} pending batches. They will time out on the server.",
+                state.pending.len()
+            ));
+        }
+        let pending: Vec<BatchId> = state.pending.drain().map(|(k, _)| k).collect();
+        for k in pending {
+            if abort {
+                state.stats_recorder.record_failure(Failure::Aborted);
+                self.api.abort(k);
+            }
+        }
+    }
+
+    /// Discard work that went stale while the system was suspended, and
+    /// acquire fresh work without delay.
+    pub async fn resumed(&mut self) {
+        let mut state = self.state.lock().await;
+        let state = &mut *state;
+        for chunk in state.incoming.drain(..) {
+            // Includes running spot checks.
+            state.spot_checker.forget(chunk.work.id());
+        }
+        state.move_submissions.clear();
+        let pending: Vec<BatchId> = state.pending.drain().map(|(k, _)| k).collect();
+        for k in pending {
+            state.stats_recorder.record_failure(Failure::Aborted);
+            state.spot_checker.forget(k);
             self.api.abort(k);
         }
+        state.reset_backoff = true;
+        self.api.reset_backoff();
+        self.interrupt.notify_one();
+    }
+
+    /// Retry acquiring work without delay, for example when connectivity
+    /// might have returned.
+    pub async fn reset_backoff(&mut self) {
+        let mut state = self.state.lock().await;
+        state.reset_backoff = true;
+        self.api.reset_backoff();
+        self.interrupt.notify_one();
+    }
+
+    pub async fn queued(&self) -> usize {
+        let state = self.state.lock().await;
+        state.status_bar().pending
     }
 
     pub async fn stats(&self) -> (Stats, NpsRecorder) {
         let state = self.state.lock().await;
         (
-            state.stats_recorder.stats.clone(),
+            state.stats_recorder.stats(),
             state.stats_recorder.nnue_nps.clone(),
         )
     }
@@ -124,27 +269,174 @@ impl QueueStub {
 
 struct QueueState {
     shutdown_soon: bool,
+    fatal: Option<Fatal>,
+    reset_backoff: bool,
     cores: NonZeroUsize,
     incoming: VecDeque<Chunk>,
     pending: HashMap<BatchId, PendingBatch>,
     move_submissions: VecDeque<MoveSubmission>,
     stats_recorder: StatsRecorder,
+    spot_checker: SpotChecker,
+    result_sinks: Vec<Box<dyn ResultSink>>,
     logger: Logger,
 }
 
 impl QueueState {
-    fn new(stats_opt: StatsOpt, cores: NonZeroUsize, logger: Logger) -> QueueState {
+    fn new(
+        stats_opt: StatsOpt,
+        endpoint: String,
+        cores: NonZeroUsize,
+        api_errors: Arc<ApiErrorCounters>,
+        spot_check: SpotCheckRate,
+        result_sinks: Vec<Box<dyn ResultSink>>,
+        logger: Logger,
+    ) -> QueueState {
         QueueState {
             shutdown_soon: false,
+            fatal: None,
+            reset_backoff: false,
             cores,
             incoming: VecDeque::new(),
             pending: HashMap::new(),
             move_submissions: VecDeque::new(),
-            stats_recorder: StatsRecorder::new(stats_opt, cores),
+            stats_recorder: StatsRecorder::new(stats_opt, endpoint, cores, api_errors, &logger),
+            spot_checker: SpotChecker::new(spot_check.into()),
+            result_sinks,
             logger,
         }
     }
 
+    fn submit_result(&mut self, result: AnalysisResult) {
+        for sink in &mut self.result_sinks {
+            sink.submit_analysis(&result);
+        }
+    }
+
+    /// Predicts when the queued chunks will be completed, given the
+    /// measured speed, and returns the most recently acquired batches that
+    /// have not been started, as long as any of them would miss its
+    /// deadline. The returned batches are removed from the queue.
+    fn shed_overload(&mut self) -> Vec<BatchId> {
+        let Some(nps) = self.stats_recorder.nnue_nps.confident() else {
+            return Vec::new();
+        };
+        let mut shed = Vec::new();
+        loop {
+            let now = Instant::now();
+            let late = self.predicted_completion(nps).any(|(chunk, busy)| {
+                now + busy > chunk.deadline
+                    && self
+                        .pending
+                        .get(&chunk.work.id())
+                        .is_some_and(|pending| pending.started.is_none())
+            });
+            if !late {
+                break;
+            }
+            let Some(newest) = self
+                .pending
+                .iter()
+                .filter(|(_, pending)| pending.started.is_none() && pending.work.is_analysis())
+                .max_by_key(|(_, pending)| pending.acquired)
+                .map(|(&batch_id, _)| batch_id)
+            else {
+                break;
+            };
+            self.pending.remove(&newest);
+            self.incoming.retain(|chunk| chunk.work.id() != newest);
+            self.stats_recorder.record_failure(Failure::Aborted);
+            self.spot_checker.forget(newest);
+            shed.push(newest);
+        }
+        shed
+    }
+
+    /// Time until each queued chunk is expected to be completed, in queue
+    /// order, assuming that all cores work through the queue.
+    fn predicted_completion(&self, nnue_nps: u32) -> impl Iterator<Item = (&Chunk, Duration)> {
+        let cores = self.cores.get() as u32;
+        self.incoming
+            .iter()
+            .scan(Duration::ZERO, move |busy, chunk| {
+                *busy += estimated_chunk_time(chunk, nnue_nps) / cores;
+                Some((chunk, *busy))
+            })
+    }
+
+    /// Logs when the batch is expected to be completed, and warns if that
+    /// is after its deadline.
+    fn log_eta(&self, batch_id: BatchId) {
+        let Some(nps) = self.stats_recorder.nnue_nps.confident() else {
+            return;
+        };
+        let Some((deadline, eta)) = self
+            .predicted_completion(nps)
+            .filter(|(chunk, _)| chunk.work.id() == batch_id)
+            .map(|(chunk, busy)| (chunk.deadline, busy))
+            .last()
+        else {
+            return;
+        };
+        if eta.is_zero() {
+            return;
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if eta > timeout {
+            self.logger.warn(&format!(
+                "Batch {batch_id} will take about {:?} at {} knps/core, including queued work, but times out in {:?}. This machine may be too slow for the requested analysis.",
+                Duration::from_secs(eta.as_secs()),
+                nps / 1000,
+                Duration::from_secs(timeout.as_secs())
+            ));
+        } else {
+            self.logger.debug(&format!(
+                "Batch {batch_id} will take about {:?} at {} knps/core, including queued work (times out in {:?})",
+                Duration::from_secs(eta.as_secs()),
+                nps / 1000,
+                Duration::from_secs(timeout.as_secs())
+            ));
+        }
+    }
+
+    /// Rough estimate of the memory held by queued chunks and pending
+    /// batches. Chunks that are being searched are not included.
+    fn approximate_memory(&self) -> usize {
+        let incoming: usize = self
+            .incoming
+            .iter()
+            .map(|chunk| {
+                // Positions share the moves of the game.
+                chunk.positions.len() * size_of::<Position>()
+                    + chunk
+                        .positions
+                        .iter()
+                        .map(|pos| pos.moves.len())
+                        .max()
+                        .unwrap_or(0)
+                        * size_of::<UciMove>()
+            })
+            .sum();
+        let pending: usize = self
+            .pending
+            .values()
+            .flat_map(|pending| &pending.positions)
+            .map(|pos| {
+                size_of::<Option<Skip<PositionResponse>>>()
+                    + match pos {
+                        Some(Skip::Present(res)) => res
+                            .pvs
+                            .values()
+                            .map(|pv| {
+                                size_of::<(u8, Vec<UciMove>)>() + pv.len() * size_of::<UciMove>()
+                            })
+                            .sum(),
+                        _ => 0,
+                    }
+            })
+            .sum();
+        incoming + pending
+    }
+
     fn status_bar(&self) -> QueueStatusBar {
         QueueStatusBar {
             pending: self.pending.values().map(|p| p.pending()).sum(),
@@ -152,38 +444,92 @@ impl QueueState {
         }
     }
 
-    fn add_incoming_batch(&mut self, batch: IncomingBatch) {
+    fn add_incoming_batch(&mut self, batch: IncomingBatch, network: Duration) {
         match self.pending.entry(batch.work.id()) {
-            Entry::Occupied(entry) => self.logger.error(&format!(
-                "Dropping duplicate incoming batch {}",
-                entry.key()
-            )),
+            Entry::Occupied(entry) => {
+                // The server may issue a batch again, for example after a
+                // partial progress report. Adopt chunks with positions that
+                // are neither complete nor queued, so that the batch does
+                // not stall on both sides. Positions currently being
+                // searched may be analysed twice, but only the first
+                // response is kept.
+                let batch_id = *entry.key();
+                let pending = entry.get();
+                let queued: HashSet<usize> = self
+                    .incoming
+                    .iter()
+                    .filter(|chunk| chunk.work.id() == batch_id)
+                    .flat_map(|chunk| &chunk.positions)
+                    .filter_map(|pos| pos.position_index)
+                    .map(|PositionIndex(i)| i)
+                    .collect();
+                let adopted: Vec<Chunk> = batch
+                    .chunks
+                    .into_iter()
+                    .filter(|chunk| {
+                        chunk.positions.iter().any(|pos| {
+                            !pos.skip
+                                && pos.position_index.is_some_and(|PositionIndex(i)| {
+                                    !queued.contains(&i)
+                                        && matches!(pending.positions.get(i), Some(None))
+                                })
+                        })
+                    })
+                    .collect();
+                if adopted.is_empty() {
+                    self.logger.warn(&format!(
+                        "Ignoring duplicate incoming batch {batch_id}, which has no missing positions"
+                    ));
+                } else {
+                    self.logger.warn(&format!(
+                        "Adopting {} chunk(s) with missing positions from duplicate incoming batch {batch_id}",
+                        adopted.len()
+                    ));
+                    self.incoming =
+                        interleave(mem::take(&mut self.incoming).into_iter().chain(adopted));
+                }
+            }
             Entry::Vacant(entry) => {
                 let progress_at = ProgressAt::from(&batch);
+                let batch_id = batch.work.id();
+                let batch_url = batch.url.clone();
 
-                let mut positions = Vec::with_capacity(batch.chunks.len() * Chunk::MAX_POSITIONS);
-                for chunk in batch.chunks {
-                    for pos in &chunk.positions {
-                        if let Some(position_index) = pos.position_index {
-                            *grow_with_and_get_mut(&mut positions, position_index.0, || {
-                                Some(Skip::Skip)
-                            }) = pos.skip.then_some(Skip::Skip);
-                        }
-                    }
-                    self.incoming.push_back(chunk);
-                }
+                let positions = batch.pending_positions();
 
+                // Take turns with batches that are already queued, so that
+                // a long game does not hold up newly requested analysis.
+                self.incoming = interleave(
+                    mem::take(&mut self.incoming)
+                        .into_iter()
+                        .chain(batch.chunks),
+                );
+
+                let num_positions = positions.len();
                 entry.insert(PendingBatch {
                     work: batch.work,
                     flavor: batch.flavor,
                     variant: batch.variant,
+                    root_fen: batch.root_fen,
+                    moves: batch.moves,
                     url: batch.url,
                     positions,
                     total_nodes: 0,
                     total_cpu_time: Duration::ZERO,
+                    last_report: Instant::now(),
+                    acquired: Instant::now(),
+                    network,
+                    started: None,
                 });
 
-                self.logger.progress(self.status_bar(), progress_at);
+                let status_bar = self.status_bar();
+                self.logger.event(ProgressEvent::BatchAcquired {
+                    batch_id,
+                    url: batch_url,
+                    variant: batch.variant,
+                    positions: num_positions,
+                    queued: status_bar.pending,
+                });
+                self.logger.progress(status_bar, progress_at);
             }
         }
     }
@@ -197,13 +543,26 @@ impl QueueState {
             Ok(responses) => {
                 let mut progress_at = None;
                 let mut batch_ids = Vec::new();
+                let mut done = Vec::new();
                 for res in responses {
                     let batch_id = res.work.id();
+                    if self.spot_checker.is_spot_check(batch_id) {
+                        self.handle_spot_check(&res);
+                        continue;
+                    }
                     let Some(pending) = self.pending.get_mut(&batch_id) else {
                         continue;
                     };
-                    pending.total_nodes += res.nodes;
-                    pending.total_cpu_time += res.time;
+                    if res.position_index.is_some_and(|PositionIndex(i)| {
+                        matches!(pending.positions.get(i), Some(Some(Skip::Present(_))))
+                    }) {
+                        // Already reported ahead of the complete chunk.
+                        continue;
+                    }
+                    if !res.cached {
+                        pending.total_nodes += res.nodes;
+                        pending.total_cpu_time += res.time;
+                    }
                     let Some(position_index) = res.position_index else {
                         continue;
                     };
@@ -211,19 +570,72 @@ impl QueueState {
                         continue;
                     };
                     progress_at = Some(ProgressAt::from(&res));
+                    done.push((batch_id, position_index));
+                    if let Some(chunk) = self.spot_checker.verification_chunk(&res) {
+                        self.incoming.push_back(chunk);
+                    }
                     *pos = Some(Skip::Present(res));
                     if !batch_ids.contains(&batch_id) {
                         batch_ids.push(batch_id);
                     }
                 }
+                let status_bar = self.status_bar();
+                for (batch_id, PositionIndex(position_index)) in done {
+                    self.logger.event(ProgressEvent::PositionDone {
+                        batch_id,
+                        position_index,
+                        queued: status_bar.pending,
+                    });
+                }
                 if let Some(progress_at) = progress_at {
-                    self.logger.progress(self.status_bar(), progress_at);
+                    self.logger.progress(status_bar, progress_at);
                 }
                 for batch_id in batch_ids {
                     self.maybe_finished(queue.clone(), batch_id);
                 }
             }
-            Err(failed) => {
+            Err(failed) if self.spot_checker.is_spot_check(failed.batch_id) => {
+                self.record_chunk_failed(&failed);
+                self.spot_checker.forget(failed.batch_id);
+            }
+            Err(failed)
+                if failed.retry.is_some() && self.pending.contains_key(&failed.batch_id) =>
+            {
+                self.record_chunk_failed(&failed);
+                self.logger.warn(&format!(
+                    "Retrying chunk of batch {} once with a fresh engine",
+                    failed.batch_id
+                ));
+                if let Some(chunk) = failed.retry {
+                    self.incoming.push_front(chunk);
+                }
+            }
+            Err(mut failed) => {
+                self.record_chunk_failed(&failed);
+                self.spot_checker.forget(failed.batch_id);
+                // Submit positions that were completed before the failure,
+                // as a final progress report, so that the server can keep
+                // them. Never as a complete batch, even if they fill the
+                // last gaps.
+                let completed = mem::take(&mut failed.completed);
+                if let Some(pending) = self.pending.get_mut(&failed.batch_id) {
+                    if !completed.is_empty() && !pending.work.matrix_wanted() {
+                        for res in completed {
+                            if let Some(pos @ None) = res
+                                .position_index
+                                .and_then(|PositionIndex(i)| pending.positions.get_mut(i))
+                            {
+                                *pos = Some(Skip::Present(res));
+                            }
+                        }
+                        queue.api.clone().submit_analysis(
+                            pending.work.id(),
+                            pending.flavor,
+                            pending.flavor.eval_flavor(pending.variant),
+                            pending.progress_report(),
+                        );
+                    }
+                }
                 // Just forget about batches with failed positions,
                 // intentionally letting them time out, instead of handing
                 // them to the next client.
@@ -233,10 +645,55 @@ impl QueueState {
         }
     }
 
-    fn try_pull(&mut self, callback: oneshot::Sender<Chunk>) -> Result<(), oneshot::Sender<Chunk>> {
-        if let Some(chunk) = self.incoming.pop_front() {
-            if let Err(err) = callback.send(chunk) {
-                self.incoming.push_front(err);
+    fn record_chunk_failed(&mut self, failed: &ChunkFailed) {
+        self.stats_recorder.record_failure(if failed.timed_out {
+            Failure::EngineTimeout
+        } else {
+            Failure::EngineCrash
+        });
+    }
+
+    fn handle_spot_check(&mut self, res: &PositionResponse) {
+        let Some(outcome) = self.spot_checker.complete(res) else {
+            return;
+        };
+        let context = match outcome.url {
+            Some(ref url) => url.to_string(),
+            None => outcome.batch_id.to_string(),
+        };
+        if outcome.agrees() {
+            self.logger.debug(&format!(
+                "Spot check of {context} agrees: {} vs. {} with half the nodes",
+                outcome.expected, outcome.actual
+            ));
+        } else {
+            self.logger.warn(&format!(
+                "Spot check of {context} disagrees: {} vs. {} with half the nodes ({} of {} spot checks disagreed). Unstable hardware, e.g. due to overclocking, can cause bad evaluations.",
+                outcome.expected,
+                outcome.actual,
+                self.spot_checker.disagreed,
+                self.spot_checker.checked
+            ));
+        }
+    }
+
+    fn try_pull(
+        &mut self,
+        callback: oneshot::Sender<Chunk>,
+        affinity: Option<Affinity>,
+    ) -> Result<(), oneshot::Sender<Chunk>> {
+        let index = affinity.map_or(0, |affinity| self.preferred_chunk(affinity));
+        if let Some(mut chunk) = self.incoming.remove(index) {
+            self.spot_checker.sample(&chunk);
+            chunk.nnue_nps = self.stats_recorder.nnue_nps.confident();
+            let batch_id = chunk.work.id();
+            match callback.send(chunk) {
+                Ok(()) => {
+                    if let Some(pending) = self.pending.get_mut(&batch_id) {
+                        pending.started.get_or_insert_with(Instant::now);
+                    }
+                }
+                Err(err) => self.incoming.insert(index, err),
             }
             Ok(())
         } else {
@@ -244,31 +701,97 @@ impl QueueState {
         }
     }
 
+    /// Prefers chunks for the engine and variant that a worker already runs,
+    /// to avoid switching engines and networks when workloads mix. The next
+    /// chunk in line is still taken if it is due soon.
+    fn preferred_chunk(&self, affinity: Affinity) -> usize {
+        let Some(front) = self.incoming.front() else {
+            return 0;
+        };
+        if front.affinity() == affinity
+            || !front.work.is_analysis()
+            || front.deadline < Instant::now() + Duration::from_secs(60)
+        {
+            return 0;
+        }
+        self.incoming
+            .iter()
+            .position(|chunk| chunk.affinity() == affinity)
+            .unwrap_or(0)
+    }
+
+    /// Sends progress reports for analysis that has not been reported on
+    /// for the given interval. Returns when the next report will be due.
+    fn keep_alive(&mut self, interval: Duration, api: &mut ApiStub) -> Instant {
+        let now = Instant::now();
+        let mut next = now + interval;
+        for pending in self.pending.values_mut() {
+            if !matches!(pending.work, Work::Analysis { .. }) {
+                continue;
+            }
+            if pending.last_report + interval <= now {
+                self.logger.debug(&format!(
+                    "Sending keep-alive for batch {}",
+                    pending.work.id()
+                ));
+                pending.last_report = now;
+                api.submit_analysis(
+                    pending.work.id(),
+                    pending.flavor,
+                    pending.flavor.eval_flavor(pending.variant),
+                    pending.progress_report(),
+                );
+            }
+            next = min(next, pending.last_report + interval);
+        }
+        next
+    }
+
     fn maybe_finished(&mut self, mut queue: QueueStub, batch: BatchId) {
         if let Some(pending) = self.pending.remove(&batch) {
+            let timing = pending.timing();
             match pending.try_into_completed() {
                 Ok(completed) => {
                     let mut extra = Vec::new();
                     extra.extend(short_variant_name(completed.variant).map(|n| n.to_owned()));
-                    if completed.flavor.eval_flavor().is_hce() {
+                    if completed.flavor.eval_flavor(completed.variant).is_hce() {
                         extra.push("hce".to_owned());
                     }
+                    self.logger.event(ProgressEvent::BatchCompleted {
+                        batch_id: batch,
+                        url: completed.url.clone(),
+                        positions: completed.total_positions(),
+                        nodes: completed.total_nodes,
+                        nps: completed.nps(),
+                    });
                     extra.push(match completed.nps() {
                         Some(nps) => {
-                            let nnue_nps = if completed.flavor.eval_flavor() == EvalFlavor::Nnue {
-                                Some(nps)
-                            } else {
-                                None
-                            };
-                            self.stats_recorder.record_batch(
+                            // Only the official engine is measured. Variant
+                            // nets of Fairy-Stockfish run at a different speed.
+                            let nnue_nps = (completed.flavor == EngineFlavor::Official)
+                                .then_some(nps);
+                            match self.stats_recorder.record_batch(
                                 completed.total_positions(),
                                 completed.total_nodes,
                                 nnue_nps,
-                            );
+                            ) {
+                                Some(NpsChange::Dropped { nps, baseline }) => self.logger.warn(&format!(
+                                    "Throughput dropped to {} knps/core, from {} knps/core on average. Thermal throttling or other processes could be slowing down analysis.",
+                                    nps / 1000,
+                                    baseline / 1000
+                                )),
+                                Some(NpsChange::Recovered { nps, baseline }) => self.logger.info(&format!(
+                                    "Throughput recovered to {} knps/core ({} knps/core on average)",
+                                    nps / 1000,
+                                    baseline / 1000
+                                )),
+                                None => (),
+                            }
                             format!("{} knps/core", nps / 1000)
                         }
                         None => "? nps".to_owned(),
                     });
+                    extra.push(timing.to_string());
                     let log = match completed.url {
                         Some(ref url) => format!(
                             "{} {} finished ({})",
@@ -286,11 +809,7 @@ impl QueueState {
                     match completed.work {
                         Work::Analysis { id, .. } => {
                             self.logger.info(&log);
-                            queue.api.submit_analysis(
-                                id,
-                                completed.flavor.eval_flavor(),
-                                completed.into_analysis(),
-                            );
+                            self.submit_result(completed.into_result(id));
                         }
                         Work::Move { id, .. } => {
                             self.logger.debug(&log);
@@ -302,12 +821,16 @@ impl QueueState {
                         }
                     }
                 }
-                Err(pending) => {
-                    if !pending.work.matrix_wanted() {
+                Err(mut pending) => {
+                    if !pending.work.matrix_wanted()
+                        && pending.last_report.elapsed() >= MIN_PROGRESS_INTERVAL
+                    {
                         // Send partial analysis as progress report.
+                        pending.last_report = Instant::now();
                         queue.api.submit_analysis(
                             pending.work.id(),
-                            pending.flavor.eval_flavor(),
+                            pending.flavor,
+                            pending.flavor.eval_flavor(pending.variant),
                             pending.progress_report(),
                         );
                     }
@@ -327,7 +850,10 @@ struct MoveSubmission {
 
 #[derive(Debug)]
 enum QueueMessage {
-    Pull { callback: oneshot::Sender<Chunk> },
+    Pull {
+        callback: oneshot::Sender<Chunk>,
+        affinity: Option<Affinity>,
+    },
     MoveSubmitted,
 }
 
@@ -337,21 +863,91 @@ pub struct QueueActor {
     state: Arc<Mutex<QueueState>>,
     api: ApiStub,
     backlog_opt: BacklogOpt,
+    last_status: Option<(Instant, AnalysisStatus)>,
+    max_multipv: Option<NonZeroU8>,
+    max_queued_chunks: Option<usize>,
+    max_pending_memory: Option<MemoryBudget>,
+    keep_alive: KeepAlive,
+    status_board: Option<StatusBoard>,
     backoff: RandomizedBackoff,
+    /// End of a pause requested by the server.
+    paused_until: Option<Instant>,
     logger: Logger,
 }
 
 impl QueueActor {
+    /// Decline analysis with more principal variations than given.
+    pub fn with_max_multipv(mut self, max_multipv: Option<NonZeroU8>) -> QueueActor {
+        self.max_multipv = max_multipv;
+        self
+    }
+
+    /// Decline batches while more than the given number of chunks beyond
+    /// one per core are already queued.
+    pub fn with_max_queued_chunks(mut self, max_queued_chunks: Option<usize>) -> QueueActor {
+        self.max_queued_chunks = max_queued_chunks;
+        self
+    }
+
+    /// Stop acquiring batches while queued and pending analysis uses
+    /// roughly more than the given memory.
+    pub fn with_max_pending_memory(
+        mut self,
+        max_pending_memory: Option<MemoryBudget>,
+    ) -> QueueActor {
+        self.max_pending_memory = max_pending_memory;
+        self
+    }
+
+    pub fn with_status_board(mut self, status_board: StatusBoard) -> QueueActor {
+        self.status_board = Some(status_board);
+        self
+    }
+
+    /// Report progress on long-running analysis at the given interval.
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> QueueActor {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Also send complete analysis to the given sinks, after submitting it
+    /// to the endpoint.
+    pub fn with_result_sinks(self, result_sinks: Vec<Box<dyn ResultSink>>) -> QueueActor {
+        self.state
+            .try_lock()
+            .expect("queue state not yet in use")
+            .result_sinks
+            .extend(result_sinks);
+        self
+    }
+
     pub async fn run(self) {
         self.logger.debug("Queue actor started");
-        self.run_inner().await;
+        match self.keep_alive.interval() {
+            Some(interval) => {
+                let keep_alive = keep_alive(self.state.clone(), self.api.clone(), interval);
+                tokio::select! {
+                    _ = self.run_inner() => (),
+                    _ = keep_alive => (),
+                }
+            }
+            None => self.run_inner().await,
+        }
     }
 
     pub async fn backlog_wait_time(&mut self) -> (Duration, AcquireQuery) {
-        let min_user_backlog = {
+        let (min_user_backlog, nps_pause) = {
             let state = self.state.lock().await;
-            state.stats_recorder.min_user_backlog()
+            (
+                state.stats_recorder.min_user_backlog(),
+                state.stats_recorder.nps_pause(),
+            )
         };
+        if !nps_pause.is_zero() {
+            self.logger
+                .debug("Holding off acquiring work while throughput is reduced");
+            return (nps_pause, AcquireQuery { slow: false });
+        }
         let user_backlog = max(
             min_user_backlog,
             self.backlog_opt
@@ -366,7 +962,7 @@ impl QueueActor {
             .unwrap_or_default();
 
         if user_backlog >= Duration::from_secs(1) || system_backlog >= Duration::from_secs(1) {
-            if let Some(status) = self.api.status().await {
+            if let Some(status) = self.status().await {
                 let user_wait = user_backlog
                     .checked_sub(status.user.oldest)
                     .unwrap_or_default();
@@ -389,7 +985,35 @@ impl QueueActor {
         }
     }
 
-    async fn handle_acquired_response_body(&mut self, body: AcquireResponseBody) {
+    /// Queue status from the server, requested at most once per
+    /// --status-interval.
+    async fn status(&mut self) -> Option<AnalysisStatus> {
+        let interval = Duration::from(self.backlog_opt.status_interval.unwrap_or_default());
+        if let Some((at, ref status)) = self.last_status {
+            let elapsed = at.elapsed();
+            if elapsed < interval {
+                // Queued jobs keep aging in the meantime.
+                let mut status = status.clone();
+                for queue in [&mut status.user, &mut status.system] {
+                    if queue.queued > 0 {
+                        queue.oldest += elapsed;
+                    }
+                }
+                return Some(status);
+            }
+        }
+        let status = self.api.status().await?;
+        self.last_status = Some((Instant::now(), status.clone()));
+        Some(status)
+    }
+
+    /// Returns `false` if the batch was declined, so that the caller can
+    /// back off instead of acquiring similar work again right away.
+    async fn handle_acquired_response_body(
+        &mut self,
+        body: AcquireResponseBody,
+        network: Duration,
+    ) -> bool {
         let batch_id = body.work.id();
         let context = ProgressAt {
             batch_id,
@@ -398,19 +1022,67 @@ impl QueueActor {
         };
         let is_move = body.work.is_move();
 
-        match IncomingBatch::from_acquired(self.api.endpoint(), body) {
+        if let Some(max_multipv) = self.max_multipv {
+            if body.work.multipv() > max_multipv {
+                self.logger.warn(&format!(
+                    "Declining batch {context}: multipv {} exceeds --max-multipv {max_multipv}",
+                    body.work.multipv()
+                ));
+                self.api.abort(batch_id);
+                return false;
+            }
+        }
+
+        // Replaying long games can take a while on slow hardware, so keep
+        // it off the runtime thread.
+        let url = body.batch_url(self.api.endpoint());
+        #[allow(clippy::result_large_err)]
+        let incoming = task::spawn_blocking(move || IncomingBatch::from_acquired(body, url))
+            .await
+            .expect("prepare incoming batch");
+        match incoming {
             Ok(incoming) => {
                 let mut state = self.state.lock().await;
-                state.add_incoming_batch(incoming);
+                if let Some(max_queued_chunks) = self.max_queued_chunks {
+                    // Only the existing backlog counts, so that long games
+                    // are still accepted by idle clients.
+                    let queued = state.incoming.len();
+                    if queued > state.cores.get() + max_queued_chunks {
+                        self.logger.warn(&format!(
+                            "Declining batch {context}: {queued} queued chunks exceed --max-queued-chunks {max_queued_chunks} beyond {} cores",
+                            state.cores
+                        ));
+                        self.api.abort(batch_id);
+                        return false;
+                    }
+                }
+                state.add_incoming_batch(incoming, network);
+                let shed = state.shed_overload();
+                for &shed_batch_id in &shed {
+                    self.logger.warn(&format!(
+                        "Returning batch {shed_batch_id} to the server, because it would not be completed in time at the current speed"
+                    ));
+                    self.api.abort(shed_batch_id);
+                }
+                if !shed.contains(&batch_id) {
+                    state.log_eta(batch_id);
+                }
+                // Back off while overloaded, instead of acquiring work
+                // that would be shed again.
+                shed.is_empty()
             }
             Err(IncomingError::AllSkipped(completed)) => {
                 self.logger
                     .warn(&format!("Completed empty batch {context}."));
-                self.api.submit_analysis(
-                    completed.work.id(),
-                    completed.flavor.eval_flavor(),
-                    completed.into_analysis(),
-                );
+                let mut state = self.state.lock().await;
+                state.submit_result(completed.into_result(batch_id));
+                true
+            }
+            Err(err @ (IncomingError::NotBundled(_) | IncomingError::UnknownVariant(_))) => {
+                self.logger
+                    .warn(&format!("Declining batch {context}: {err}"));
+                self.api.abort(batch_id);
+                false
             }
             Err(err) if is_move => {
                 self.logger
@@ -420,10 +1092,12 @@ impl QueueActor {
                     batch_id,
                     best_move: None,
                 });
+                true
             }
             Err(err) => {
                 self.logger
                     .warn(&format!("Ignoring invalid batch {context}: {err}"));
+                true
             }
         }
     }
@@ -443,12 +1117,14 @@ impl QueueActor {
             };
 
             if let Some(completed) = next {
+                let request = Instant::now();
                 if let Some(Acquired::Accepted(body)) = self
                     .api
                     .submit_move_and_acquire(completed.batch_id, completed.best_move)
                     .await
                 {
-                    self.handle_acquired_response_body(body).await;
+                    self.handle_acquired_response_body(body, request.elapsed())
+                        .await;
                 }
             } else {
                 break;
@@ -459,12 +1135,18 @@ impl QueueActor {
     async fn run_inner(mut self) {
         while let Some(msg) = self.rx.recv().await {
             match msg {
-                QueueMessage::Pull { mut callback } => loop {
+                QueueMessage::Pull {
+                    mut callback,
+                    affinity,
+                } => loop {
                     self.handle_move_submissions().await;
 
-                    {
+                    let over_budget = {
                         let mut state = self.state.lock().await;
-                        callback = match state.try_pull(callback) {
+                        if mem::take(&mut state.reset_backoff) {
+                            self.backoff.reset();
+                        }
+                        callback = match state.try_pull(callback, affinity) {
                             Ok(()) => break,
                             Err(not_done) => not_done,
                         };
@@ -472,6 +1154,45 @@ impl QueueActor {
                         if state.shutdown_soon {
                             break;
                         }
+
+                        self.max_pending_memory.and_then(|max_pending_memory| {
+                            let used = state.approximate_memory();
+                            (used as u64 > NonZeroU64::from(max_pending_memory).get())
+                                .then_some((used, max_pending_memory))
+                        })
+                    };
+
+                    if let Some((used, max_pending_memory)) = over_budget {
+                        // Wait for pending batches to complete.
+                        let wait = Duration::from_secs(1);
+                        self.logger.event(ProgressEvent::Idle {
+                            wait_ms: wait,
+                            reason: IdleReason::MemoryBudget,
+                        });
+                        self.logger.debug(&format!(
+                            "Not acquiring while pending batches use about {used} bytes (--max-pending-memory {max_pending_memory})"
+                        ));
+                        tokio::select! {
+                            _ = callback.closed() => break,
+                            _ = self.interrupt.notified() => continue,
+                            _ = sleep(wait) => continue,
+                        }
+                    }
+
+                    if let Some(until) = self.paused_until {
+                        if until > Instant::now() {
+                            // Interrupted to submit moves or to shut down,
+                            // but continue the pause afterwards.
+                            tokio::select! {
+                                _ = callback.closed() => break,
+                                _ = self.interrupt.notified() => continue,
+                                _ = sleep_until(until) => (),
+                            }
+                        }
+                        self.paused_until = None;
+                        if let Some(ref status_board) = self.status_board {
+                            status_board.acquire_backoff(None);
+                        }
                     }
 
                     let (wait, query) = tokio::select! {
@@ -480,6 +1201,10 @@ impl QueueActor {
                     };
 
                     if wait >= Duration::from_secs(1) {
+                        self.logger.event(ProgressEvent::Idle {
+                            wait_ms: wait,
+                            reason: IdleReason::Backlog,
+                        });
                         if wait >= Duration::from_secs(40) {
                             self.logger.info(&format!("Going idle for {wait:?}."));
                         } else {
@@ -493,25 +1218,64 @@ impl QueueActor {
                         }
                     }
 
+                    let request = Instant::now();
                     match self.api.acquire(query).await {
                         Some(Acquired::Accepted(body)) => {
-                            self.backoff.reset();
-                            self.handle_acquired_response_body(body).await;
+                            if self
+                                .handle_acquired_response_body(body, request.elapsed())
+                                .await
+                            {
+                                self.backoff.reset();
+                            } else {
+                                let backoff = self.backoff.next();
+                                self.logger
+                                    .debug(&format!("Declined batch. Backing off {backoff:?}."));
+                                tokio::select! {
+                                    _ = callback.closed() => break,
+                                    _ = self.interrupt.notified() => (),
+                                    _ = sleep(backoff) => (),
+                                }
+                            }
                         }
                         Some(Acquired::NoContent) => {
                             let backoff = self.backoff.next();
+                            self.logger.event(ProgressEvent::Idle {
+                                wait_ms: backoff,
+                                reason: IdleReason::NoJob,
+                            });
                             self.logger
                                 .debug(&format!("No job received. Backing off {backoff:?}."));
+                            if let Some(ref status_board) = self.status_board {
+                                status_board.acquire_backoff(Some(backoff));
+                            }
                             tokio::select! {
                                 _ = callback.closed() => break,
                                 _ = self.interrupt.notified() => (),
                                 _ = sleep(backoff) => (),
                             }
+                            if let Some(ref status_board) = self.status_board {
+                                status_board.acquire_backoff(None);
+                            }
                         }
-                        Some(Acquired::Rejected) => {
+                        Some(Acquired::Paused(pause)) => {
+                            self.backoff.reset();
+                            self.logger.event(ProgressEvent::Idle {
+                                wait_ms: pause,
+                                reason: IdleReason::ServerPause,
+                            });
+                            self.logger.info(&format!(
+                                "Server asked clients to pause. Idle for {pause:?}."
+                            ));
+                            if let Some(ref status_board) = self.status_board {
+                                status_board.acquire_backoff(Some(pause));
+                            }
+                            self.paused_until = Some(Instant::now() + pause);
+                        }
+                        Some(Acquired::Rejected(fatal)) => {
                             self.logger.error("Client update or reconfiguration might be required. Stopping queue.");
                             let mut state = self.state.lock().await;
                             state.shutdown_soon = true;
+                            state.fatal = Some(fatal);
                         }
                         None => (),
                     }
@@ -522,37 +1286,135 @@ impl QueueActor {
     }
 }
 
+async fn keep_alive(state: Arc<Mutex<QueueState>>, mut api: ApiStub, interval: Duration) {
+    loop {
+        let next = {
+            let mut state = state.lock().await;
+            state.keep_alive(interval, &mut api)
+        };
+        sleep_until(next).await;
+    }
+}
+
 impl Drop for QueueActor {
     fn drop(&mut self) {
         self.logger.debug("Queue actor exited");
     }
 }
 
+/// Orders chunks round-robin by batch, keeping the order within each batch.
+fn interleave(chunks: impl IntoIterator<Item = Chunk>) -> VecDeque<Chunk> {
+    let mut batches: Vec<VecDeque<Chunk>> = Vec::new();
+    for chunk in chunks {
+        match batches.iter_mut().find(|batch| {
+            batch
+                .front()
+                .is_some_and(|c| c.work.id() == chunk.work.id())
+        }) {
+            Some(batch) => batch.push_back(chunk),
+            None => batches.push(VecDeque::from([chunk])),
+        }
+    }
+
+    let mut interleaved = VecDeque::new();
+    while !batches.is_empty() {
+        for batch in &mut batches {
+            interleaved.extend(batch.pop_front());
+        }
+        batches.retain(|batch| !batch.is_empty());
+    }
+    interleaved
+}
+
 #[derive(Debug, Clone)]
 enum Skip<T> {
     Present(T),
     Skip,
 }
 
+/// Work as it would be submitted.
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Submission {
+    Analysis(AnalysisResult),
+    Move { best_move: Option<UciMove> },
+}
+
+struct OfflineSink(Arc<std::sync::Mutex<Option<AnalysisResult>>>);
+
+impl ResultSink for OfflineSink {
+    fn submit_analysis(&mut self, result: &AnalysisResult) {
+        *self.0.lock().expect("offline result") = Some(result.clone());
+    }
+}
+
+/// Feeds a batch and the responses to its chunks through the same queue
+/// state as work from the server, but without workers or requests.
+/// Returns `None` if responses are missing.
+pub(crate) async fn complete_offline(
+    batch: IncomingBatch,
+    responses: Vec<PositionResponse>,
+    logger: Logger,
+) -> Option<Submission> {
+    let (api, _api_rx) = api::mock();
+    let result = Arc::default();
+    let state = Arc::new(Mutex::new(QueueState::new(
+        StatsOpt {
+            stats_file: None,
+            no_stats_file: true,
+            nps_drop: None,
+            pause_on_nps_drop: false,
+        },
+        api.endpoint().host(),
+        NonZeroUsize::MIN,
+        api.error_counters(),
+        SpotCheckRate::default(),
+        vec![Box::new(OfflineSink(Arc::clone(&result)))],
+        logger,
+    )));
+    let queue = QueueStub {
+        tx: None,
+        interrupt: Arc::new(Notify::new()),
+        state: Arc::clone(&state),
+        api,
+    };
+
+    let mut state = state.lock().await;
+    state.add_incoming_batch(batch, Duration::ZERO);
+    state.handle_position_responses(&queue, Ok(responses));
+    if let Some(submission) = state.move_submissions.pop_front() {
+        return Some(Submission::Move {
+            best_move: submission.best_move,
+        });
+    }
+    let result = result.lock().expect("offline result").take();
+    result.map(Submission::Analysis)
+}
+
 #[derive(Debug)]
 pub struct IncomingBatch {
     work: Work,
     flavor: EngineFlavor,
     variant: Variant,
+    root_fen: Fen,
+    moves: Moves,
     chunks: Vec<Chunk>,
     url: Option<Url>,
+    num_positions: usize,
 }
 
 impl IncomingBatch {
     #[allow(clippy::result_large_err)]
-    fn from_acquired(
-        endpoint: &Endpoint,
+    pub(crate) fn from_acquired(
         body: AcquireResponseBody,
+        url: Option<Url>,
     ) -> Result<IncomingBatch, IncomingError> {
-        let url = body.batch_url(endpoint);
+        let variant = match body.variant {
+            LichessVariant::Known(variant) => variant,
+            LichessVariant::Unknown(name) => return Err(IncomingError::UnknownVariant(name)),
+        };
 
         let maybe_root_pos = VariantPosition::from_setup(
-            body.variant,
+            variant,
             body.position.into_setup(),
             CastlingMode::Chess960,
         )
@@ -560,13 +1422,19 @@ impl IncomingBatch {
         .or_else(PositionError::ignore_invalid_castling_rights);
 
         let (flavor, root_pos) = match maybe_root_pos {
-            Ok(pos @ VariantPosition::Chess(_)) if body.work.is_analysis() => {
+            Ok(pos @ VariantPosition::Chess(_))
+                if body.work.is_analysis() || !EngineFlavor::MultiVariant.is_bundled() =>
+            {
                 (EngineFlavor::Official, pos)
             }
             Ok(pos) => (EngineFlavor::MultiVariant, pos),
             Err(pos) => (EngineFlavor::MultiVariant, pos.ignore_too_much_material()?),
         };
 
+        if !flavor.is_bundled() {
+            return Err(IncomingError::NotBundled(variant));
+        }
+
         let root_fen = Fen::from_position(&root_pos, EnPassantMode::Legal);
 
         let body_moves = {
@@ -580,32 +1448,42 @@ impl IncomingBatch {
             moves
         };
 
+        let num_positions = match body.work {
+            Work::Move { .. } => 1,
+            Work::Analysis { .. } => body_moves.len() + 1,
+        };
+        let moves = Moves::from(body_moves);
+
         Ok(IncomingBatch {
             work: body.work.clone(),
             url: url.clone(),
             flavor,
-            variant: body.variant,
+            variant,
+            root_fen: root_fen.clone(),
+            moves: moves.clone(),
+            num_positions,
             chunks: match body.work {
                 Work::Move { .. } => {
                     vec![Chunk {
                         work: body.work.clone(),
                         deadline: Instant::now() + body.work.timeout_per_ply(),
                         flavor,
-                        variant: body.variant,
+                        variant,
                         positions: vec![Position {
                             work: body.work,
                             url,
                             skip: false,
                             position_index: Some(PositionIndex(0)),
                             root_fen,
-                            moves: body_moves,
+                            moves,
                         }],
+                        nnue_nps: None,
+                        retried: false,
                     }]
                 }
                 Work::Analysis { .. } => {
-                    // Iterate forwards to prepare positions.
-                    let mut moves = Vec::new();
-                    let num_positions = body_moves.len() + 1;
+                    // Iterate forwards to prepare positions, all sharing
+                    // the moves of the game.
                     let deadline =
                         Instant::now() + body.work.timeout_per_ply() * num_positions as u32;
                     let mut positions = Vec::with_capacity(num_positions);
@@ -618,11 +1496,10 @@ impl IncomingBatch {
                         skip: body.skip_positions.contains(&PositionIndex(0)),
                         position_index: Some(PositionIndex(0)),
                         root_fen: root_fen.clone(),
-                        moves: moves.clone(),
+                        moves: moves.prefix(0),
                     });
-                    for (i, m) in body_moves.into_iter().enumerate() {
-                        let position_index = PositionIndex(i + 1);
-                        moves.push(m);
+                    for i in 1..=moves.len() {
+                        let position_index = PositionIndex(i);
                         positions.push(Position {
                             work: body.work.clone(),
                             url: url.clone().map(|mut url| {
@@ -632,7 +1509,7 @@ impl IncomingBatch {
                             skip: body.skip_positions.contains(&position_index),
                             position_index: Some(position_index),
                             root_fen: root_fen.clone(),
-                            moves: moves.clone(),
+                            moves: moves.prefix(i),
                         });
                     }
 
@@ -673,8 +1550,10 @@ impl IncomingBatch {
                                 work: body.work.clone(),
                                 deadline,
                                 flavor,
-                                variant: body.variant,
+                                variant,
                                 positions: chunk_positions,
+                                nnue_nps: None,
+                                retried: false,
                             });
                         }
                     }
@@ -686,7 +1565,9 @@ impl IncomingBatch {
                             work: body.work,
                             url,
                             flavor,
-                            variant: body.variant,
+                            variant,
+                            root_fen,
+                            moves,
                             positions: vec![Skip::Skip; num_positions],
                             total_nodes: 0,
                             total_cpu_time: Duration::ZERO,
@@ -698,6 +1579,30 @@ impl IncomingBatch {
             },
         })
     }
+
+    pub(crate) fn into_chunks(self) -> Vec<Chunk> {
+        self.chunks
+    }
+
+    pub(crate) fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Slots for the results of all positions, with skipped positions
+    /// already filled in. Trailing skipped positions may not appear in any
+    /// chunk, but still need a slot.
+    fn pending_positions(&self) -> Vec<Option<Skip<PositionResponse>>> {
+        let mut positions = vec![Some(Skip::Skip); self.num_positions];
+        for chunk in &self.chunks {
+            for pos in &chunk.positions {
+                if let Some(position_index) = pos.position_index {
+                    *grow_with_and_get_mut(&mut positions, position_index.0, || Some(Skip::Skip)) =
+                        pos.skip.then_some(Skip::Skip);
+                }
+            }
+        }
+        positions
+    }
 }
 
 impl From<&IncomingBatch> for ProgressAt {
@@ -712,9 +1617,11 @@ impl From<&IncomingBatch> for ProgressAt {
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
-enum IncomingError {
+pub enum IncomingError {
     Position(PositionError<VariantPosition>),
     IllegalUciMove(IllegalUciMoveError),
+    NotBundled(Variant),
+    UnknownVariant(String),
     AllSkipped(CompletedBatch),
 }
 
@@ -725,6 +1632,12 @@ impl fmt::Display for IncomingError {
         match self {
             IncomingError::Position(err) => err.fmt(f),
             IncomingError::IllegalUciMove(err) => err.fmt(f),
+            IncomingError::NotBundled(variant) => {
+                write!(f, "no engine for {} bundled with this build", variant.uci())
+            }
+            IncomingError::UnknownVariant(name) => {
+                write!(f, "client update needed for variant {name}")
+            }
             IncomingError::AllSkipped(_) => f.write_str("all positions skipped"),
         }
     }
@@ -748,9 +1661,17 @@ struct PendingBatch {
     url: Option<Url>,
     flavor: EngineFlavor,
     variant: Variant,
+    root_fen: Fen,
+    moves: Moves,
     positions: Vec<Option<Skip<PositionResponse>>>,
     total_nodes: u64,
     total_cpu_time: Duration,
+    last_report: Instant,
+    /// When the batch was received, after `network` time to acquire it.
+    acquired: Instant,
+    network: Duration,
+    /// When the first chunk was handed to a worker.
+    started: Option<Instant>,
 }
 
 impl PendingBatch {
@@ -762,6 +1683,8 @@ impl PendingBatch {
                 url: self.url,
                 flavor: self.flavor,
                 variant: self.variant,
+                root_fen: self.root_fen,
+                moves: self.moves,
                 positions,
                 total_nodes: self.total_nodes,
                 total_cpu_time: self.total_cpu_time,
@@ -776,30 +1699,98 @@ impl PendingBatch {
             .enumerate()
             .map(|(i, p)| match p {
                 // Quirk: Lila distinguishes progress reports from complete
-                // analysis by looking at the first part.
-                Some(Skip::Present(pos)) if i > 0 => Some(pos.to_best()),
+                // analysis by looking at the first part. Partial matrix
+                // results are not reported, so that the report only keeps
+                // the batch alive.
+                Some(Skip::Present(pos)) if i > 0 && !self.work.matrix_wanted() => {
+                    Some(pos.to_best())
+                }
                 _ => None,
             })
             .collect()
     }
 
+    fn timing(&self) -> BatchTiming {
+        BatchTiming {
+            total: self.network + self.acquired.elapsed(),
+            queued: self
+                .started
+                .map_or(Duration::ZERO, |started| started - self.acquired),
+            engine: self.total_cpu_time,
+            network: self.network,
+        }
+    }
+
     fn pending(&self) -> usize {
         self.positions.iter().filter(|p| p.is_none()).count()
     }
 }
 
+/// Where the time between acquiring and submitting a batch went.
+#[derive(Debug, Copy, Clone)]
+struct BatchTiming {
+    total: Duration,
+    queued: Duration,
+    /// Summed over all positions, even if analysed in parallel.
+    engine: Duration,
+    network: Duration,
+}
+
+impl fmt::Display for BatchTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1?} total, {:.1?} queued, {:.1?} engine, {:.1?} network",
+            self.total, self.queued, self.engine, self.network
+        )
+    }
+}
+
+/// Expected time for a single core to analyse the chunk. Only chunks for the
+/// official engine are estimated, because that is the only speed measured.
+fn estimated_chunk_time(chunk: &Chunk, nnue_nps: u32) -> Duration {
+    let Work::Analysis {
+        nodes, movetime, ..
+    } = chunk.work
+    else {
+        return Duration::ZERO;
+    };
+    let eval_flavor = chunk.flavor.eval_flavor(chunk.variant);
+    if chunk.flavor != EngineFlavor::Official || nnue_nps == 0 {
+        return Duration::ZERO;
+    }
+    let per_position = Duration::from_secs_f64(nodes.get(eval_flavor) as f64 / f64::from(nnue_nps));
+    movetime.map_or(per_position, |movetime| min(per_position, movetime))
+        * chunk.positions.len() as u32
+}
+
 #[derive(Debug)]
 pub struct CompletedBatch {
     work: Work,
     url: Option<Url>,
     flavor: EngineFlavor,
     variant: Variant,
+    root_fen: Fen,
+    moves: Moves,
     positions: Vec<Skip<PositionResponse>>,
     total_nodes: u64,
     total_cpu_time: Duration,
 }
 
 impl CompletedBatch {
+    fn into_result(self, batch_id: BatchId) -> AnalysisResult {
+        AnalysisResult {
+            batch_id,
+            url: self.url.clone(),
+            engine: self.flavor,
+            flavor: self.flavor.eval_flavor(self.variant),
+            variant: self.variant,
+            root_fen: self.root_fen.clone(),
+            moves: self.moves.clone(),
+            analysis: self.into_analysis(),
+        }
+    }
+
     fn into_analysis(self) -> Vec<Option<AnalysisPart>> {
         self.positions
             .into_iter()
@@ -836,3 +1827,387 @@ impl CompletedBatch {
             .and_then(|nps| nps.try_into().ok())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+
+    use shakmaty::Chess;
+    use tokio::task::JoinSet;
+
+    use super::*;
+    use crate::{
+        api::{self, ApiMessage, NodeLimit},
+        configure::Verbose,
+        stats::NpsRecorder,
+        stockfish::{
+            Engine,
+            mock::{self, MockEngine},
+        },
+    };
+
+    struct Expected {
+        num_positions: usize,
+        skipped: BTreeSet<usize>,
+        multipv: Option<NonZeroU8>,
+    }
+
+    type Results = Arc<std::sync::Mutex<Vec<(BatchId, Vec<Option<AnalysisPart>>)>>>;
+
+    struct CollectSink(Results);
+
+    impl ResultSink for CollectSink {
+        fn submit_analysis(&mut self, result: &AnalysisResult) {
+            self.0
+                .lock()
+                .expect("results")
+                .push((result.batch_id, result.analysis.clone()));
+        }
+    }
+
+    fn random_batch(rng: &mut fastrand::Rng, i: usize) -> (AcquireResponseBody, Expected) {
+        let mut pos = Chess::default();
+        let mut moves = Vec::new();
+        for _ in 0..rng.usize(0..80) {
+            let legals = pos.legal_moves();
+            let Some(m) = rng.choice(legals) else {
+                break;
+            };
+            moves.push(m.to_uci(CastlingMode::Standard));
+            pos.play_unchecked(m);
+        }
+        let num_positions = moves.len() + 1;
+        let skipped: BTreeSet<usize> = (0..num_positions).filter(|_| rng.u8(0..4) == 0).collect();
+        let multipv = match rng.u8(0..3) {
+            0 => NonZeroU8::new(rng.u8(1..=3)),
+            _ => None,
+        };
+        let body = AcquireResponseBody {
+            work: Work::Analysis {
+                id: format!("batch{i}").parse().expect("valid batch id"),
+                nodes: NodeLimit::new(4_050_000, 1_500_000),
+                depth: None,
+                multipv,
+                movetime: None,
+                timeout: Duration::from_secs(7),
+            },
+            game_id: None,
+            position: Fen::from_position(&Chess::default(), EnPassantMode::Legal),
+            variant: Variant::Chess.into(),
+            moves,
+            skip_positions: skipped.iter().copied().map(PositionIndex).collect(),
+        };
+        let expected = Expected {
+            num_positions,
+            skipped,
+            multipv,
+        };
+        (body, expected)
+    }
+
+    fn check_chunks(batch: &IncomingBatch, expected: &Expected) {
+        let mut indexes = Vec::new();
+        for chunk in batch.chunks() {
+            assert!(!chunk.positions.is_empty());
+            assert!(chunk.positions.len() <= Chunk::MAX_POSITIONS);
+            indexes.extend(chunk.positions.iter().filter_map(|pos| pos.position_index));
+        }
+        indexes.sort_by_key(|&PositionIndex(i)| i);
+        let wanted: Vec<_> = (0..expected.num_positions)
+            .filter(|i| !expected.skipped.contains(i))
+            .map(PositionIndex)
+            .collect();
+        assert_eq!(indexes, wanted, "each position analysed exactly once");
+    }
+
+    /// Pulls chunks like the workers of the binary, including the single
+    /// retry after a failure.
+    async fn worker<E: Engine>(
+        mut queue: QueueStub,
+        engine: Arc<E>,
+        dropped: Arc<std::sync::Mutex<HashSet<BatchId>>>,
+    ) {
+        let mut responses = Ok(Vec::new());
+        loop {
+            let (callback, waiter) = oneshot::channel();
+            queue
+                .pull(Pull {
+                    responses,
+                    callback,
+                    affinity: None,
+                })
+                .await;
+            let Ok(chunk) = waiter.await else {
+                break;
+            };
+            let retry = (!chunk.retried).then(|| Chunk {
+                retried: true,
+                ..chunk.clone()
+            });
+            if retry.is_none() {
+                // Batch is dropped if this fails again.
+                let batch_id = chunk.work.id();
+                responses = engine.go_multiple(chunk).await;
+                if responses.is_err() {
+                    dropped.lock().expect("dropped").insert(batch_id);
+                }
+            } else {
+                responses = engine
+                    .go_multiple(chunk)
+                    .await
+                    .map_err(|failed| ChunkFailed { retry, ..failed });
+            }
+        }
+    }
+
+    fn check_part(part: &Option<AnalysisPart>, ply: usize, expected: &Expected) {
+        match (part, expected.multipv) {
+            (Some(AnalysisPart::Skipped { .. }), _) => {
+                assert!(expected.skipped.contains(&ply), "unexpected skip at {ply}");
+            }
+            (Some(AnalysisPart::Best { score, .. }), None) => {
+                let want = MockEngine::score(ply, NonZeroU8::MIN, mock::DEPTH);
+                assert_eq!(format!("{score:?}"), format!("{want:?}"), "score at {ply}");
+            }
+            (Some(AnalysisPart::Matrix { score, .. }), Some(multipv)) => {
+                assert_eq!(score.len(), usize::from(multipv.get()));
+                for (row, scores) in score.iter().enumerate() {
+                    let multipv = NonZeroU8::new(row as u8 + 1).expect("multipv");
+                    assert_eq!(scores.len(), usize::from(mock::DEPTH) + 1);
+                    assert!(scores[0].is_none());
+                    for (depth, score) in scores.iter().enumerate().skip(1) {
+                        let want = MockEngine::score(ply, multipv, depth as u8);
+                        assert_eq!(format!("{score:?}"), format!("{:?}", Some(want)));
+                    }
+                }
+            }
+            (part, _) => panic!("unexpected part at {ply}: {part:?}"),
+        }
+    }
+
+    fn new_state(api: &ApiStub, cores: NonZeroUsize, results: &Results) -> QueueState {
+        QueueState::new(
+            StatsOpt {
+                stats_file: None,
+                no_stats_file: true,
+                nps_drop: None,
+                pause_on_nps_drop: false,
+            },
+            api.endpoint().host(),
+            cores,
+            api.error_counters(),
+            SpotCheckRate::default(),
+            vec![Box::new(CollectSink(results.clone()))],
+            Logger::new(Verbose::default(), true),
+        )
+    }
+
+    /// Analysis of the first `plies` moves of a fixed game.
+    fn fixed_batch(i: usize, plies: usize) -> IncomingBatch {
+        let mut pos = Chess::default();
+        let mut moves = Vec::new();
+        for _ in 0..plies {
+            let m = pos.legal_moves()[0];
+            moves.push(m.to_uci(CastlingMode::Standard));
+            pos.play_unchecked(m);
+        }
+        IncomingBatch::from_acquired(
+            AcquireResponseBody {
+                work: Work::Analysis {
+                    id: format!("batch{i}").parse().expect("valid batch id"),
+                    nodes: NodeLimit::new(4_050_000, 1_500_000),
+                    depth: None,
+                    multipv: None,
+                    movetime: None,
+                    timeout: Duration::from_secs(7),
+                },
+                game_id: None,
+                position: Fen::from_position(&Chess::default(), EnPassantMode::Legal),
+                variant: Variant::Chess.into(),
+                moves,
+                skip_positions: Vec::new(),
+            },
+            None,
+        )
+        .expect("valid batch")
+    }
+
+    async fn simulate(seed: u64) {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let (api, mut api_rx) = api::mock();
+        let results = Results::default();
+        let cores = NonZeroUsize::new(rng.usize(1..=4)).expect("cores");
+        let state = Arc::new(Mutex::new(new_state(&api, cores, &results)));
+        let queue = QueueStub {
+            tx: None,
+            interrupt: Arc::new(Notify::new()),
+            state: state.clone(),
+            api,
+        };
+
+        let mut expected = HashMap::new();
+        for i in 0..rng.usize(1..=4) {
+            let (body, batch_expected) = random_batch(&mut rng, i);
+            let batch_id = body.work.id();
+            match IncomingBatch::from_acquired(body, None) {
+                Ok(batch) => {
+                    check_chunks(&batch, &batch_expected);
+                    state.lock().await.add_incoming_batch(batch, Duration::ZERO);
+                    expected.insert(batch_id, batch_expected);
+                }
+                Err(IncomingError::AllSkipped(_)) => {
+                    assert_eq!(batch_expected.skipped.len(), batch_expected.num_positions);
+                }
+                Err(err) => panic!("invalid batch: {err}"),
+            }
+        }
+
+        let engine = Arc::new(MockEngine::new(seed).with_failure_rate(0.1));
+        let dropped = Arc::default();
+        let mut workers = JoinSet::new();
+        for _ in 0..cores.get() {
+            workers.spawn(worker(queue.clone(), engine.clone(), Arc::clone(&dropped)));
+        }
+        while let Some(res) = workers.join_next().await {
+            res.expect("join");
+        }
+
+        {
+            let state = state.lock().await;
+            assert!(state.incoming.is_empty());
+            assert!(state.pending.is_empty());
+        }
+
+        // Complete analysis.
+        let dropped = dropped.lock().expect("dropped");
+        let results = results.lock().expect("results");
+        for (batch_id, batch_expected) in &expected {
+            let submitted: Vec<_> = results.iter().filter(|(id, _)| id == batch_id).collect();
+            if dropped.contains(batch_id) {
+                assert!(submitted.is_empty(), "dropped batch {batch_id} submitted");
+                continue;
+            }
+            let [(_, analysis)] = submitted[..] else {
+                panic!("batch {batch_id} submitted {} times", submitted.len());
+            };
+            assert_eq!(analysis.len(), batch_expected.num_positions);
+            for (ply, part) in analysis.iter().enumerate() {
+                check_part(part, ply, batch_expected);
+            }
+        }
+
+        // Progress reports.
+        while let Ok(msg) = api_rx.try_recv() {
+            let ApiMessage::SubmitAnalysis {
+                batch_id, analysis, ..
+            } = msg
+            else {
+                continue;
+            };
+            let batch_expected = &expected[&batch_id];
+            assert!(batch_expected.multipv.is_none(), "no partial matrix");
+            assert_eq!(analysis.len(), batch_expected.num_positions);
+            assert!(analysis[0].is_none(), "first part marks progress report");
+            for (ply, part) in analysis.iter().enumerate().skip(1) {
+                if part.is_some() {
+                    check_part(part, ply, batch_expected);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shed_overload() {
+        let (api, _api_rx) = api::mock();
+        let mut state = new_state(&api, NonZeroUsize::MIN, &Results::default());
+        let batch_id = fixed_batch(0, 10).work.id();
+
+        // 11 positions at 1.5s each, due in 77s.
+        state.stats_recorder.nnue_nps = NpsRecorder {
+            nps: 1_000_000,
+            uncertainty: 0.0,
+        };
+        state.add_incoming_batch(fixed_batch(0, 10), Duration::ZERO);
+        assert!(state.shed_overload().is_empty());
+
+        // 11 positions at 15s each would miss the deadline.
+        state.stats_recorder.nnue_nps.nps = 100_000;
+        assert_eq!(state.shed_overload(), vec![batch_id]);
+        assert!(state.pending.is_empty());
+        assert!(state.incoming.is_empty());
+
+        // Batches that were already started are kept.
+        state.add_incoming_batch(fixed_batch(0, 10), Duration::ZERO);
+        state.pending.get_mut(&batch_id).expect("pending").started = Some(Instant::now());
+        assert!(state.shed_overload().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reissued_batch() {
+        let (api, mut api_rx) = api::mock();
+        let results = Results::default();
+        let state = Arc::new(Mutex::new(new_state(&api, NonZeroUsize::MIN, &results)));
+        let queue = QueueStub {
+            tx: None,
+            interrupt: Arc::new(Notify::new()),
+            state: state.clone(),
+            api,
+        };
+        let engine = MockEngine::new(0);
+        let mut state = state.lock().await;
+        let batch_id = fixed_batch(0, 40).work.id();
+        state.add_incoming_batch(fixed_batch(0, 40), Duration::ZERO);
+
+        // First chunk is reported as progress, second chunk is still being
+        // searched.
+        state
+            .pending
+            .get_mut(&batch_id)
+            .expect("pending")
+            .last_report = Instant::now()
+            .checked_sub(MIN_PROGRESS_INTERVAL)
+            .expect("instant");
+        let first = state.incoming.pop_front().expect("first chunk");
+        let in_flight = state.incoming.pop_front().expect("second chunk");
+        state.handle_position_responses(&queue, engine.go_multiple(first).await);
+        assert!(matches!(
+            api_rx.try_recv(),
+            Ok(ApiMessage::SubmitAnalysis { analysis, .. }) if analysis[0].is_none()
+        ));
+
+        // The server issues the batch again. Only the chunk that is neither
+        // complete nor queued is adopted.
+        let queued = state.incoming.len();
+        state.add_incoming_batch(fixed_batch(0, 40), Duration::ZERO);
+        assert_eq!(state.incoming.len(), queued + 1);
+
+        // Both the original and the adopted chunk are answered.
+        state.handle_position_responses(&queue, engine.go_multiple(in_flight).await);
+        while let Some(chunk) = state.incoming.pop_front() {
+            state.handle_position_responses(&queue, engine.go_multiple(chunk).await);
+        }
+        assert!(state.pending.is_empty());
+
+        let results = results.lock().expect("results");
+        let [(id, ref analysis)] = results[..] else {
+            panic!("batch submitted {} times", results.len());
+        };
+        assert_eq!(id, batch_id);
+        let expected = Expected {
+            num_positions: 41,
+            skipped: BTreeSet::new(),
+            multipv: None,
+        };
+        assert_eq!(analysis.len(), expected.num_positions);
+        for (ply, part) in analysis.iter().enumerate() {
+            check_part(part, ply, &expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulated_pipeline() {
+        for seed in 0..100 {
+            simulate(seed).await;
+        }
+    }
+}
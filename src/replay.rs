@@ -0,0 +1,167 @@
+//! Offline replay of recorded work, for deterministic end-to-end tests of
+//! chunking and result assembly.
+
+use std::{num::NonZeroU8, time::Duration};
+
+use serde::Serialize;
+use serde_with::{DisplayFromStr, serde_as};
+use shakmaty::uci::UciMove;
+
+use crate::{
+    api::{AcquireResponseBody, BatchId, PositionIndex, Score},
+    client::{AnalysisClient, AnalysisError},
+    ipc::{Chunk, Matrix, PositionResponse},
+    logger::Logger,
+    queue::{IncomingBatch, Submission, complete_offline},
+    sink::AnalysisResult,
+};
+
+/// Answers chunks of the replayed batch.
+pub enum ReplayEngine {
+    /// Answers instantly, without running an engine. Each position is
+    /// evaluated as its ply in centipawns, so that results assigned to the
+    /// wrong position stand out.
+    Stub,
+    /// Runs the bundled engines.
+    Real(AnalysisClient),
+}
+
+#[serde_as]
+#[derive(Debug, Serialize)]
+pub struct Replay {
+    #[serde_as(as = "DisplayFromStr")]
+    pub batch_id: BatchId,
+    /// Position indexes of each chunk, in queue order. Positions that are
+    /// only included to provide context for the next one are `null`.
+    pub chunks: Vec<Vec<Option<PositionIndex>>>,
+    #[serde(flatten)]
+    pub outcome: ReplayOutcome,
+}
+
+#[serde_as]
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayOutcome {
+    /// What would be submitted for analysis.
+    Analysis(AnalysisResult),
+    /// What would be submitted for a move.
+    Move {
+        #[serde_as(as = "Option<DisplayFromStr>")]
+        best_move: Option<UciMove>,
+    },
+}
+
+/// Feeds a recorded acquire response through the same preparation and
+/// assembly as work from the server, without any network requests.
+pub async fn replay(
+    body: AcquireResponseBody,
+    engine: &ReplayEngine,
+    logger: &Logger,
+) -> Result<Replay, AnalysisError> {
+    let batch_id = body.work.id();
+    let batch = IncomingBatch::from_acquired(body, None)?;
+    let chunks: Vec<Chunk> = batch.chunks().to_vec();
+    let layout = chunks
+        .iter()
+        .map(|chunk| {
+            chunk
+                .positions
+                .iter()
+                .map(|pos| pos.position_index)
+                .collect()
+        })
+        .collect();
+
+    let responses = match engine {
+        ReplayEngine::Stub => chunks.iter().flat_map(stub_responses).collect(),
+        ReplayEngine::Real(client) => client.analyse_chunks(chunks).await?,
+    };
+
+    let submission = complete_offline(batch, responses, logger.clone())
+        .await
+        .ok_or(AnalysisError::EngineFailed)?;
+    Ok(Replay {
+        batch_id,
+        chunks: layout,
+        outcome: match submission {
+            Submission::Analysis(result) => ReplayOutcome::Analysis(result),
+            Submission::Move { best_move } => ReplayOutcome::Move { best_move },
+        },
+    })
+}
+
+fn stub_responses(chunk: &Chunk) -> Vec<PositionResponse> {
+    chunk
+        .positions
+        .iter()
+        .map(|pos| {
+            let mut scores = Matrix::new();
+            scores.set(NonZeroU8::MIN, 0, Score::Cp(pos.moves.len() as i64));
+            PositionResponse {
+                work: pos.work.clone(),
+                position_index: pos.position_index,
+                url: pos.url.clone(),
+                scores,
+                pvs: Matrix::new(),
+                best_move: None,
+                depth: 0,
+                nodes: 0,
+                time: Duration::ZERO,
+                nps: None,
+                stopped_early: false,
+                cached: false,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::AnalysisPart, configure::Verbose};
+
+    #[tokio::test]
+    async fn test_replay_with_skipped_positions() {
+        let body: AcquireResponseBody = serde_json::from_str(
+            r#"{
+                "work": {"type": "analysis", "id": "abcd1234", "nodes": {"sf16": 1500000, "classical": 4050000}, "timeout": 7000},
+                "position": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "variant": "standard",
+                "moves": "e2e4 c7c5 c2c4 b8c6 g1e2 g8f6 b1c3 c6b4",
+                "skipPositions": [1, 2]
+            }"#,
+        )
+        .expect("valid fixture");
+
+        let replay = replay(
+            body,
+            &ReplayEngine::Stub,
+            &Logger::new(Verbose::default(), true),
+        )
+        .await
+        .expect("replay");
+        let index = |i| Some(PositionIndex(i));
+        assert_eq!(
+            replay.chunks,
+            [
+                vec![index(8), index(7), index(6), index(5), index(4)],
+                vec![None, index(3), None, index(0)],
+            ]
+        );
+
+        let ReplayOutcome::Analysis(result) = replay.outcome else {
+            panic!("expected analysis");
+        };
+        assert_eq!(result.analysis.len(), 9);
+        for (ply, part) in result.analysis.into_iter().enumerate() {
+            match part {
+                Some(AnalysisPart::Skipped { .. }) => assert!(ply == 1 || ply == 2),
+                Some(AnalysisPart::Best { score, .. }) => {
+                    assert!(matches!(score, Score::Cp(cp) if cp == ply as i64));
+                }
+                _ => panic!("unexpected part at ply {ply}"),
+            }
+        }
+    }
+}
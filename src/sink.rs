@@ -0,0 +1,308 @@
+//! Destinations for completed analysis.
+
+use std::{
+    fs::OpenOptions,
+    io,
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use reqwest::Client;
+use serde::Serialize;
+use serde_with::{DisplayFromStr, serde_as};
+use shakmaty::{fen::Fen, variant::Variant};
+use tokio::{sync::mpsc, task};
+use url::Url;
+
+use crate::{
+    api::{AnalysisPart, ApiStub, BatchId},
+    archive::{Archive, PgnGame, annotated_pgn},
+    assets::{ByEngineFlavor, EngineFlavor, EvalFlavor},
+    configure::ResultSinkSpec,
+    ipc::Moves,
+    logger::Logger,
+    stockfish::EngineId,
+    util::NevermindExt as _,
+};
+
+/// Complete analysis of a batch.
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalysisResult {
+    #[serde_as(as = "DisplayFromStr")]
+    pub batch_id: BatchId,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub url: Option<Url>,
+    #[serde(skip)]
+    pub engine: EngineFlavor,
+    pub flavor: EvalFlavor,
+    #[serde_as(as = "DisplayFromStr")]
+    pub variant: Variant,
+    /// Root position of the game, as acquired.
+    #[serde(skip)]
+    pub root_fen: Fen,
+    /// Moves of the game, in UCI notation for Chess960.
+    #[serde(skip)]
+    pub moves: Moves,
+    pub analysis: Vec<Option<AnalysisPart>>,
+}
+
+/// Receives each complete analysis. Sinks must not block, because they are
+/// called while the queue is locked.
+pub trait ResultSink: Send {
+    fn submit_analysis(&mut self, result: &AnalysisResult);
+}
+
+/// Submits to the endpoint that the batch was acquired from. Always the
+/// first sink.
+impl ResultSink for ApiStub {
+    fn submit_analysis(&mut self, result: &AnalysisResult) {
+        ApiStub::submit_analysis(
+            self,
+            result.batch_id,
+            result.engine,
+            result.flavor,
+            result.analysis.clone(),
+        );
+    }
+}
+
+/// Appends each result as a line of JSON, in the background.
+pub struct FileSink {
+    tx: mpsc::UnboundedSender<(BatchId, Vec<u8>)>,
+}
+
+impl FileSink {
+    pub fn spawn(path: PathBuf, logger: Logger) -> FileSink {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(BatchId, Vec<u8>)>();
+        tokio::spawn(async move {
+            let path = Arc::new(path);
+            while let Some((batch_id, line)) = rx.recv().await {
+                let res = task::spawn_blocking({
+                    let path = Arc::clone(&path);
+                    move || append(&path, &line)
+                })
+                .await
+                .expect("append to file sink");
+                if let Err(err) = res {
+                    logger.warn(&format!(
+                        "Failed to write result of batch {batch_id} to {}: {err}",
+                        path.display()
+                    ));
+                }
+            }
+        });
+        FileSink { tx }
+    }
+}
+
+fn append(path: &Path, line: &[u8]) -> io::Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line)
+}
+
+impl ResultSink for FileSink {
+    fn submit_analysis(&mut self, result: &AnalysisResult) {
+        if let Ok(mut line) = serde_json::to_vec(result) {
+            line.push(b'\n');
+            self.tx
+                .send((result.batch_id, line))
+                .nevermind("file sink dropped");
+        }
+    }
+}
+
+/// Posts each result as JSON to a URL, in the background.
+pub struct WebhookSink {
+    tx: mpsc::UnboundedSender<(BatchId, Vec<u8>)>,
+}
+
+impl WebhookSink {
+    pub fn spawn(url: Url, client: Client, logger: Logger) -> WebhookSink {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(BatchId, Vec<u8>)>();
+        tokio::spawn(async move {
+            while let Some((batch_id, body)) = rx.recv().await {
+                let res = client
+                    .post(url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .timeout(Duration::from_secs(30))
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status());
+                if let Err(err) = res {
+                    logger.warn(&format!(
+                        "Failed to post result of batch {batch_id} to {url}: {err}"
+                    ));
+                }
+            }
+        });
+        WebhookSink { tx }
+    }
+}
+
+impl ResultSink for WebhookSink {
+    fn submit_analysis(&mut self, result: &AnalysisResult) {
+        if let Ok(body) = serde_json::to_vec(result) {
+            self.tx
+                .send((result.batch_id, body))
+                .nevermind("webhook sink dropped");
+        }
+    }
+}
+
+/// Engine that produced an archived analysis.
+#[derive(Debug, Serialize)]
+struct ArchivedEngine {
+    flavor: EvalFlavor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arch: Option<String>,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize)]
+struct ArchivedGame {
+    #[serde_as(as = "DisplayFromStr")]
+    position: Fen,
+    #[serde_as(as = "DisplayFromStr")]
+    variant: Variant,
+    moves: String,
+}
+
+#[serde_as]
+#[derive(Debug, Serialize)]
+struct ArchiveRecord<'a> {
+    #[serde_as(as = "DisplayFromStr")]
+    batch_id: BatchId,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    url: Option<&'a Url>,
+    submitted_at: u64,
+    game: ArchivedGame,
+    stockfish: &'a ArchivedEngine,
+    analysis: &'a [Option<AnalysisPart>],
+}
+
+/// Appends each result to the archive given with --archive-dir, together
+/// with an annotated PGN of the game, in the background.
+pub struct ArchiveSink {
+    engine_ids: Arc<ByEngineFlavor<EngineId>>,
+    tx: mpsc::UnboundedSender<(AnalysisResult, ArchivedEngine)>,
+}
+
+impl ArchiveSink {
+    pub fn spawn(
+        archive: Archive,
+        engine_ids: Arc<ByEngineFlavor<EngineId>>,
+        logger: Logger,
+    ) -> ArchiveSink {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(AnalysisResult, ArchivedEngine)>();
+        tokio::spawn(async move {
+            let archive = Arc::new(archive);
+            while let Some((result, engine)) = rx.recv().await {
+                // Compression and PGN export take a moment for long games.
+                task::spawn_blocking({
+                    let archive = Arc::clone(&archive);
+                    let logger = logger.clone();
+                    move || write_archive(&archive, &result, &engine, &logger)
+                })
+                .await
+                .expect("write archive");
+            }
+        });
+        ArchiveSink { engine_ids, tx }
+    }
+}
+
+impl ResultSink for ArchiveSink {
+    fn submit_analysis(&mut self, result: &AnalysisResult) {
+        let engine_id = self.engine_ids.get(result.engine);
+        let engine = ArchivedEngine {
+            flavor: result.flavor,
+            name: engine_id.name.get().cloned(),
+            arch: engine_id.arch.clone(),
+        };
+        self.tx
+            .send((result.clone(), engine))
+            .nevermind("archive sink dropped");
+    }
+}
+
+fn write_archive(
+    archive: &Archive,
+    result: &AnalysisResult,
+    engine: &ArchivedEngine,
+    logger: &Logger,
+) {
+    let batch_id = result.batch_id;
+    let record = ArchiveRecord {
+        batch_id,
+        url: result.url.as_ref(),
+        submitted_at: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        game: ArchivedGame {
+            position: result.root_fen.clone(),
+            variant: result.variant,
+            moves: result
+                .moves
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+        },
+        stockfish: engine,
+        analysis: &result.analysis,
+    };
+    if let Err(err) = archive.append(&record) {
+        logger.warn(&format!(
+            "Failed to archive analysis of batch {batch_id} in {}: {err}",
+            archive.dir().display()
+        ));
+    }
+
+    let Some(pgn) = annotated_pgn(
+        &PgnGame {
+            site: result.url.as_ref().map(Url::as_str),
+            annotator: engine.name.as_deref(),
+            variant: result.variant,
+            position: &result.root_fen,
+            moves: &result.moves,
+        },
+        &result.analysis,
+    ) else {
+        logger.warn(&format!("Failed to replay batch {batch_id} for PGN export"));
+        return;
+    };
+    if let Err(err) = archive.write_pgn(batch_id, &pgn) {
+        logger.warn(&format!(
+            "Failed to write PGN of batch {batch_id} to {}: {err}",
+            archive.dir().display()
+        ));
+    }
+}
+
+/// Creates the additional sinks given with --result-sink.
+pub fn from_specs(
+    specs: Vec<ResultSinkSpec>,
+    client: &Client,
+    logger: &Logger,
+) -> Vec<Box<dyn ResultSink>> {
+    specs
+        .into_iter()
+        .map(|spec| -> Box<dyn ResultSink> {
+            match spec {
+                ResultSinkSpec::File(path) => Box::new(FileSink::spawn(path, logger.clone())),
+                ResultSinkSpec::Webhook(url) => {
+                    Box::new(WebhookSink::spawn(url, client.clone(), logger.clone()))
+                }
+            }
+        })
+        .collect()
+}
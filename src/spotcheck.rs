@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use fastrand::Rng;
+use shakmaty::variant::Variant;
+use tokio::time::Instant;
+use url::Url;
+
+use crate::{
+    api::{BatchId, PositionIndex, Score, Work},
+    assets::EngineFlavor,
+    ipc::{Chunk, Position, PositionResponse},
+};
+
+/// Re-analyses a random sample of completed positions with half the nodes
+/// and compares the results, to detect unstable (for example overclocked)
+/// machines producing bad evaluations.
+pub struct SpotChecker {
+    rate: f64,
+    rng: Rng,
+    next_id: u64,
+    sampled: HashMap<(BatchId, PositionIndex), Sampled>,
+    running: HashMap<BatchId, Running>,
+    pub checked: u64,
+    pub disagreed: u64,
+}
+
+struct Sampled {
+    position: Position,
+    variant: Variant,
+    flavor: EngineFlavor,
+}
+
+struct Running {
+    batch_id: BatchId,
+    url: Option<Url>,
+    expected: Score,
+}
+
+pub struct SpotCheckOutcome {
+    pub batch_id: BatchId,
+    pub url: Option<Url>,
+    pub expected: Score,
+    pub actual: Score,
+}
+
+impl SpotCheckOutcome {
+    pub fn agrees(&self) -> bool {
+        scores_agree(self.expected, self.actual)
+    }
+}
+
+impl SpotChecker {
+    pub fn new(rate: f64) -> SpotChecker {
+        SpotChecker {
+            rate,
+            rng: Rng::new(),
+            next_id: 0,
+            sampled: HashMap::new(),
+            running: HashMap::new(),
+            checked: 0,
+            disagreed: 0,
+        }
+    }
+
+    /// Remember some of the positions of a chunk that is about to be
+    /// analysed.
+    pub fn sample(&mut self, chunk: &Chunk) {
+        if self.rate <= 0.0 || !chunk.work.is_analysis() || self.is_spot_check(chunk.work.id()) {
+            return;
+        }
+        for pos in &chunk.positions {
+            if let Some(position_index) = pos.position_index {
+                if !pos.skip && self.rng.f64() < self.rate {
+                    self.sampled.insert(
+                        (pos.work.id(), position_index),
+                        Sampled {
+                            position: pos.clone(),
+                            variant: chunk.variant,
+                            flavor: chunk.flavor,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Given a completed response, returns a chunk to verify it, if the
+    /// position was sampled.
+    pub fn verification_chunk(&mut self, res: &PositionResponse) -> Option<Chunk> {
+        let position_index = res.position_index?;
+        let sampled = self.sampled.remove(&(res.work.id(), position_index))?;
+        let expected = res.scores.best().copied()?;
+        // Time-limited analysis is not reproducible.
+        let Work::Analysis {
+            nodes,
+            depth,
+            movetime: None,
+            timeout,
+            ..
+        } = sampled.position.work
+        else {
+            return None;
+        };
+
+        self.next_id += 1;
+        let id: BatchId = format!("spot-check-{}", self.next_id)
+            .parse()
+            .expect("spot check id fits");
+        let work = Work::Analysis {
+            id,
+            nodes: nodes.halved(),
+            depth,
+            multipv: None,
+            movetime: None,
+            timeout,
+        };
+
+        self.running.insert(
+            id,
+            Running {
+                batch_id: res.work.id(),
+                url: sampled.position.url.clone(),
+                expected,
+            },
+        );
+
+        Some(Chunk {
+            work: work.clone(),
+            deadline: Instant::now() + timeout,
+            variant: sampled.variant,
+            flavor: sampled.flavor,
+            positions: vec![Position {
+                work,
+                ..sampled.position
+            }],
+            nnue_nps: None,
+            retried: false,
+        })
+    }
+
+    /// Returns whether the batch is a spot check, rather than real work.
+    pub fn is_spot_check(&self, batch_id: BatchId) -> bool {
+        self.running.contains_key(&batch_id)
+    }
+
+    /// Consumes the response to a verification chunk.
+    pub fn complete(&mut self, res: &PositionResponse) -> Option<SpotCheckOutcome> {
+        let running = self.running.remove(&res.work.id())?;
+        let actual = res.scores.best().copied()?;
+        let outcome = SpotCheckOutcome {
+            batch_id: running.batch_id,
+            url: running.url,
+            expected: running.expected,
+            actual,
+        };
+        self.checked += 1;
+        if !outcome.agrees() {
+            self.disagreed += 1;
+        }
+        Some(outcome)
+    }
+
+    /// Forget about samples from a batch that failed or was dropped.
+    pub fn forget(&mut self, batch_id: BatchId) {
+        self.sampled.retain(|(b, _), _| *b != batch_id);
+        self.running.remove(&batch_id);
+    }
+}
+
+fn scores_agree(a: Score, b: Score) -> bool {
+    match (a, b) {
+        (Score::Cp(a), Score::Cp(b)) => a.abs_diff(b) <= 300,
+        (Score::Mate(a), Score::Mate(b)) => a.signum() == b.signum(),
+        (Score::Mate(mate), Score::Cp(cp)) | (Score::Cp(cp), Score::Mate(mate)) => {
+            mate.signum() == cp.signum() && cp.abs() >= 500
+        }
+    }
+}
@@ -1,25 +1,36 @@
 use std::{
     cmp::{max, min},
+    collections::BTreeMap,
     env, fmt,
     fs::{File, OpenOptions},
     io,
     io::{Read as _, Seek as _, Write as _},
     num::NonZeroUsize,
+    ops::{Add, Sub},
     path::PathBuf,
-    time::Duration,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
 use serde::{Deserialize, Serialize};
 
-use crate::configure::StatsOpt;
+use crate::{configure::StatsOpt, logger::Logger};
 
-fn default_stats_file() -> Option<PathBuf> {
+pub fn default_stats_file() -> Option<PathBuf> {
     env::home_dir().map(|dir| dir.join(".fishnet-stats"))
 }
 
 pub struct StatsRecorder {
-    pub stats: Stats,
+    endpoint: String,
+    by_endpoint: BTreeMap<String, Stats>,
+    unsaved: Stats,
+    api_errors: Arc<ApiErrorCounters>,
+    saved_counters: Stats,
     pub nnue_nps: NpsRecorder,
+    nps_monitor: NpsMonitor,
     store: Option<(PathBuf, File)>,
     cores: NonZeroUsize,
 }
@@ -29,10 +40,216 @@ pub struct Stats {
     pub total_batches: u64,
     pub total_positions: u64,
     pub total_nodes: u64,
+    #[serde(default)]
+    pub api_errors: ApiErrors,
+    #[serde(default)]
+    pub failures: Failures,
 }
 
-impl Stats {
-    fn load_from(file: &mut File) -> io::Result<Option<Stats>> {
+impl Add for Stats {
+    type Output = Stats;
+
+    fn add(self, other: Stats) -> Stats {
+        Stats {
+            total_batches: self.total_batches + other.total_batches,
+            total_positions: self.total_positions + other.total_positions,
+            total_nodes: self.total_nodes + other.total_nodes,
+            api_errors: self.api_errors + other.api_errors,
+            failures: self.failures + other.failures,
+        }
+    }
+}
+
+impl Sub for Stats {
+    type Output = Stats;
+
+    fn sub(self, other: Stats) -> Stats {
+        Stats {
+            total_batches: self.total_batches - other.total_batches,
+            total_positions: self.total_positions - other.total_positions,
+            total_nodes: self.total_nodes - other.total_nodes,
+            api_errors: self.api_errors - other.api_errors,
+            failures: self.failures - other.failures,
+        }
+    }
+}
+
+/// Batches that could not be completed, to tell reliability problems apart
+/// from throughput problems.
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
+pub struct Failures {
+    pub engine_timeouts: u64,
+    pub engine_crashes: u64,
+    pub aborted: u64,
+    pub aborted_by_server: u64,
+}
+
+impl Failures {
+    pub fn is_empty(&self) -> bool {
+        self.engine_timeouts == 0
+            && self.engine_crashes == 0
+            && self.aborted == 0
+            && self.aborted_by_server == 0
+    }
+}
+
+impl Add for Failures {
+    type Output = Failures;
+
+    fn add(self, other: Failures) -> Failures {
+        Failures {
+            engine_timeouts: self.engine_timeouts + other.engine_timeouts,
+            engine_crashes: self.engine_crashes + other.engine_crashes,
+            aborted: self.aborted + other.aborted,
+            aborted_by_server: self.aborted_by_server + other.aborted_by_server,
+        }
+    }
+}
+
+impl Sub for Failures {
+    type Output = Failures;
+
+    fn sub(self, other: Failures) -> Failures {
+        Failures {
+            engine_timeouts: self.engine_timeouts - other.engine_timeouts,
+            engine_crashes: self.engine_crashes - other.engine_crashes,
+            aborted: self.aborted - other.aborted,
+            aborted_by_server: self.aborted_by_server - other.aborted_by_server,
+        }
+    }
+}
+
+impl fmt::Display for Failures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} engine timeouts, {} engine crashes, {} aborted, {} aborted by server",
+            self.engine_timeouts, self.engine_crashes, self.aborted, self.aborted_by_server
+        )
+    }
+}
+
+/// Kind of failure recorded by [`StatsRecorder::record_failure`].
+#[derive(Debug, Copy, Clone)]
+pub enum Failure {
+    EngineTimeout,
+    EngineCrash,
+    Aborted,
+}
+
+/// Contents of the stats file. The top-level totals sum up all endpoints,
+/// so that older versions can still read the file.
+#[derive(Default, Serialize, Deserialize)]
+struct StatsFile {
+    #[serde(flatten)]
+    total: Stats,
+    #[serde(default)]
+    endpoints: BTreeMap<String, Stats>,
+}
+
+/// Failed API requests. Rate limiting and timeouts are counted in addition
+/// to the kind of request that failed.
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
+pub struct ApiErrors {
+    pub acquire: u64,
+    pub submit: u64,
+    pub rate_limited: u64,
+    pub timeouts: u64,
+}
+
+impl ApiErrors {
+    pub fn is_empty(&self) -> bool {
+        self.acquire == 0 && self.submit == 0 && self.rate_limited == 0 && self.timeouts == 0
+    }
+}
+
+impl Add for ApiErrors {
+    type Output = ApiErrors;
+
+    fn add(self, other: ApiErrors) -> ApiErrors {
+        ApiErrors {
+            acquire: self.acquire + other.acquire,
+            submit: self.submit + other.submit,
+            rate_limited: self.rate_limited + other.rate_limited,
+            timeouts: self.timeouts + other.timeouts,
+        }
+    }
+}
+
+impl Sub for ApiErrors {
+    type Output = ApiErrors;
+
+    fn sub(self, other: ApiErrors) -> ApiErrors {
+        ApiErrors {
+            acquire: self.acquire - other.acquire,
+            submit: self.submit - other.submit,
+            rate_limited: self.rate_limited - other.rate_limited,
+            timeouts: self.timeouts - other.timeouts,
+        }
+    }
+}
+
+impl fmt::Display for ApiErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} acquire, {} submit, {} rate limited, {} timed out",
+            self.acquire, self.submit, self.rate_limited, self.timeouts
+        )
+    }
+}
+
+/// Counts failed API requests and batches aborted by the server since
+/// startup. Shared between the API actor and the stats recorder.
+#[derive(Debug, Default)]
+pub struct ApiErrorCounters {
+    acquire: AtomicU64,
+    submit: AtomicU64,
+    rate_limited: AtomicU64,
+    timeouts: AtomicU64,
+    aborted_by_server: AtomicU64,
+}
+
+impl ApiErrorCounters {
+    pub fn acquire_failed(&self) {
+        self.acquire.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn submit_failed(&self) {
+        self.submit.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn timed_out(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn aborted_by_server(&self) {
+        self.aborted_by_server.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Stats {
+        Stats {
+            api_errors: ApiErrors {
+                acquire: self.acquire.load(Ordering::Relaxed),
+                submit: self.submit.load(Ordering::Relaxed),
+                rate_limited: self.rate_limited.load(Ordering::Relaxed),
+                timeouts: self.timeouts.load(Ordering::Relaxed),
+            },
+            failures: Failures {
+                aborted_by_server: self.aborted_by_server.load(Ordering::Relaxed),
+                ..Failures::default()
+            },
+            ..Stats::default()
+        }
+    }
+}
+
+impl StatsFile {
+    fn load_from(mut file: &File) -> io::Result<Option<StatsFile>> {
         file.rewind()?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
@@ -46,27 +263,96 @@ impl Stats {
         })
     }
 
-    fn save_to(&self, file: &mut File) -> io::Result<()> {
+    fn save_to(by_endpoint: &BTreeMap<String, Stats>, mut file: &File) -> io::Result<()> {
+        let contents = StatsFile {
+            total: by_endpoint
+                .values()
+                .cloned()
+                .fold(Stats::default(), Add::add),
+            endpoints: by_endpoint.clone(),
+        };
         file.set_len(0)?;
         file.rewind()?;
         file.write_all(
-            serde_json::to_string_pretty(&self)
+            serde_json::to_string_pretty(&contents)
                 .expect("serialize stats")
                 .as_bytes(),
         )?;
         Ok(())
     }
+
+    fn into_endpoints(self, endpoint: &str) -> BTreeMap<String, Stats> {
+        let mut endpoints = self.endpoints;
+        if endpoints.is_empty() && self.total.total_batches > 0 {
+            // Written by an older version, that did not distinguish
+            // endpoints.
+            endpoints.insert(endpoint.to_owned(), self.total);
+        }
+        endpoints
+    }
+}
+
+/// Advisory lock on the stats file, so that concurrent instances do not
+/// lose each other's updates. Released when dropped.
+struct FileLock<'a> {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    file: &'a File,
+}
+
+impl FileLock<'_> {
+    #[cfg(unix)]
+    #[allow(unsafe_code)]
+    fn exclusive(file: &File) -> io::Result<FileLock<'_>> {
+        use std::os::fd::AsRawFd as _;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            Ok(FileLock { file })
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn exclusive(file: &File) -> io::Result<FileLock<'_>> {
+        // Not locking, but still merging with the latest contents.
+        Ok(FileLock { file })
+    }
+}
+
+impl Drop for FileLock<'_> {
+    #[cfg(unix)]
+    #[allow(unsafe_code)]
+    fn drop(&mut self) {
+        use std::os::fd::AsRawFd as _;
+        unsafe {
+            libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn drop(&mut self) {}
 }
 
 impl StatsRecorder {
-    pub fn new(opt: StatsOpt, cores: NonZeroUsize) -> StatsRecorder {
+    pub fn new(
+        opt: StatsOpt,
+        endpoint: String,
+        cores: NonZeroUsize,
+        api_errors: Arc<ApiErrorCounters>,
+        logger: &Logger,
+    ) -> StatsRecorder {
         let nnue_nps = NpsRecorder::new();
+        let nps_monitor = NpsMonitor::new(&opt);
 
         if opt.no_stats_file {
             return StatsRecorder {
-                stats: Stats::default(),
+                endpoint,
+                by_endpoint: BTreeMap::new(),
+                unsaved: Stats::default(),
+                api_errors,
+                saved_counters: Stats::default(),
                 store: None,
                 nnue_nps,
+                nps_monitor,
                 cores,
             };
         }
@@ -74,67 +360,151 @@ impl StatsRecorder {
         let path = if let Some(path) = opt.stats_file.or_else(default_stats_file) {
             path
         } else {
-            eprintln!("E: Could not resolve ~/.fishnet-stats");
+            logger.error("Could not resolve ~/.fishnet-stats");
             return StatsRecorder {
-                stats: Stats::default(),
+                endpoint,
+                by_endpoint: BTreeMap::new(),
+                unsaved: Stats::default(),
+                api_errors,
+                saved_counters: Stats::default(),
                 store: None,
                 nnue_nps,
+                nps_monitor,
                 cores,
             };
         };
 
-        let (stats, store) = match OpenOptions::new()
+        let (by_endpoint, store) = match OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .truncate(false)
             .open(&path)
         {
-            Ok(mut file) => (
-                match Stats::load_from(&mut file) {
-                    Ok(Some(stats)) => {
-                        println!("Resuming from {path:?} ...");
-                        stats
+            Ok(file) => (
+                match FileLock::exclusive(&file).and_then(|_lock| StatsFile::load_from(&file)) {
+                    Ok(Some(contents)) => {
+                        logger.info(&format!("Resuming from {path:?} ..."));
+                        contents.into_endpoints(&endpoint)
                     }
                     Ok(None) => {
-                        println!("Recording to new stats file {path:?} ...");
-                        Stats::default()
+                        logger.info(&format!("Recording to new stats file {path:?} ..."));
+                        BTreeMap::new()
                     }
                     Err(err) => {
-                        eprintln!("E: Failed to resume from {path:?}: {err}. Resetting ...");
-                        Stats::default()
+                        logger.error(&format!(
+                            "Failed to resume from {path:?}: {err}. Resetting ..."
+                        ));
+                        BTreeMap::new()
                     }
                 },
                 Some((path, file)),
             ),
             Err(err) => {
-                eprintln!("E: Failed to open {path:?}: {err}");
-                (Stats::default(), None)
+                logger.error(&format!("Failed to open {path:?}: {err}"));
+                (BTreeMap::new(), None)
             }
         };
 
         StatsRecorder {
-            stats,
+            endpoint,
+            by_endpoint,
+            unsaved: Stats::default(),
+            api_errors,
+            saved_counters: Stats::default(),
             store,
             nnue_nps,
+            nps_monitor,
             cores,
         }
     }
 
-    pub fn record_batch(&mut self, positions: u64, nodes: u64, nnue_nps: Option<u32>) {
-        self.stats.total_batches += 1;
-        self.stats.total_positions += positions;
-        self.stats.total_nodes += nodes;
+    pub fn record_batch(
+        &mut self,
+        positions: u64,
+        nodes: u64,
+        nnue_nps: Option<u32>,
+    ) -> Option<NpsChange> {
+        self.unsaved.total_batches += 1;
+        self.unsaved.total_positions += positions;
+        self.unsaved.total_nodes += nodes;
 
-        if let Some(nnue_nps) = nnue_nps {
+        let change = nnue_nps.and_then(|nnue_nps| {
             self.nnue_nps.record(nnue_nps);
+            self.nps_monitor.record(&self.nnue_nps)
+        });
+
+        self.save();
+
+        change
+    }
+
+    pub fn record_failure(&mut self, failure: Failure) {
+        let failures = &mut self.unsaved.failures;
+        match failure {
+            Failure::EngineTimeout => failures.engine_timeouts += 1,
+            Failure::EngineCrash => failures.engine_crashes += 1,
+            Failure::Aborted => failures.aborted += 1,
         }
+        self.save();
+    }
+
+    /// Adds unsaved totals to the stats file. Rereads the file while holding
+    /// a lock, so that other instances using the same file are not
+    /// overwritten.
+    fn save(&mut self) {
+        let counters = self.api_errors.get();
+        let unsaved = self.unsaved.clone() + (counters.clone() - self.saved_counters.clone());
 
-        if let Some((ref path, ref mut stats_file)) = self.store {
-            if let Err(err) = self.stats.save_to(stats_file) {
-                eprintln!("E: Failed to write stats to {path:?}: {err}");
+        if let Some((ref path, ref file)) = self.store {
+            let res = FileLock::exclusive(file).and_then(|_lock| {
+                let mut by_endpoint = match StatsFile::load_from(file) {
+                    Ok(Some(contents)) => contents.into_endpoints(&self.endpoint),
+                    Ok(None) => BTreeMap::new(),
+                    Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                        self.by_endpoint.clone()
+                    }
+                    Err(err) => return Err(err),
+                };
+                let stats = by_endpoint.entry(self.endpoint.clone()).or_default();
+                *stats = stats.clone() + unsaved;
+                StatsFile::save_to(&by_endpoint, file)?;
+                Ok(by_endpoint)
+            });
+            match res {
+                Ok(by_endpoint) => self.by_endpoint = by_endpoint,
+                Err(err) => {
+                    // Keep unsaved totals, and try again next time.
+                    eprintln!("E: Failed to write stats to {path:?}: {err}");
+                    return;
+                }
             }
+        } else {
+            let stats = self.by_endpoint.entry(self.endpoint.clone()).or_default();
+            *stats = stats.clone() + unsaved;
         }
+
+        self.unsaved = Stats::default();
+        self.saved_counters = counters;
+    }
+
+    /// Totals for the current endpoint, including API errors since startup.
+    pub fn stats(&self) -> Stats {
+        self.by_endpoint
+            .get(&self.endpoint)
+            .cloned()
+            .unwrap_or_default()
+            + self.unsaved.clone()
+            + (self.api_errors.get() - self.saved_counters.clone())
+    }
+
+    /// How long to hold off acquiring work, while throughput is reduced.
+    pub fn nps_pause(&self) -> Duration {
+        self.nps_monitor
+            .paused_until
+            .map_or(Duration::ZERO, |until| {
+                until.saturating_duration_since(Instant::now())
+            })
     }
 
     pub fn min_user_backlog(&self) -> Duration {
@@ -152,6 +522,75 @@ impl StatsRecorder {
     }
 }
 
+/// Sustained change of throughput, compared to the long-term average.
+#[derive(Debug, Copy, Clone)]
+pub enum NpsChange {
+    Dropped { nps: u32, baseline: u32 },
+    Recovered { nps: u32, baseline: u32 },
+}
+
+/// Compares the recent nps estimate to a slowly moving baseline.
+struct NpsMonitor {
+    threshold: Option<f64>,
+    pause: bool,
+    baseline: Option<f64>,
+    dropped: bool,
+    paused_until: Option<Instant>,
+}
+
+impl NpsMonitor {
+    const PAUSE: Duration = Duration::from_secs(5 * 60);
+
+    fn new(opt: &StatsOpt) -> NpsMonitor {
+        NpsMonitor {
+            threshold: opt.nps_drop.map(|percent| f64::from(percent) / 100.0),
+            pause: opt.pause_on_nps_drop,
+            baseline: None,
+            dropped: false,
+            paused_until: None,
+        }
+    }
+
+    fn record(&mut self, recorder: &NpsRecorder) -> Option<NpsChange> {
+        let threshold = self.threshold?;
+        let nps = recorder.confident()?;
+        let Some(baseline) = self.baseline else {
+            self.baseline = Some(f64::from(nps));
+            return None;
+        };
+
+        let change = if !self.dropped && f64::from(nps) < baseline * (1.0 - threshold) {
+            self.dropped = true;
+            Some(NpsChange::Dropped {
+                nps,
+                baseline: baseline as u32,
+            })
+        } else if self.dropped && f64::from(nps) >= baseline * (1.0 - threshold / 2.0) {
+            self.dropped = false;
+            Some(NpsChange::Recovered {
+                nps,
+                baseline: baseline as u32,
+            })
+        } else {
+            None
+        };
+
+        if self.dropped {
+            // Keep the baseline, and give the machine time to cool down
+            // before each further batch.
+            if self.pause {
+                self.paused_until = Some(Instant::now() + NpsMonitor::PAUSE);
+            }
+        } else {
+            let alpha = 0.98;
+            self.baseline = Some(baseline * alpha + f64::from(nps) * (1.0 - alpha));
+            self.paused_until = None;
+        }
+
+        change
+    }
+}
+
 #[derive(Clone)]
 pub struct NpsRecorder {
     pub nps: u32,
@@ -166,6 +605,11 @@ impl NpsRecorder {
         }
     }
 
+    /// The estimate, unless it is still very uncertain.
+    pub fn confident(&self) -> Option<u32> {
+        Some(self.nps).filter(|_| self.uncertainty <= 0.4)
+    }
+
     fn record(&mut self, nps: u32) {
         let alpha = 0.9;
         self.uncertainty *= alpha;
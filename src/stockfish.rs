@@ -1,61 +1,237 @@
-use std::{io, mem, num::NonZeroU8, path::PathBuf, process::Stdio, time::Duration};
+use std::{
+    io, mem,
+    num::{NonZeroU8, NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
-use shakmaty::uci::UciMove;
+use shakmaty::{fen::Fen, uci::UciMove, variant::Variant};
 use tokio::{
     io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader, BufWriter, Lines},
     process::{ChildStdin, ChildStdout, Command},
-    sync::{mpsc, oneshot},
+    sync::{Notify, mpsc, oneshot},
 };
 
 use crate::{
-    api::{Score, Work},
-    assets::{EngineFlavor, EvalFlavor},
-    ipc::{Chunk, ChunkFailed, Matrix, Position, PositionResponse},
+    api::{BatchId, Score, Work},
+    assets::{ByEngineFlavor, EngineFlavor, EvalFlavor},
+    configure::{CpuPriority, NodeMultipliers},
+    ipc::{Chunk, ChunkFailed, MAX_DEPTH, Matrix, Position, PositionResponse},
     logger::Logger,
+    syzygy::Tablebases,
+    transcript::Transcript,
     util::NevermindExt as _,
 };
 
-pub fn channel(exe: PathBuf, logger: Logger) -> (StockfishStub, StockfishActor) {
+/// Settings shared by all engine processes.
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    pub node_multipliers: NodeMultipliers,
+    pub tablebases: Option<Arc<Tablebases>>,
+    pub ids: Arc<ByEngineFlavor<EngineId>>,
+    /// Replace engine processes after this many chunks.
+    pub recycle_chunks: Option<NonZeroU32>,
+    /// Replace engine processes after running this long.
+    pub recycle_age: Option<Duration>,
+    pub cpu_priority: CpuPriority,
+    /// Keep the hash table between consecutive chunks of the same batch.
+    pub keep_hash: bool,
+    /// Override the engine default for Move Overhead.
+    pub move_overhead: Option<Duration>,
+    /// Receives analysed positions as soon as they are done, ahead of the
+    /// complete chunk.
+    pub progress: Option<mpsc::UnboundedSender<PositionResponse>>,
+    /// Record the UCI dialogue, to save transcripts of failed chunks to
+    /// this directory.
+    pub record_uci: Option<PathBuf>,
+}
+
+/// Identifies the engine that produced an analysis.
+#[derive(Debug, Default)]
+pub struct EngineId {
+    /// Selected build of the engine, known up front.
+    pub arch: Option<String>,
+    /// As reported by the engine (`id name`), once started.
+    pub name: OnceLock<String>,
+}
+
+/// Runs the bench command of the given engine and returns the number of
+/// nodes searched, which is a signature of its search behavior.
+pub async fn bench(exe: &Path) -> io::Result<Option<u64>> {
+    run_bench(exe, &[], "Nodes searched").await
+}
+
+/// Searches the starting position with the given number of threads for the
+/// given time, and returns the nodes per second.
+pub async fn bench_nps(
+    exe: &Path,
+    threads: NonZeroUsize,
+    movetime: Duration,
+) -> io::Result<Option<u64>> {
+    run_bench(
+        exe,
+        &[
+            "16".to_owned(),
+            threads.to_string(),
+            movetime.as_millis().to_string(),
+            "current".to_owned(),
+            "movetime".to_owned(),
+        ],
+        "Nodes/second",
+    )
+    .await
+}
+
+async fn run_bench(exe: &Path, args: &[String], result: &str) -> io::Result<Option<u64>> {
+    let output = Command::new(exe)
+        .current_dir(exe.parent().expect("absolute path"))
+        .arg("bench")
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "bench exited with {}",
+            output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stdout).lines())
+        .find_map(|line| {
+            line.strip_prefix(result)?
+                .trim_start_matches([' ', ':'])
+                .trim()
+                .parse()
+                .ok()
+        }))
+}
+
+pub fn channel(
+    exe: PathBuf,
+    options: EngineOptions,
+    logger: Logger,
+) -> (StockfishStub, StockfishActor) {
     let (tx, rx) = mpsc::channel(1);
+    let stop = Arc::new(Notify::new());
+    let transcript = options.record_uci.is_some().then(Transcript::default);
     (
-        StockfishStub { tx },
+        StockfishStub {
+            tx,
+            stop: stop.clone(),
+            transcript: transcript.clone(),
+        },
         StockfishActor {
             rx,
             exe,
+            options,
             initialized: false,
+            tablebases_loaded: false,
+            last_batch: None,
+            stop,
+            stopped: false,
+            transcript,
             logger,
         },
     )
 }
 
+/// Analyses chunks. Implemented by engine processes, and by simulated
+/// engines in tests.
+pub trait Engine {
+    fn go_multiple(
+        &self,
+        chunk: Chunk,
+    ) -> impl Future<Output = Result<Vec<PositionResponse>, ChunkFailed>> + Send;
+
+    fn stop(&self);
+}
+
 pub struct StockfishStub {
     tx: mpsc::Sender<StockfishMessage>,
+    stop: Arc<Notify>,
+    transcript: Option<Transcript>,
 }
 
 impl StockfishStub {
-    pub async fn go_multiple(
-        &mut self,
-        chunk: Chunk,
-    ) -> Result<Vec<PositionResponse>, ChunkFailed> {
+    pub async fn go_multiple(&self, chunk: Chunk) -> Result<Vec<PositionResponse>, ChunkFailed> {
         let (callback, responses) = oneshot::channel();
         let batch_id = chunk.work.id();
         self.tx
             .send(StockfishMessage::GoMultiple { chunk, callback })
             .await
-            .map_err(|_| ChunkFailed { batch_id })?;
-        responses.await.map_err(|_| ChunkFailed { batch_id })
+            .map_err(|_| ChunkFailed {
+                batch_id,
+                timed_out: false,
+                retry: None,
+                completed: Vec::new(),
+            })?;
+        responses.await.map_err(|_| ChunkFailed {
+            batch_id,
+            timed_out: false,
+            retry: None,
+            completed: Vec::new(),
+        })
+    }
+
+    /// Initializes the engine ahead of time, so that it is ready when the
+    /// first chunk arrives.
+    pub async fn warm_up(&self, flavor: EngineFlavor) {
+        self.tx
+            .send(StockfishMessage::WarmUp { flavor })
+            .await
+            .nevermind("engine actor exited");
+    }
+
+    /// Asks the engine to stop searching. The pending
+    /// [`StockfishStub::go_multiple()`] then completes early, with the best
+    /// results so far, leaving out positions that were not started.
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+
+    /// Dialogue of the current or last chunk, if recording is enabled.
+    pub fn transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+}
+
+impl Engine for StockfishStub {
+    fn go_multiple(
+        &self,
+        chunk: Chunk,
+    ) -> impl Future<Output = Result<Vec<PositionResponse>, ChunkFailed>> + Send {
+        StockfishStub::go_multiple(self, chunk)
+    }
+
+    fn stop(&self) {
+        StockfishStub::stop(self);
     }
 }
 
 pub struct StockfishActor {
     rx: mpsc::Receiver<StockfishMessage>,
     exe: PathBuf,
+    options: EngineOptions,
     initialized: bool,
+    /// SyzygyPath is currently set, which is done only for move requests.
+    tablebases_loaded: bool,
+    last_batch: Option<BatchId>,
+    stop: Arc<Notify>,
+    stopped: bool,
+    transcript: Option<Transcript>,
     logger: Logger,
 }
 
 #[derive(Debug)]
 enum StockfishMessage {
+    WarmUp {
+        flavor: EngineFlavor,
+    },
     GoMultiple {
         chunk: Chunk,
         callback: oneshot::Sender<Vec<PositionResponse>>,
@@ -64,17 +240,22 @@ enum StockfishMessage {
 
 struct Stdout {
     inner: Lines<BufReader<ChildStdout>>,
+    transcript: Option<Transcript>,
 }
 
 impl Stdout {
-    fn new(inner: ChildStdout) -> Stdout {
+    fn new(inner: ChildStdout, transcript: Option<Transcript>) -> Stdout {
         Stdout {
             inner: BufReader::new(inner).lines(),
+            transcript,
         }
     }
 
     async fn read_line(&mut self) -> io::Result<String> {
         if let Some(line) = self.inner.next_line().await? {
+            if let Some(ref transcript) = self.transcript {
+                transcript.output(&line);
+            }
             Ok(line)
         } else {
             Err(io::ErrorKind::UnexpectedEof.into())
@@ -84,16 +265,21 @@ impl Stdout {
 
 struct Stdin {
     inner: BufWriter<ChildStdin>,
+    transcript: Option<Transcript>,
 }
 
 impl Stdin {
-    fn new(inner: ChildStdin) -> Stdin {
+    fn new(inner: ChildStdin, transcript: Option<Transcript>) -> Stdin {
         Stdin {
             inner: BufWriter::new(inner),
+            transcript,
         }
     }
 
     async fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if let Some(ref transcript) = self.transcript {
+            transcript.input(line);
+        }
         self.inner.write_all(line.as_bytes()).await?;
         self.inner.write_all(b"\n").await
     }
@@ -133,6 +319,100 @@ fn new_process_group(command: &mut Command) -> &mut Command {
     command
 }
 
+/// Lowers the CPU and I/O priority of the engine process, which does the
+/// heavy lifting.
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn lower_priority(command: &mut Command, priority: CpuPriority) -> &mut Command {
+    if matches!(priority, CpuPriority::Unchanged) {
+        return command;
+    }
+
+    // Safety: Runs in the child process between fork and exec, where only
+    // async-signal-safe functions may be used. Failures are ignored, so that
+    // the engine starts in any case.
+    unsafe {
+        command.pre_exec(move || {
+            #[cfg(target_os = "linux")]
+            const MINIMUM_PRIORITY_NICENESS: libc::c_int = 19;
+            #[cfg(not(target_os = "linux"))]
+            const MINIMUM_PRIORITY_NICENESS: libc::c_int = 20;
+            libc::setpriority(libc::PRIO_PROCESS, 0, MINIMUM_PRIORITY_NICENESS);
+
+            #[cfg(target_os = "linux")]
+            {
+                // Like ionice -c 2 -n 7 (lowest best-effort priority), or
+                // ionice -c 3 (idle).
+                const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+                const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+                let ioprio = match priority {
+                    CpuPriority::Idle => 3 << IOPRIO_CLASS_SHIFT,
+                    _ => (2 << IOPRIO_CLASS_SHIFT) | 7,
+                };
+                libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio);
+
+                if matches!(priority, CpuPriority::Idle) {
+                    // Zeroed rather than a struct literal, because musl
+                    // has additional fields.
+                    let mut param: libc::sched_param = mem::zeroed();
+                    param.sched_priority = 0;
+                    libc::sched_setscheduler(0, libc::SCHED_IDLE, &param);
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(windows)]
+fn lower_priority(command: &mut Command, priority: CpuPriority) -> &mut Command {
+    use windows::Win32::System::Threading::{BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS};
+
+    let priority_class = match priority {
+        CpuPriority::Unchanged => return command,
+        CpuPriority::Min => BELOW_NORMAL_PRIORITY_CLASS,
+        CpuPriority::Idle => IDLE_PRIORITY_CLASS,
+    };
+
+    // Replaces the flags from new_process_group(), so keep those.
+    let create_new_process_group = 0x0000_0200;
+    command.creation_flags(create_new_process_group | priority_class.0)
+}
+
+/// Opts the engine process into EcoQoS, so that Windows schedules it on
+/// efficient cores at lower clock speeds.
+#[cfg(windows)]
+#[allow(unsafe_code)]
+fn enable_efficiency_mode(child: &tokio::process::Child) -> windows::core::Result<()> {
+    use std::ffi::c_void;
+
+    use windows::Win32::{
+        Foundation::HANDLE,
+        System::Threading::{
+            PROCESS_POWER_THROTTLING_CURRENT_VERSION, PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+            PROCESS_POWER_THROTTLING_STATE, ProcessPowerThrottling, SetProcessInformation,
+        },
+    };
+
+    let Some(handle) = child.raw_handle() else {
+        return Ok(());
+    };
+    let state = PROCESS_POWER_THROTTLING_STATE {
+        Version: PROCESS_POWER_THROTTLING_CURRENT_VERSION,
+        ControlMask: PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+        StateMask: PROCESS_POWER_THROTTLING_EXECUTION_SPEED,
+    };
+    unsafe {
+        SetProcessInformation(
+            HANDLE(handle),
+            ProcessPowerThrottling,
+            &state as *const PROCESS_POWER_THROTTLING_STATE as *const c_void,
+            size_of::<PROCESS_POWER_THROTTLING_STATE>() as u32,
+        )
+    }
+}
+
 impl StockfishActor {
     pub async fn run(self) {
         let logger = self.logger.clone();
@@ -142,12 +422,23 @@ impl StockfishActor {
     }
 
     async fn run_inner(mut self) -> Result<(), EngineError> {
-        let mut child = new_process_group(&mut Command::new(&self.exe))
-            .current_dir(self.exe.parent().expect("absolute path"))
-            .stdout(Stdio::piped())
-            .stdin(Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()?;
+        let mut child = lower_priority(
+            new_process_group(&mut Command::new(&self.exe)),
+            self.options.cpu_priority,
+        )
+        .current_dir(self.exe.parent().expect("absolute path"))
+        .stdout(Stdio::piped())
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+        #[cfg(windows)]
+        if !matches!(self.options.cpu_priority, CpuPriority::Unchanged) {
+            if let Err(err) = enable_efficiency_mode(&child) {
+                self.logger
+                    .debug(&format!("Failed to enable efficiency mode: {err}"));
+            }
+        }
 
         let pid = child.id().expect("pid");
         let mut stdout = Stdout::new(
@@ -155,12 +446,14 @@ impl StockfishActor {
                 .stdout
                 .take()
                 .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "stdout closed"))?,
+            self.transcript.clone(),
         );
         let mut stdin = Stdin::new(
             child
                 .stdin
                 .take()
                 .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "stdin closed"))?,
+            self.transcript.clone(),
         );
 
         loop {
@@ -196,10 +489,14 @@ impl StockfishActor {
         msg: StockfishMessage,
     ) -> Result<(), EngineError> {
         match msg {
+            StockfishMessage::WarmUp { flavor } => Ok(self.init(stdout, stdin, flavor).await?),
             StockfishMessage::GoMultiple {
                 mut callback,
                 chunk,
             } => {
+                if let Some(ref transcript) = self.transcript {
+                    transcript.begin_chunk();
+                }
                 tokio::select! {
                     _ = callback.closed() => Err(EngineError::Shutdown),
                     res = self.go_multiple(stdout, stdin, chunk) => {
@@ -211,11 +508,42 @@ impl StockfishActor {
         }
     }
 
-    async fn init(&mut self, stdout: &mut Stdout, stdin: &mut Stdin) -> io::Result<()> {
+    async fn init(
+        &mut self,
+        stdout: &mut Stdout,
+        stdin: &mut Stdin,
+        flavor: EngineFlavor,
+    ) -> io::Result<()> {
         if !mem::replace(&mut self.initialized, true) {
+            stdin.write_line("uci").await?;
+            stdin.flush().await?;
+
+            loop {
+                let line = stdout.read_line().await?;
+                let line = line.trim_end();
+                if line == "uciok" {
+                    break;
+                } else if let Some(name) = line.strip_prefix("id name ") {
+                    self.logger.debug(&format!("Engine is {name}"));
+                    self.options
+                        .ids
+                        .get(flavor)
+                        .name
+                        .get_or_init(|| name.to_owned());
+                }
+            }
+
             stdin
                 .write_line("setoption name UCI_Chess960 value true")
                 .await?;
+            if let Some(move_overhead) = self.options.move_overhead {
+                stdin
+                    .write_line(&format!(
+                        "setoption name Move Overhead value {}",
+                        move_overhead.as_millis().min(5000)
+                    ))
+                    .await?;
+            }
             stdin.write_line("isready").await?;
             stdin.flush().await?;
 
@@ -223,7 +551,13 @@ impl StockfishActor {
                 let line = stdout.read_line().await?;
                 if line.trim_end() == "readyok" {
                     self.logger.debug("Engine is ready");
+                    if let Some(ref transcript) = self.transcript {
+                        transcript.end_handshake();
+                    }
                     break;
+                } else if let Some(info) = line.strip_prefix("info string ") {
+                    // For example, number of tablebases found.
+                    self.logger.debug(info.trim_end());
                 } else if !line.starts_with("Stockfish ") && !line.starts_with("Fairy-Stockfish ") {
                     // ignore preamble
                     self.logger.warn(&format!(
@@ -243,19 +577,40 @@ impl StockfishActor {
         chunk: Chunk,
     ) -> io::Result<Vec<PositionResponse>> {
         // Set global options (once).
-        self.init(stdout, stdin).await?;
+        self.init(stdout, stdin, chunk.flavor).await?;
+
+        let eval_flavor = chunk.flavor.eval_flavor(chunk.variant);
+
+        // Clear hash, unless continuing with adjacent positions of the
+        // same batch.
+        let batch_id = chunk.work.id();
+        if !self.options.keep_hash || self.last_batch != Some(batch_id) {
+            stdin.write_line("ucinewgame").await?;
+        }
+        self.last_batch = Some(batch_id);
 
-        // Clear hash.
-        stdin.write_line("ucinewgame").await?;
+        // Probe tablebases only when playing, so that analysis submitted
+        // to lichess is unchanged. Switching reloads the tablebases, so
+        // only do it when the kind of work changes.
+        if let Some(ref tablebases) = self.options.tablebases {
+            let wanted = chunk.work.is_move();
+            if self.tablebases_loaded != wanted {
+                stdin
+                    .write_line(&format!(
+                        "setoption name SyzygyPath value {}",
+                        if wanted {
+                            tablebases.path().to_string_lossy()
+                        } else {
+                            "<empty>".into()
+                        }
+                    ))
+                    .await?;
+                self.tablebases_loaded = wanted;
+            }
+        }
 
         // Set basic options.
         if chunk.flavor == EngineFlavor::MultiVariant {
-            stdin
-                .write_line(&format!(
-                    "setoption name Use NNUE value {}",
-                    chunk.flavor.eval_flavor().is_nnue()
-                ))
-                .await?;
             stdin
                 .write_line(&format!(
                     "setoption name UCI_AnalyseMode value {}",
@@ -268,6 +623,18 @@ impl StockfishActor {
                     chunk.variant.uci()
                 ))
                 .await?;
+            // Select the network after the variant, which it must match.
+            if let Some(eval_file) = chunk.flavor.variant_eval_file(chunk.variant) {
+                stdin
+                    .write_line(&format!("setoption name EvalFile value {eval_file}"))
+                    .await?;
+            }
+            stdin
+                .write_line(&format!(
+                    "setoption name Use NNUE value {}",
+                    eval_flavor.is_nnue()
+                ))
+                .await?;
         }
         stdin
             .write_line(&format!(
@@ -280,86 +647,83 @@ impl StockfishActor {
                 "setoption name Skill Level value {}",
                 match chunk.work {
                     Work::Analysis { .. } => 20,
+                    // Play perfectly when the engine can rely on tablebases.
+                    Work::Move { .. } if self.in_tablebases(&chunk) => 20,
                     Work::Move { level, .. } => level.skill_level(),
                 }
             ))
             .await?;
 
         // Collect results for all positions of the chunk.
+        let node_multiplier = self.options.node_multipliers.get(chunk.variant);
         let mut responses = Vec::with_capacity(chunk.positions.len());
         for position in chunk.positions {
-            responses.push(
-                self.go(stdout, stdin, chunk.flavor.eval_flavor(), position)
-                    .await?,
-            );
+            let res = self
+                .go(
+                    stdout,
+                    stdin,
+                    eval_flavor,
+                    node_multiplier,
+                    chunk.nnue_nps,
+                    position,
+                )
+                .await?;
+            if let (Some(progress), Some(res)) = (&self.options.progress, &res) {
+                if res.work.is_analysis() && res.position_index.is_some() {
+                    progress.send(res.clone()).nevermind("queue dropped");
+                }
+            }
+            responses.extend(res);
+            if self.stopped {
+                break;
+            }
         }
         Ok(responses)
     }
 
+    fn in_tablebases(&self, chunk: &Chunk) -> bool {
+        let (Some(tablebases), Variant::Chess, [position]) = (
+            &self.options.tablebases,
+            chunk.variant,
+            &chunk.positions[..],
+        ) else {
+            return false;
+        };
+        let covered = tablebases.covers(&position.root_fen, &position.moves);
+        if covered {
+            self.logger
+                .debug(&format!("Tablebase position in {}", chunk.work.id()));
+        }
+        covered
+    }
+
     async fn go(
         &mut self,
         stdout: &mut Stdout,
         stdin: &mut Stdin,
         eval_flavor: EvalFlavor,
+        node_multiplier: f64,
+        nnue_nps: Option<u32>,
         position: Position,
-    ) -> io::Result<PositionResponse> {
-        // Setup position.
-        let moves = position
-            .moves
-            .iter()
-            .map(|m| m.to_string())
-            .collect::<Vec<_>>()
-            .join(" ");
+    ) -> io::Result<Option<PositionResponse>> {
+        // Setup position and go.
+        set_position(stdin, &position.root_fen, &position.moves).await?;
         stdin
-            .write_line(&format!(
-                "position fen {} moves {}",
-                position.root_fen, moves
-            ))
+            .write_line(
+                &go_command(&position.work, eval_flavor, node_multiplier, nnue_nps).join(" "),
+            )
             .await?;
-
-        // Go.
-        let go = match &position.work {
-            Work::Move { level, clock, .. } => {
-                let mut go = vec![
-                    "go".to_owned(),
-                    "movetime".to_owned(),
-                    level.time().as_millis().to_string(),
-                    "depth".to_owned(),
-                    level.depth().to_string(),
-                ];
-
-                if let Some(clock) = clock {
-                    go.extend_from_slice(&[
-                        "wtime".to_owned(),
-                        Duration::from(clock.wtime).as_millis().to_string(),
-                        "btime".to_owned(),
-                        Duration::from(clock.btime).as_millis().to_string(),
-                        "winc".to_owned(),
-                        clock.inc.as_millis().to_string(),
-                        "binc".to_owned(),
-                        clock.inc.as_millis().to_string(),
-                    ]);
-                }
-
-                go
-            }
-            Work::Analysis { nodes, depth, .. } => {
-                let mut go = vec![
-                    "go".to_owned(),
-                    "nodes".to_owned(),
-                    nodes.get(eval_flavor).to_string(),
-                ];
-
-                if let Some(depth) = depth {
-                    go.extend_from_slice(&["depth".to_owned(), depth.to_string()]);
-                }
-
-                go
-            }
-        };
-        stdin.write_line(&go.join(" ")).await?;
         stdin.flush().await?;
 
+        self.read_response(stdout, stdin, position).await
+    }
+
+    async fn read_response(
+        &mut self,
+        stdout: &mut Stdout,
+        stdin: &mut Stdin,
+        position: Position,
+    ) -> io::Result<Option<PositionResponse>> {
         // Process response.
         let mut scores = Matrix::new();
         let mut pvs = Matrix::new();
@@ -369,16 +733,29 @@ impl StockfishActor {
         let mut nodes = 0;
         let mut nps = None;
 
+        let stop = self.stop.clone();
         loop {
-            let line = stdout.read_line().await?;
+            let line = tokio::select! {
+                line = stdout.read_line() => line?,
+                _ = stop.notified(), if !self.stopped => {
+                    // Finish early, with the best result so far.
+                    self.stopped = true;
+                    stdin.write_line("stop").await?;
+                    stdin.flush().await?;
+                    continue;
+                }
+            };
             let mut parts = line.split(' ');
             match parts.next() {
                 Some("bestmove") => {
                     if scores.best().is_none() {
+                        if self.stopped {
+                            return Ok(None);
+                        }
                         return Err(io::Error::new(io::ErrorKind::InvalidData, "missing score"));
                     }
 
-                    return Ok(PositionResponse {
+                    return Ok(Some(PositionResponse {
                         work: position.work,
                         position_index: position.position_index,
                         url: position.url,
@@ -389,7 +766,9 @@ impl StockfishActor {
                         time,
                         nodes,
                         nps,
-                    });
+                        stopped_early: self.stopped,
+                        cached: false,
+                    }));
                 }
                 Some("info") => {
                     while let Some(part) = parts.next() {
@@ -404,8 +783,11 @@ impl StockfishActor {
                                     })?;
                             }
                             "depth" => {
-                                depth =
-                                    parts.next().and_then(|t| t.parse().ok()).ok_or_else(|| {
+                                depth = parts
+                                    .next()
+                                    .and_then(|t| t.parse().ok())
+                                    .filter(|&depth| depth <= MAX_DEPTH)
+                                    .ok_or_else(|| {
                                         io::Error::new(io::ErrorKind::InvalidData, "expected depth")
                                     })?;
                             }
@@ -475,3 +857,171 @@ impl StockfishActor {
         }
     }
 }
+
+async fn set_position(stdin: &mut Stdin, root_fen: &Fen, moves: &[UciMove]) -> io::Result<()> {
+    let moves = moves
+        .iter()
+        .map(|m| m.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    stdin
+        .write_line(&format!("position fen {root_fen} moves {moves}"))
+        .await
+}
+
+fn go_command(
+    work: &Work,
+    eval_flavor: EvalFlavor,
+    node_multiplier: f64,
+    nnue_nps: Option<u32>,
+) -> Vec<String> {
+    match work {
+        Work::Move { level, clock, .. } => {
+            let mut go = vec![
+                "go".to_owned(),
+                "movetime".to_owned(),
+                level.scaled_time(nnue_nps).as_millis().to_string(),
+                "depth".to_owned(),
+                level.depth().to_string(),
+            ];
+
+            if let Some(clock) = clock {
+                go.extend_from_slice(&[
+                    "wtime".to_owned(),
+                    Duration::from(clock.wtime).as_millis().to_string(),
+                    "btime".to_owned(),
+                    Duration::from(clock.btime).as_millis().to_string(),
+                    "winc".to_owned(),
+                    clock.inc.as_millis().to_string(),
+                    "binc".to_owned(),
+                    clock.inc.as_millis().to_string(),
+                ]);
+            }
+
+            go
+        }
+        Work::Analysis {
+            nodes,
+            depth,
+            movetime,
+            ..
+        } => {
+            let mut go = vec![
+                "go".to_owned(),
+                "nodes".to_owned(),
+                nodes.scaled(node_multiplier).get(eval_flavor).to_string(),
+            ];
+
+            if let Some(depth) = depth {
+                go.extend_from_slice(&["depth".to_owned(), depth.to_string()]);
+            }
+
+            if let Some(movetime) = movetime {
+                go.extend_from_slice(&["movetime".to_owned(), movetime.as_millis().to_string()]);
+            }
+
+            go
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::sync::Mutex;
+
+    use tokio::time::sleep;
+
+    use super::*;
+
+    /// Depth of all simulated searches.
+    pub const DEPTH: u8 = 3;
+
+    /// Simulated engine. Answers after a random delay, or fails at random.
+    /// Scores are derived from the position, so that misplaced results
+    /// can be detected, see [`MockEngine::score()`].
+    pub struct MockEngine {
+        rng: Mutex<fastrand::Rng>,
+        max_latency: Duration,
+        failure_rate: f64,
+    }
+
+    impl MockEngine {
+        pub fn new(seed: u64) -> MockEngine {
+            MockEngine {
+                rng: Mutex::new(fastrand::Rng::with_seed(seed)),
+                max_latency: Duration::from_millis(1),
+                failure_rate: 0.0,
+            }
+        }
+
+        pub fn with_failure_rate(mut self, failure_rate: f64) -> MockEngine {
+            self.failure_rate = failure_rate;
+            self
+        }
+
+        /// Score reported for the position after the given number of
+        /// moves.
+        pub fn score(ply: usize, multipv: NonZeroU8, depth: u8) -> Score {
+            Score::Cp(ply as i64 * 100 + i64::from(multipv.get()) * 10 + i64::from(depth))
+        }
+
+        fn respond(position: Position) -> PositionResponse {
+            let ply = position.moves.len();
+            let mut scores = Matrix::new();
+            let mut pvs = Matrix::new();
+            for multipv in 1..=position.work.multipv().get() {
+                let multipv = NonZeroU8::new(multipv).expect("multipv");
+                for depth in 1..=DEPTH {
+                    scores.set(multipv, depth, MockEngine::score(ply, multipv, depth));
+                    pvs.set(multipv, depth, Vec::new());
+                }
+            }
+            PositionResponse {
+                work: position.work,
+                position_index: position.position_index,
+                url: position.url,
+                scores,
+                pvs,
+                best_move: None,
+                depth: DEPTH,
+                nodes: 1000,
+                time: Duration::from_millis(1),
+                nps: Some(1_000_000),
+                stopped_early: false,
+                cached: false,
+            }
+        }
+    }
+
+    impl Engine for MockEngine {
+        fn go_multiple(
+            &self,
+            chunk: Chunk,
+        ) -> impl Future<Output = Result<Vec<PositionResponse>, ChunkFailed>> + Send {
+            let (latency, failure) = {
+                let mut rng = self.rng.lock().expect("rng");
+                let latency = self.max_latency.mul_f64(rng.f64());
+                let failure = (rng.f64() < self.failure_rate).then(|| rng.bool());
+                (latency, failure)
+            };
+            async move {
+                sleep(latency).await;
+                match failure {
+                    Some(timed_out) => Err(ChunkFailed {
+                        batch_id: chunk.work.id(),
+                        timed_out,
+                        retry: None,
+                        completed: Vec::new(),
+                    }),
+                    None => Ok(chunk
+                        .positions
+                        .into_iter()
+                        .map(MockEngine::respond)
+                        .collect()),
+                }
+            }
+        }
+
+        fn stop(&self) {}
+    }
+}
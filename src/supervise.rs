@@ -0,0 +1,150 @@
+use std::{env, process, time::Duration};
+
+use fishnet::{assets, fatal::Fatal, logger::Logger, util::RandomizedBackoff};
+use tokio::{
+    process::{Child, Command},
+    signal,
+    time::{Instant, sleep, sleep_until},
+};
+
+/// Passed to the supervised process. Points to the directory with the
+/// extracted assets, which is kept across restarts.
+pub const ASSET_DIR_VAR: &str = "FISHNET_SUPERVISED_ASSET_DIR";
+
+/// Runs long enough to consider the process healthy again.
+const HEALTHY: Duration = Duration::from_secs(5 * 60);
+
+/// Time for the process to shut down gracefully before it is killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn is_supervised() -> bool {
+    env::var_os(ASSET_DIR_VAR).is_some()
+}
+
+pub async fn supervise(logger: &Logger) {
+    let dir = match assets::temp_dir() {
+        Ok(dir) => dir,
+        Err(err) => {
+            logger.error(&format!("Failed to create asset directory: {err}"));
+            process::exit(1);
+        }
+    };
+    let exe = env::current_exe().expect("current exe");
+
+    // On Windows, the console also delivers Ctrl+C to the child, which then
+    // shuts down gracefully on its own.
+    #[cfg(unix)]
+    let mut sig_int = signal::unix::signal(signal::unix::SignalKind::interrupt())
+        .expect("install handler for sigint");
+    #[cfg(windows)]
+    let mut sig_int = signal::windows::ctrl_c().expect("install handler for ctrl+c");
+
+    #[cfg(unix)]
+    let mut sig_term = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .expect("install handler for sigterm");
+    #[cfg(windows)]
+    let mut sig_term = signal::windows::ctrl_break().expect("install handler for ctrl+break");
+
+    let mut backoff = RandomizedBackoff::default();
+    let mut stopping = false;
+
+    loop {
+        let started = Instant::now();
+        let mut command = Command::new(&exe);
+        command
+            .args(env::args_os().skip(1))
+            .env(ASSET_DIR_VAR, dir.path())
+            .kill_on_drop(true);
+        // Keep the terminal from delivering SIGINT to the child directly, so
+        // that it arrives exactly once, when forwarded.
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(err) => {
+                logger.error(&format!("Failed to start {exe:?}: {err}"));
+                process::exit(1);
+            }
+        };
+
+        let mut kill_at = None;
+        let status = loop {
+            tokio::select! {
+                status = child.wait() => break status,
+                res = sig_int.recv() => {
+                    res.expect("sigint handler installed");
+                    stopping = true;
+                    interrupt(&mut child);
+                }
+                res = sig_term.recv() => {
+                    res.expect("sigterm handler installed");
+                    stopping = true;
+                    terminate(&mut child);
+                    kill_at.get_or_insert_with(|| Instant::now() + STOP_TIMEOUT);
+                }
+                _ = sleep_until(kill_at.unwrap_or_else(Instant::now)), if kill_at.is_some() => {
+                    logger.warn("Fishnet did not stop in time. Killing it.");
+                    kill_at = None;
+                    child.start_kill().ok();
+                }
+            }
+        };
+
+        match status {
+            Ok(status) if status.success() => return,
+            _ if stopping => return,
+            Ok(status) if status.code().and_then(Fatal::from_exit_code).is_some() => {
+                // Restarting would not help. Pass the code on.
+                logger.error(&format!("Fishnet exited with {status}. Not restarting."));
+                process::exit(status.code().expect("exit code"));
+            }
+            Ok(status) => logger.error(&format!("Fishnet exited with {status}")),
+            Err(err) => logger.error(&format!("Failed to wait for fishnet: {err}")),
+        }
+
+        if started.elapsed() >= HEALTHY {
+            backoff.reset();
+        }
+        let wait = backoff.next();
+        logger.headline(&format!("Restarting in {wait:?} ..."));
+        tokio::select! {
+            _ = sig_int.recv() => return,
+            _ = sig_term.recv() => return,
+            _ = sleep(wait) => (),
+        }
+    }
+}
+
+/// Asks the process to shut down gracefully.
+#[cfg(unix)]
+fn terminate(child: &mut Child) {
+    send_signal(child, libc::SIGTERM);
+}
+
+#[cfg(windows)]
+fn terminate(child: &mut Child) {
+    child.start_kill().ok();
+}
+
+/// Passes SIGINT on, so that the process can escalate its shutdown in the
+/// same steps as when running unsupervised.
+#[cfg(unix)]
+fn interrupt(child: &mut Child) {
+    send_signal(child, libc::SIGINT);
+}
+
+#[cfg(windows)]
+fn interrupt(_child: &mut Child) {
+    // Already received Ctrl+C from the console.
+}
+
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn send_signal(child: &mut Child, sig: libc::c_int) {
+    if let Some(pid) = child.id().and_then(|pid| libc::pid_t::try_from(pid).ok()) {
+        // SAFETY: Only sends a signal to the child process.
+        unsafe {
+            libc::kill(pid, sig);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::mpsc,
+    time::{MissedTickBehavior, interval},
+};
+
+/// Detects that the system was suspended, by observing a clock that keeps
+/// running during suspend advance while the monotonic clock did not.
+///
+/// Detection happens only after resuming, so work that was in flight before
+/// the suspend cannot be returned in time. But it can at least be discarded
+/// promptly, instead of running into confusing timeouts.
+///
+/// Not supported on Windows, where the monotonic clock keeps advancing
+/// while the system is suspended.
+pub struct SuspendDetector {
+    monotonic: Instant,
+    clock: SuspendClock,
+}
+
+/// Clocks, as observed after resuming.
+#[derive(Debug)]
+pub struct Resumed {
+    pub suspended: Duration,
+    monotonic_elapsed: Duration,
+    clock_elapsed: Duration,
+}
+
+impl fmt::Display for Resumed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "monotonic clock advanced {}s, {} {}s",
+            self.monotonic_elapsed.as_secs(),
+            SuspendClock::NAME,
+            self.clock_elapsed.as_secs()
+        )
+    }
+}
+
+impl Default for SuspendDetector {
+    fn default() -> SuspendDetector {
+        SuspendDetector::new()
+    }
+}
+
+impl SuspendDetector {
+    const TICK: Duration = Duration::from_secs(5);
+    const THRESHOLD: Duration = Duration::from_secs(30);
+
+    pub fn new() -> SuspendDetector {
+        SuspendDetector {
+            monotonic: Instant::now(),
+            clock: SuspendClock::now(),
+        }
+    }
+
+    /// Checks the clocks at a fixed interval in the background, and reports
+    /// each resume. Never reports on Windows.
+    pub fn spawn(mut self) -> mpsc::UnboundedReceiver<Resumed> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            if cfg!(windows) {
+                // Keep the channel open.
+                let _tx = tx;
+                return std::future::pending().await;
+            }
+            let mut interval = interval(SuspendDetector::TICK);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Some(resumed) = self.check() {
+                    if tx.send(resumed).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        rx
+    }
+
+    fn check(&mut self) -> Option<Resumed> {
+        let monotonic = Instant::now();
+        let clock = SuspendClock::now();
+        let monotonic_elapsed = monotonic.duration_since(self.monotonic);
+        let clock_elapsed = clock.duration_since(self.clock);
+        self.monotonic = monotonic;
+        self.clock = clock;
+        clock_elapsed
+            .checked_sub(monotonic_elapsed)
+            .filter(|drift| *drift >= SuspendDetector::THRESHOLD)
+            .map(|suspended| Resumed {
+                suspended,
+                monotonic_elapsed,
+                clock_elapsed,
+            })
+    }
+}
+
+/// Time since boot, including time spent suspended. Unlike the wall clock,
+/// not stepped by NTP.
+#[cfg(target_os = "linux")]
+#[derive(Copy, Clone)]
+struct SuspendClock(Duration);
+
+#[cfg(target_os = "linux")]
+impl SuspendClock {
+    const NAME: &str = "boot time";
+
+    #[allow(unsafe_code)]
+    fn now() -> SuspendClock {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: Valid clock and pointer to a timespec.
+        let res = unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts) };
+        assert_eq!(res, 0, "clock_gettime(CLOCK_BOOTTIME)");
+        SuspendClock(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+
+    fn duration_since(self, earlier: SuspendClock) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+/// Wall clock. May also be stepped forward by NTP, which is then mistaken
+/// for a suspend.
+#[cfg(not(target_os = "linux"))]
+#[derive(Copy, Clone)]
+struct SuspendClock(std::time::SystemTime);
+
+#[cfg(not(target_os = "linux"))]
+impl SuspendClock {
+    const NAME: &str = "wall clock";
+
+    fn now() -> SuspendClock {
+        SuspendClock(std::time::SystemTime::now())
+    }
+
+    fn duration_since(self, earlier: SuspendClock) -> Duration {
+        // Errors if the wall clock was set back. Not a suspend.
+        self.0.duration_since(earlier.0).unwrap_or_default()
+    }
+}
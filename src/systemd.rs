@@ -6,85 +6,50 @@ use std::{
 
 use shell_escape::escape;
 
-use crate::configure::{Key, Opt};
+use fishnet::{
+    configure::{Key, Opt},
+    fatal::Fatal,
+};
 
-pub fn systemd_system(opt: Opt) {
-    println!("[Unit]");
-    println!("Description=Fishnet client");
-    println!("After=network-online.target");
-    println!("Wants=network-online.target");
-    println!();
-    println!("[Service]");
-    println!("ExecStart={} run", exec_start(Invocation::Absolute, &opt));
-    println!("KillMode=mixed");
-    println!("WorkingDirectory=/tmp");
-    println!(
-        "User={}",
-        env::var("USER").unwrap_or_else(|_| "XXX".to_owned())
-    );
-    println!("CapabilityBoundingSet=");
-    println!("PrivateTmp=true");
-    println!("PrivateDevices=true");
-    println!("DevicePolicy=closed");
-    if opt.auto_update
-        && env::current_exe()
-            .expect("current exe")
-            .starts_with("/usr/")
-    {
-        println!("ProtectSystem=false");
-    } else {
-        println!("ProtectSystem=full");
+pub const SYSTEM_CONTROL_SOCKET: &str = "/run/fishnet/fishnet.sock";
+
+pub fn systemd_system(mut opt: Opt) {
+    if opt.control_socket.is_none() {
+        opt.control_socket = Some(SYSTEM_CONTROL_SOCKET.into());
     }
-    println!("NoNewPrivileges=true");
-    println!("Restart=on-failure");
-    println!();
-    println!("[Install]");
-    println!("WantedBy=multi-user.target");
+
+    print!("{}", system_unit(&Invocation::Absolute, &opt));
 
     if io::stdout().is_terminal() {
-        let command = exec_start(Invocation::Relative, &opt);
+        let command = exec_start(&Invocation::Relative, &opt);
         eprintln!();
         eprintln!("# Example usage:");
         eprintln!("# {command} systemd | sudo tee /etc/systemd/system/fishnet.service");
         eprintln!("# systemctl enable fishnet.service");
         eprintln!("# systemctl start fishnet.service");
         eprintln!("# Live view of log: journalctl --unit fishnet --follow");
+        eprintln!(
+            "# Query status: {command} status --control-socket {}",
+            escape(
+                opt.control_socket()
+                    .to_str()
+                    .expect("printable --control-socket path")
+                    .into()
+            )
+        );
         eprintln!("# Prefer a user unit? {command} systemd-user");
     }
 }
 
 pub fn systemd_user(opt: Opt) {
-    println!("[Unit]");
-    println!("Description=Fishnet client");
-    println!("After=network-online.target");
-    println!("Wants=network-online.target");
-    println!();
-    println!("[Service]");
-    println!("ExecStart={} run", exec_start(Invocation::Absolute, &opt));
-    println!("KillMode=mixed");
-    println!("WorkingDirectory=/tmp");
-    println!("PrivateTmp=true");
-    println!("DevicePolicy=closed");
-    if opt.auto_update
-        && env::current_exe()
-            .expect("current exe")
-            .starts_with("/usr/")
-    {
-        println!("ProtectSystem=false");
-    } else {
-        println!("ProtectSystem=full");
-    }
-    println!("Restart=on-failure");
-    println!();
-    println!("[Install]");
-    println!("WantedBy=default.target");
+    print!("{}", user_unit(&Invocation::Absolute, &opt));
 
     if io::stdout().is_terminal() {
         eprintln!();
         eprintln!("# Example usage:");
         eprintln!(
             "# {} systemd-user | tee ~/.config/systemd/user/fishnet.service",
-            exec_start(Invocation::Relative, &opt)
+            exec_start(&Invocation::Relative, &opt)
         );
         eprintln!("# systemctl enable --user fishnet.service");
         eprintln!("# systemctl start --user fishnet.service");
@@ -92,29 +57,180 @@ pub fn systemd_user(opt: Opt) {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
-enum Invocation {
+pub fn systemd_system_socket(opt: Opt) {
+    println!("[Unit]");
+    println!("Description=Fishnet client control socket");
+    println!();
+    println!("[Socket]");
+    println!(
+        "ListenStream={}",
+        opt.control_socket
+            .as_deref()
+            .unwrap_or(Path::new(SYSTEM_CONTROL_SOCKET))
+            .display()
+    );
+    println!(
+        "SocketUser={}",
+        service_user().unwrap_or_else(|| "XXX".to_owned())
+    );
+    println!("SocketMode=0600");
+    println!("RemoveOnStop=true");
+    println!();
+    println!("[Install]");
+    println!("WantedBy=sockets.target");
+
+    if io::stdout().is_terminal() {
+        let command = exec_start(&Invocation::Relative, &opt);
+        eprintln!();
+        eprintln!("# Example usage:");
+        eprintln!("# {command} systemd | sudo tee /etc/systemd/system/fishnet.service");
+        eprintln!("# {command} systemd-socket | sudo tee /etc/systemd/system/fishnet.socket");
+        eprintln!("# systemctl enable fishnet.socket fishnet.service");
+        eprintln!("# systemctl start fishnet.socket");
+    }
+}
+
+pub fn systemd_user_socket(opt: Opt) {
+    println!("[Unit]");
+    println!("Description=Fishnet client control socket");
+    println!();
+    println!("[Socket]");
+    match opt.control_socket {
+        Some(ref control_socket) => println!("ListenStream={}", control_socket.display()),
+        None => println!("ListenStream=%t/fishnet.sock"),
+    }
+    println!("SocketMode=0600");
+    println!("RemoveOnStop=true");
+    println!();
+    println!("[Install]");
+    println!("WantedBy=sockets.target");
+
+    if io::stdout().is_terminal() {
+        let command = exec_start(&Invocation::Relative, &opt);
+        eprintln!();
+        eprintln!("# Example usage:");
+        eprintln!("# {command} systemd-user | tee ~/.config/systemd/user/fishnet.service");
+        eprintln!("# {command} systemd-user-socket | tee ~/.config/systemd/user/fishnet.socket");
+        eprintln!("# systemctl enable --user fishnet.socket fishnet.service");
+        eprintln!("# systemctl start --user fishnet.socket");
+    }
+}
+
+/// Account for the system service: the user who invoked `sudo`, or the
+/// current user. Never root, because the service does not need any
+/// privileges.
+pub fn service_user() -> Option<String> {
+    ["SUDO_USER", "USER"]
+        .into_iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|user| !user.is_empty() && user != "root")
+}
+
+/// Service file for the system instance of systemd.
+pub fn system_unit(invocation: &Invocation, opt: &Opt) -> String {
+    let mut unit = Vec::new();
+    unit.push("[Unit]".to_owned());
+    unit.push("Description=Fishnet client".to_owned());
+    unit.push("After=network-online.target".to_owned());
+    unit.push("Wants=network-online.target".to_owned());
+    unit.push(String::new());
+    unit.push("[Service]".to_owned());
+    unit.push(format!("ExecStart={} run", exec_start(invocation, opt)));
+    unit.push("KillMode=mixed".to_owned());
+    unit.push("WorkingDirectory=/tmp".to_owned());
+    unit.push("RuntimeDirectory=fishnet".to_owned());
+    unit.push("RuntimeDirectoryPreserve=yes".to_owned());
+    unit.push(format!(
+        "User={}",
+        service_user().unwrap_or_else(|| "XXX".to_owned())
+    ));
+    unit.push("CapabilityBoundingSet=".to_owned());
+    unit.push("PrivateTmp=true".to_owned());
+    unit.push("PrivateDevices=true".to_owned());
+    unit.push("DevicePolicy=closed".to_owned());
+    unit.push(protect_system(invocation, opt).to_owned());
+    unit.push("NoNewPrivileges=true".to_owned());
+    unit.push("Restart=on-failure".to_owned());
+    unit.push(restart_prevent_exit_status());
+    unit.push(String::new());
+    unit.push("[Install]".to_owned());
+    unit.push("WantedBy=multi-user.target".to_owned());
+    unit.push(String::new());
+    unit.join("\n")
+}
+
+/// Service file for the user instance of systemd.
+pub fn user_unit(invocation: &Invocation, opt: &Opt) -> String {
+    let mut unit = Vec::new();
+    unit.push("[Unit]".to_owned());
+    unit.push("Description=Fishnet client".to_owned());
+    unit.push("After=network-online.target".to_owned());
+    unit.push("Wants=network-online.target".to_owned());
+    unit.push(String::new());
+    unit.push("[Service]".to_owned());
+    unit.push(format!("ExecStart={} run", exec_start(invocation, opt)));
+    unit.push("KillMode=mixed".to_owned());
+    unit.push("WorkingDirectory=/tmp".to_owned());
+    unit.push("PrivateTmp=true".to_owned());
+    unit.push("DevicePolicy=closed".to_owned());
+    unit.push(protect_system(invocation, opt).to_owned());
+    unit.push("Restart=on-failure".to_owned());
+    unit.push(restart_prevent_exit_status());
+    unit.push(String::new());
+    unit.push("[Install]".to_owned());
+    unit.push("WantedBy=default.target".to_owned());
+    unit.push(String::new());
+    unit.join("\n")
+}
+
+fn restart_prevent_exit_status() -> String {
+    format!(
+        "RestartPreventExitStatus={}",
+        Fatal::ALL
+            .into_iter()
+            .map(|fatal| fatal.exit_code().to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    )
+}
+
+fn protect_system(invocation: &Invocation, opt: &Opt) -> &'static str {
+    if opt.auto_update && invocation.exe().starts_with("/usr/") {
+        "ProtectSystem=false"
+    } else {
+        "ProtectSystem=full"
+    }
+}
+
+#[derive(Clone, Eq, PartialEq)]
+pub enum Invocation {
     Absolute,
     Relative,
+    /// Copy of the executable installed at the given path.
+    Installed(PathBuf),
 }
 
 impl Invocation {
-    fn exe(self) -> PathBuf {
+    fn exe(&self) -> PathBuf {
         match self {
             Invocation::Absolute => env::current_exe().expect("current exe"),
             Invocation::Relative => env::args_os().next().expect("argv[0]").into(),
+            Invocation::Installed(exe) => exe.clone(),
         }
     }
 
-    fn path<P: AsRef<Path>>(self, path: P) -> PathBuf {
+    fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
         match self {
-            Invocation::Absolute => fs::canonicalize(path).expect("canonicalize path"),
+            Invocation::Absolute | Invocation::Installed(_) => {
+                fs::canonicalize(path).expect("canonicalize path")
+            }
             Invocation::Relative => path.as_ref().into(),
         }
     }
 }
 
-fn exec_start(invocation: Invocation, opt: &Opt) -> String {
+/// Command line to run fishnet with the given options.
+pub fn exec_start(invocation: &Invocation, opt: &Opt) -> String {
     let mut builder = vec![
         escape(
             invocation
@@ -129,13 +245,17 @@ fn exec_start(invocation: Invocation, opt: &Opt) -> String {
     if opt.verbose.level > 0 {
         builder.push(format!("-{}", "v".repeat(usize::from(opt.verbose.level))));
     }
+    if let Some(progress_interval) = opt.progress_interval {
+        builder.push("--progress-interval".to_owned());
+        builder.push(progress_interval.to_string());
+    }
     if opt.auto_update {
         builder.push("--auto-update".to_owned());
     }
 
     if opt.no_conf {
         builder.push("--no-conf".to_owned());
-    } else if opt.conf.is_some() || invocation == Invocation::Absolute {
+    } else if opt.conf.is_some() || *invocation != Invocation::Relative {
         builder.push("--conf".to_owned());
         builder.push(
             escape(
@@ -166,18 +286,203 @@ fn exec_start(invocation: Invocation, opt: &Opt) -> String {
         builder.push(escape(key.into()).into_owned());
     }
 
-    if let Some(ref endpoint) = opt.endpoint {
+    for endpoint in &opt.endpoint {
         builder.push("--endpoint".to_owned());
         builder.push(escape(endpoint.to_string().into()).into_owned());
     }
+    if let Some(failover_after) = opt.failover_after {
+        builder.push("--failover-after".to_owned());
+        builder.push(failover_after.to_string());
+    }
+    if let Some(ref user_agent_suffix) = opt.user_agent_suffix {
+        builder.push("--user-agent-suffix".to_owned());
+        builder.push(escape(user_agent_suffix.to_string().into()).into_owned());
+    }
+    if opt.development {
+        builder.push("--development".to_owned());
+    }
+    if opt.insecure_skip_tls_verify {
+        builder.push("--insecure-skip-tls-verify".to_owned());
+    }
     if let Some(ref cores) = opt.cores {
         builder.push("--cores".to_owned());
         builder.push(escape(cores.to_string().into()).into_owned());
     }
+    if opt.topology.use_e_cores {
+        builder.push("--use-e-cores".to_owned());
+    }
+    if opt.topology.physical_cores_only {
+        builder.push("--physical-cores-only".to_owned());
+    }
+    if let Some(ref cpu_features) = opt.cpu_features {
+        builder.push("--cpu-features".to_owned());
+        builder.push(cpu_features.to_string());
+    }
+    if let Some(min_cores) = opt.min_cores {
+        builder.push("--min-cores".to_owned());
+        builder.push(min_cores.to_string());
+    }
     if let Some(ref max_backoff) = opt.max_backoff {
         builder.push("--max-backoff".to_owned());
         builder.push(max_backoff.to_string());
     }
+    if opt.http3 {
+        builder.push("--http3".to_owned());
+    }
+    if let Some(connect_timeout) = opt.connect_timeout {
+        builder.push("--connect-timeout".to_owned());
+        builder.push(connect_timeout.to_string());
+    }
+    if let Some(ref contributor_tag) = opt.contributor_tag {
+        builder.push("--contributor-tag".to_owned());
+        builder.push(contributor_tag.to_string());
+    }
+    if let Some(max_upload_rate) = opt.max_upload_rate {
+        builder.push("--max-upload-rate".to_owned());
+        builder.push(max_upload_rate.to_string());
+    }
+    if let Some(ref control_socket) = opt.control_socket {
+        builder.push("--control-socket".to_owned());
+        builder.push(
+            escape(
+                control_socket
+                    .to_str()
+                    .expect("printable --control-socket path")
+                    .into(),
+            )
+            .into_owned(),
+        );
+    }
+    if let Some(admin_port) = opt.admin_port {
+        builder.push("--admin-port".to_owned());
+        builder.push(admin_port.to_string());
+    }
+    if let Some(ref admin_token_file) = opt.admin_token_file {
+        builder.push("--admin-token-file".to_owned());
+        builder.push(
+            escape(
+                invocation
+                    .path(admin_token_file)
+                    .to_str()
+                    .expect("printable --admin-token-file path")
+                    .into(),
+            )
+            .into_owned(),
+        );
+    }
+    if let Some(ref spot_check) = opt.spot_check {
+        builder.push("--spot-check".to_owned());
+        builder.push(escape(spot_check.to_string().into()).into_owned());
+    }
+    if let Some(max_multipv) = opt.max_multipv {
+        builder.push("--max-multipv".to_owned());
+        builder.push(max_multipv.to_string());
+    }
+    if let Some(nps_drop) = opt.stats.nps_drop {
+        builder.push("--nps-drop".to_owned());
+        builder.push(nps_drop.to_string());
+    }
+    if opt.stats.pause_on_nps_drop {
+        builder.push("--pause-on-nps-drop".to_owned());
+    }
+    if let Some(max_queued_chunks) = opt.max_queued_chunks {
+        builder.push("--max-queued-chunks".to_owned());
+        builder.push(max_queued_chunks.to_string());
+    }
+    if let Some(max_pending_memory) = opt.max_pending_memory {
+        builder.push("--max-pending-memory".to_owned());
+        builder.push(max_pending_memory.to_string());
+    }
+    if let Some(recycle_engine_chunks) = opt.recycle_engine_chunks {
+        builder.push("--recycle-engine-chunks".to_owned());
+        builder.push(recycle_engine_chunks.to_string());
+    }
+    if let Some(recycle_engine_age) = opt.recycle_engine_age {
+        builder.push("--recycle-engine-age".to_owned());
+        builder.push(recycle_engine_age.to_string());
+    }
+    if let Some(keep_alive) = opt.keep_alive {
+        builder.push("--keep-alive".to_owned());
+        builder.push(keep_alive.to_string());
+    }
+    if opt.no_abort_on_shutdown {
+        builder.push("--no-abort-on-shutdown".to_owned());
+    }
+    if let Some(move_overhead) = opt.move_overhead {
+        builder.push("--move-overhead".to_owned());
+        builder.push(move_overhead.to_string());
+    }
+    if opt.report_each_position {
+        builder.push("--report-each-position".to_owned());
+    }
+    if opt.keep_hash {
+        builder.push("--keep-hash".to_owned());
+    }
+    for node_multiplier in &opt.node_multipliers {
+        builder.push("--node-multiplier".to_owned());
+        builder.push(escape(node_multiplier.to_string().into()).into_owned());
+    }
+    if let Some(ref book) = opt.book {
+        builder.push("--book".to_owned());
+        builder.push(escape(book.to_str().expect("printable --book path").into()).into_owned());
+    }
+    if let Some(ref syzygy_path) = opt.syzygy_path {
+        builder.push("--syzygy-path".to_owned());
+        builder.push(
+            escape(
+                syzygy_path
+                    .to_str()
+                    .expect("printable --syzygy-path")
+                    .into(),
+            )
+            .into_owned(),
+        );
+    }
+    if let Some(ref position_cache) = opt.position_cache {
+        builder.push("--position-cache".to_owned());
+        builder.push(
+            escape(
+                position_cache
+                    .to_str()
+                    .expect("printable --position-cache path")
+                    .into(),
+            )
+            .into_owned(),
+        );
+    }
+    if let Some(ref archive_dir) = opt.archive_dir {
+        builder.push("--archive-dir".to_owned());
+        builder.push(
+            escape(
+                archive_dir
+                    .to_str()
+                    .expect("printable --archive-dir path")
+                    .into(),
+            )
+            .into_owned(),
+        );
+    }
+    if let Some(ref record_uci) = opt.record_uci {
+        builder.push("--record-uci".to_owned());
+        builder.push(
+            escape(
+                invocation
+                    .path(record_uci)
+                    .to_str()
+                    .expect("printable --record-uci path")
+                    .into(),
+            )
+            .into_owned(),
+        );
+    }
+    for result_sink in &opt.result_sinks {
+        builder.push("--result-sink".to_owned());
+        builder.push(escape(result_sink.to_string().into()).into_owned());
+    }
+    if let Some(position_cache_size) = opt.position_cache_size {
+        builder.push("--position-cache-size".to_owned());
+        builder.push(position_cache_size.to_string());
+    }
     if let Some(ref user_backlog) = opt.backlog.user {
         builder.push("--user-backlog".to_owned());
         builder.push(escape(user_backlog.to_string().into()).into_owned());
@@ -186,6 +491,10 @@ fn exec_start(invocation: Invocation, opt: &Opt) -> String {
         builder.push("--system-backlog".to_owned());
         builder.push(escape(system_backlog.to_string().into()).into_owned());
     }
+    if let Some(status_interval) = opt.backlog.status_interval {
+        builder.push("--status-interval".to_owned());
+        builder.push(status_interval.to_string());
+    }
 
     builder.join(" ")
 }
@@ -0,0 +1,104 @@
+use std::{collections::HashSet, env, ffi::OsString, fs, io, path::PathBuf};
+
+use shakmaty::{
+    CastlingMode, Chess, Color, Position as _, PositionError, Role, fen::Fen, uci::UciMove,
+};
+
+/// Syzygy tablebases available to the engines.
+///
+/// Probing is left to the engines. This only keeps track of which tables
+/// exist, to decide when a position can be played perfectly.
+#[derive(Debug)]
+pub struct Tablebases {
+    path: OsString,
+    tables: HashSet<String>,
+}
+
+impl Tablebases {
+    pub const MAX_PIECES: usize = 7;
+
+    /// Scans the given directories (separated like `PATH`) for tables.
+    pub fn scan(path: OsString) -> io::Result<Tablebases> {
+        let mut wdl = HashSet::new();
+        let mut dtz = HashSet::new();
+        for dir in env::split_paths(&path) {
+            for entry in fs::read_dir(&dir)? {
+                let file = PathBuf::from(entry?.file_name());
+                let (Some(stem), Some(ext)) = (
+                    file.file_stem().and_then(|s| s.to_str()),
+                    file.extension().and_then(|s| s.to_str()),
+                ) else {
+                    continue;
+                };
+                match ext {
+                    "rtbw" => wdl.insert(stem.to_owned()),
+                    "rtbz" => dtz.insert(stem.to_owned()),
+                    _ => false,
+                };
+            }
+        }
+        Ok(Tablebases {
+            path,
+            tables: wdl.intersection(&dtz).cloned().collect(),
+        })
+    }
+
+    pub fn path(&self) -> &OsString {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Returns whether tables for the position after the given moves are
+    /// available.
+    pub fn covers(&self, root_fen: &Fen, moves: &[UciMove]) -> bool {
+        let Some(pos) = play(root_fen, moves) else {
+            return false;
+        };
+        if pos.board().occupied().count() > Tablebases::MAX_PIECES || pos.castles().any() {
+            return false;
+        }
+        let white = material(&pos, Color::White);
+        let black = material(&pos, Color::Black);
+        self.tables.contains(&format!("{white}v{black}"))
+            || self.tables.contains(&format!("{black}v{white}"))
+    }
+}
+
+fn play(root_fen: &Fen, moves: &[UciMove]) -> Option<Chess> {
+    let mut pos: Chess = root_fen
+        .clone()
+        .into_position(CastlingMode::Chess960)
+        .or_else(PositionError::ignore_invalid_ep_square)
+        .or_else(PositionError::ignore_invalid_castling_rights)
+        .ok()?;
+    for uci in moves {
+        let m = uci.to_move(&pos).ok()?;
+        pos.play_unchecked(m);
+    }
+    Some(pos)
+}
+
+fn material(pos: &Chess, color: Color) -> String {
+    let mut material = String::new();
+    for role in [
+        Role::King,
+        Role::Queen,
+        Role::Rook,
+        Role::Bishop,
+        Role::Knight,
+        Role::Pawn,
+    ] {
+        let count = (pos.board().by_color(color) & pos.board().by_role(role)).count();
+        for _ in 0..count {
+            material.push(role.upper_char());
+        }
+    }
+    material
+}
@@ -0,0 +1,137 @@
+//! Recording of the UCI dialogue with an engine, so that chunks that fail
+//! or time out can be reproduced from transcripts.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Write as _},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::api::BatchId;
+
+/// Keep at most this much of the dialogue of a single chunk. Older lines
+/// are dropped first.
+const MAX_CHUNK_BYTES: usize = 512 * 1024;
+
+/// Keep at most this many transcripts in the directory. Older transcripts
+/// are removed first.
+const MAX_TRANSCRIPTS: usize = 50;
+
+const PREFIX: &str = "uci-";
+
+/// Dialogue with a single engine process, shared between the engine actor
+/// and the worker that owns it.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    inner: Arc<Mutex<TranscriptInner>>,
+}
+
+#[derive(Debug, Default)]
+struct TranscriptInner {
+    /// Initialization, which applies to all chunks.
+    handshake: Vec<String>,
+    lines: VecDeque<String>,
+    bytes: usize,
+    dropped: usize,
+}
+
+impl Transcript {
+    /// Records a line sent to the engine.
+    pub fn input(&self, line: &str) {
+        self.push(format!("> {line}"));
+    }
+
+    /// Records a line received from the engine.
+    pub fn output(&self, line: &str) {
+        self.push(format!("< {line}"));
+    }
+
+    fn push(&self, line: String) {
+        let mut inner = self.inner.lock().expect("transcript");
+        inner.bytes += line.len() + 1;
+        inner.lines.push_back(line);
+        while inner.bytes > MAX_CHUNK_BYTES {
+            let Some(dropped) = inner.lines.pop_front() else {
+                break;
+            };
+            inner.bytes -= dropped.len() + 1;
+            inner.dropped += 1;
+        }
+    }
+
+    /// Keeps the lines recorded so far for all following chunks.
+    pub fn end_handshake(&self) {
+        let mut inner = self.inner.lock().expect("transcript");
+        let lines = inner.take_lines();
+        inner.handshake.extend(lines);
+    }
+
+    /// Forgets the dialogue of the previous chunk.
+    pub fn begin_chunk(&self) {
+        self.inner.lock().expect("transcript").take_lines();
+    }
+
+    /// Writes the transcript to a new file in the directory, and removes
+    /// the oldest transcripts beyond the limit.
+    pub fn save(&self, dir: &Path, batch_id: BatchId, reason: &str) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = dir.join(format!("{PREFIX}{timestamp}-{batch_id}.txt"));
+
+        let mut text = String::new();
+        text.push_str(&format!(
+            "# fishnet {}, batch {batch_id}: {reason}\n",
+            env!("CARGO_PKG_VERSION")
+        ));
+        {
+            let inner = self.inner.lock().expect("transcript");
+            for line in &inner.handshake {
+                text.push_str(line);
+                text.push('\n');
+            }
+            if inner.dropped > 0 {
+                text.push_str(&format!("# ... {} line(s) dropped\n", inner.dropped));
+            }
+            for line in &inner.lines {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        fs::File::create(&path)?.write_all(text.as_bytes())?;
+
+        prune(dir)?;
+        Ok(path)
+    }
+}
+
+impl TranscriptInner {
+    fn take_lines(&mut self) -> VecDeque<String> {
+        self.bytes = 0;
+        self.dropped = 0;
+        mem::take(&mut self.lines)
+    }
+}
+
+fn prune(dir: &Path) -> io::Result<()> {
+    let mut transcripts = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(PREFIX) {
+            transcripts.push((entry.metadata()?.modified()?, entry.path()));
+        }
+    }
+    if transcripts.len() > MAX_TRANSCRIPTS {
+        transcripts.sort();
+        for (_, path) in &transcripts[..transcripts.len() - MAX_TRANSCRIPTS] {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
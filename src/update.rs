@@ -8,7 +8,7 @@ use serde::Deserialize;
 use tempfile::NamedTempFile;
 use tokio::time::{error::Elapsed, timeout};
 
-use crate::logger::Logger;
+use fishnet::logger::Logger;
 
 pub async fn auto_update(
     verbose: bool,
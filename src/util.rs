@@ -1,6 +1,10 @@
 use std::{
     cmp::{max, min},
     str,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
     time::Duration,
 };
 
@@ -8,22 +12,48 @@ use fastrand::Rng;
 
 use crate::configure::MaxBackoff;
 
-#[derive(Debug, Default)]
+static BACKOFF_SEED: OnceLock<u64> = OnceLock::new();
+static BACKOFF_INSTANCES: AtomicU64 = AtomicU64::new(0);
+
+/// Makes all following backoff sequences reproducible. Each backoff is
+/// seeded with the given seed plus the number of backoffs created before.
+pub fn set_backoff_seed(seed: u64) {
+    BACKOFF_SEED.set(seed).nevermind("backoff seed already set");
+}
+
+#[derive(Debug)]
 pub struct RandomizedBackoff {
     duration: Duration,
     max_backoff: MaxBackoff,
     rng: Rng,
 }
 
+impl Default for RandomizedBackoff {
+    fn default() -> RandomizedBackoff {
+        RandomizedBackoff::new(MaxBackoff::default())
+    }
+}
+
 impl RandomizedBackoff {
     pub fn new(max_backoff: MaxBackoff) -> RandomizedBackoff {
+        let rng = match BACKOFF_SEED.get() {
+            Some(seed) => {
+                Rng::with_seed(seed.wrapping_add(BACKOFF_INSTANCES.fetch_add(1, Ordering::Relaxed)))
+            }
+            None => Rng::new(),
+        };
+        RandomizedBackoff::with_rng(max_backoff, rng)
+    }
+
+    pub fn with_rng(max_backoff: MaxBackoff, rng: Rng) -> RandomizedBackoff {
         RandomizedBackoff {
             duration: Duration::default(),
             max_backoff,
-            rng: Rng::new(),
+            rng,
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Duration {
         let low = 100;
         let cap = max(low, Duration::from(self.max_backoff).as_millis() as u64);
@@ -69,6 +99,17 @@ pub fn dot_thousands(n: u64) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_seeded_backoff() {
+        let sequence = |seed| {
+            let mut backoff =
+                RandomizedBackoff::with_rng(MaxBackoff::default(), Rng::with_seed(seed));
+            (0..8).map(|_| backoff.next()).collect::<Vec<_>>()
+        };
+        assert_eq!(sequence(42), sequence(42));
+        assert_ne!(sequence(42), sequence(43));
+    }
+
     #[test]
     fn test_grow_with_and_get_mut() {
         let mut vec = Vec::new();